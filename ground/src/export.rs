@@ -0,0 +1,172 @@
+use std::io::{self, Write};
+
+use stack_ripper::{flight::Phase, state::State, timesync::Utc};
+
+/// A point on the track, only states with a full GPS fix make one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fix {
+    pub time: Option<Utc>,
+    pub latitude: f32,
+    pub longitude: f32,
+    pub altitude: f32, // GPS altitude above sea level, meters
+    pub phase: Phase,
+}
+
+impl Fix {
+    pub fn from_state(state: &State) -> Option<Self> {
+        Some(Fix {
+            time: state.t,
+            latitude: state.lt?,
+            longitude: state.ln?,
+            altitude: state.ga?,
+            phase: state.fp,
+        })
+    }
+}
+
+/// The places worth walking to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Waypoints {
+    pub launch: Fix,
+    pub apogee: Fix,
+    pub last: Fix,
+}
+
+impl Waypoints {
+    pub fn from_track(track: &[Fix]) -> Option<Self> {
+        let last = *track.last()?;
+
+        // The pad is wherever we were last seen before the phase moved on, if we never saw that the first fix will do
+        let launch = track
+            .iter()
+            .take_while(|fix| fix.phase == Phase::Pad)
+            .last()
+            .unwrap_or(&track[0]);
+
+        let apogee = track
+            .iter()
+            .max_by(|a, b| a.altitude.total_cmp(&b.altitude))?;
+
+        Some(Waypoints {
+            launch: *launch,
+            apogee: *apogee,
+            last,
+        })
+    }
+
+    fn named(&self) -> [(&'static str, &Fix); 3] {
+        [
+            ("Launch", &self.launch),
+            ("Apogee", &self.apogee),
+            ("Last known position", &self.last),
+        ]
+    }
+}
+
+pub fn track(states: &[State]) -> Vec<Fix> {
+    states.iter().filter_map(Fix::from_state).collect()
+}
+
+/// Google Earth document with the flight as an extruded 3D line, plus a placemark for each waypoint.
+pub fn write_kml(track: &[Fix], out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<kml xmlns="http://www.opengis.net/kml/2.2">"#)?;
+    writeln!(out, "<Document>")?;
+    writeln!(out, "<name>stack-ripper flight</name>")?;
+    writeln!(
+        out,
+        r#"<Style id="track"><LineStyle><color>ff0000ff</color><width>3</width></LineStyle><PolyStyle><color>7f0000ff</color></PolyStyle></Style>"#
+    )?;
+
+    writeln!(out, "<Placemark>")?;
+    writeln!(out, "<name>Flight</name>")?;
+    writeln!(out, "<styleUrl>#track</styleUrl>")?;
+    writeln!(out, "<LineString>")?;
+    // Extruding down to the ground draws a curtain under the track, which makes the 3D shape much easier to read
+    writeln!(out, "<extrude>1</extrude>")?;
+    writeln!(out, "<tessellate>1</tessellate>")?;
+    writeln!(out, "<altitudeMode>absolute</altitudeMode>")?;
+    writeln!(out, "<coordinates>")?;
+    for fix in track {
+        writeln!(out, "{},{},{}", fix.longitude, fix.latitude, fix.altitude)?;
+    }
+    writeln!(out, "</coordinates>")?;
+    writeln!(out, "</LineString>")?;
+    writeln!(out, "</Placemark>")?;
+
+    if let Some(waypoints) = Waypoints::from_track(track) {
+        for (name, fix) in waypoints.named() {
+            writeln!(out, "<Placemark>")?;
+            writeln!(out, "<name>{}</name>", name)?;
+            writeln!(out, "<description>{}</description>", describe(fix))?;
+            writeln!(out, "<Point>")?;
+            writeln!(out, "<altitudeMode>absolute</altitudeMode>")?;
+            writeln!(
+                out,
+                "<coordinates>{},{},{}</coordinates>",
+                fix.longitude, fix.latitude, fix.altitude
+            )?;
+            writeln!(out, "</Point>")?;
+            writeln!(out, "</Placemark>")?;
+        }
+    }
+
+    writeln!(out, "</Document>")?;
+    writeln!(out, "</kml>")
+}
+
+/// GPX with the waypoints, for a handheld GPS or phone on the walk out, and the track for context.
+pub fn write_gpx(track: &[Fix], out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(
+        out,
+        r#"<gpx version="1.1" creator="stack-ripper ground" xmlns="http://www.topografix.com/GPX/1/1">"#
+    )?;
+
+    if let Some(waypoints) = Waypoints::from_track(track) {
+        for (name, fix) in waypoints.named() {
+            writeln!(
+                out,
+                r#"<wpt lat="{}" lon="{}"><ele>{}</ele><name>{}</name><desc>{}</desc></wpt>"#,
+                fix.latitude,
+                fix.longitude,
+                fix.altitude,
+                name,
+                describe(fix)
+            )?;
+        }
+    }
+
+    writeln!(out, "<trk><name>Flight</name><trkseg>")?;
+    for fix in track {
+        write!(
+            out,
+            r#"<trkpt lat="{}" lon="{}"><ele>{}</ele>"#,
+            fix.latitude, fix.longitude, fix.altitude
+        )?;
+        if let Some(time) = fix.time {
+            write!(out, "<time>{}</time>", time)?;
+        }
+        writeln!(out, "</trkpt>")?;
+    }
+    writeln!(out, "</trkseg></trk>")?;
+
+    writeln!(out, "</gpx>")
+}
+
+fn describe(fix: &Fix) -> String {
+    match fix.time {
+        Some(t) => {
+            let ms = t.ms_of_day();
+            format!(
+                "{:?} at {:02}:{:02}:{:02} UTC, {:.0}m",
+                fix.phase,
+                ms / 3_600_000,
+                ms / 60_000 % 60,
+                ms / 1_000 % 60,
+                fix.altitude
+            )
+        }
+        None => format!("{:?}, {:.0}m", fix.phase, fix.altitude),
+    }
+}
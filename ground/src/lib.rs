@@ -0,0 +1,3 @@
+pub mod export;
+pub mod link;
+pub mod session;
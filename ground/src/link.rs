@@ -0,0 +1,57 @@
+use std::collections::BTreeMap;
+
+use stack_ripper::protocol::Header;
+
+/// Packet loss, worked out from the gaps in each vehicle's sequence numbers, less the packets rebuilt from parity.
+#[derive(Debug, Default)]
+pub struct Link {
+    vehicles: BTreeMap<u8, Counts>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Counts {
+    pub received: u32,
+    pub missed: u32,
+    pub recovered: u32, // Of the missed ones
+    last_seq: Option<u16>,
+}
+
+impl Counts {
+    /// Fraction of the packets sent that never made it, even after FEC, between 0 and 1.
+    pub fn loss(&self) -> f32 {
+        match self.received + self.missed {
+            0 => 0.0,
+            sent => self.missed.saturating_sub(self.recovered) as f32 / sent as f32,
+        }
+    }
+}
+
+impl Link {
+    pub fn record(&mut self, header: &Header) -> Counts {
+        let counts = self.vehicles.entry(header.vehicle).or_default();
+
+        match counts.last_seq.map(|last| header.seq.wrapping_sub(last)) {
+            // A repeat of the last packet, nothing new learnt
+            Some(0) => return *counts,
+            Some(gap) if gap < 0x8000 => counts.missed += (gap - 1) as u32,
+            // Going backwards means the vehicle rebooted, there's no telling what was lost
+            _ => {}
+        }
+
+        counts.received += 1;
+        counts.last_seq = Some(header.seq);
+        *counts
+    }
+
+    /// A packet that was never heard, but rebuilt from the parity frames after it. It was counted as missed when
+    /// the packets either side of it came in, so it doesn't move the sequence numbers along.
+    pub fn recover(&mut self, header: &Header) -> Counts {
+        let counts = self.vehicles.entry(header.vehicle).or_default();
+        counts.recovered += 1;
+        *counts
+    }
+
+    pub fn vehicle(&self, vehicle: u8) -> Option<Counts> {
+        self.vehicles.get(&vehicle).copied()
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    env,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter},
+    process::ExitCode,
+};
+
+use ground::{
+    export,
+    session::{altitude, Session, Update},
+};
+use stack_ripper::telemetry::Demux;
+
+const USAGE: &str =
+    "usage: ground <serial port or capture file> [--log <flight.csv>] [--kml <flight.kml>] [--gpx <flight.gpx>]";
+
+#[derive(Default)]
+struct Outputs {
+    log: Option<String>,
+    kml: Option<String>,
+    gpx: Option<String>,
+}
+
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1);
+    let mut input = None;
+    let mut outputs = Outputs::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--log" => outputs.log = args.next(),
+            "--kml" => outputs.kml = args.next(),
+            "--gpx" => outputs.gpx = args.next(),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return ExitCode::SUCCESS;
+            }
+            _ if input.is_none() => input = Some(arg),
+            _ => {
+                eprintln!("{}", USAGE);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let Some(input) = input else {
+        eprintln!("{}", USAGE);
+        return ExitCode::FAILURE;
+    };
+
+    match run(&input, &outputs) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}: {}", input, e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// A serial port is just a file that never ends, so both get read the same way
+fn run(input: &str, outputs: &Outputs) -> io::Result<()> {
+    let mut reader = BufReader::new(File::open(input)?);
+    let log = outputs
+        .log
+        .as_ref()
+        .map(File::create)
+        .transpose()?
+        .map(BufWriter::new);
+    let mut session = Session::new(log)?;
+    let mut states = Vec::new();
+
+    // defmt output shares the port, and a frame of it can land partway through a line or have a newline in it
+    let mut demux = Demux::new();
+    let mut buf = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        buf.clear();
+        let end = reader.read_until(b'\n', &mut buf)? == 0;
+        line.extend(buf.iter().filter_map(|&byte| demux.push(byte)));
+        if !end && line.last() != Some(&b'\n') {
+            continue;
+        }
+
+        if !line.is_empty() {
+            if let Ok(update) = session.handle_line(&String::from_utf8_lossy(&line))? {
+                print_update(&update);
+                states.push(update.packet.state);
+            }
+            line.clear();
+        }
+
+        if end {
+            break;
+        }
+    }
+
+    println!("{} unreadable lines skipped", session.unreadable());
+
+    // Only written once the input runs out, which for a serial port means once it's unplugged
+    let track = export::track(&states);
+    if let Some(kml) = &outputs.kml {
+        export::write_kml(&track, &mut BufWriter::new(File::create(kml)?))?;
+    }
+    if let Some(gpx) = &outputs.gpx {
+        export::write_gpx(&track, &mut BufWriter::new(File::create(gpx)?))?;
+    }
+
+    Ok(())
+}
+
+fn print_update(update: &Update) {
+    let packet = &update.packet;
+    let state = &packet.state;
+
+    let altitude = match (altitude(packet), update.max_altitude) {
+        (Some(altitude), Some(max)) => format!("{:7.1}m (max {:.1}m)", altitude, max),
+        _ => "      -".to_string(),
+    };
+
+    // A position with no fix is the last one we had, worth showing but not worth trusting
+    let position = match (state.lt, state.ln) {
+        (Some(lt), Some(ln)) => format!("{:10.5},{:11.5} {:?}", lt, ln, state.fx),
+        _ => "no position".to_string(),
+    };
+
+    let quality = match (state.ns, state.hd) {
+        (Some(ns), Some(hd)) => format!("{:2} sats HDOP {:4.1}", ns, hd),
+        _ => "-".to_string(),
+    };
+
+    // Derived Debug doesn't pad, so it has to be a string first
+    let phase = format!("{:?}", state.fp);
+
+    println!(
+        "vehicle {} seq {:5} | {:<7} | {} | {} | {} | RSSI {:4}dBm (avg {:4}) SNR {:3}dB | loss {:5.1}% bad {}{}",
+        packet.header.vehicle,
+        packet.header.seq,
+        phase,
+        altitude,
+        position,
+        quality,
+        packet.rssi,
+        packet.link.rssi_avg,
+        packet.snr,
+        update.link.loss() * 100.0,
+        packet.link.corrupt,
+        if packet.recovered { " (rebuilt)" } else { "" },
+    );
+}
@@ -0,0 +1,78 @@
+use std::io::{self, Write};
+
+use stack_ripper::telemetry::{self, Format, Packet, ParseError};
+
+use crate::link::{Counts, Link};
+
+/// Everything seen so far from one serial port or capture file, optionally copied to a CSV flight log.
+pub struct Session<W> {
+    link: Link,
+    log: Option<W>,
+    max_altitude: Option<f32>,
+    unreadable: u32,
+}
+
+/// A packet along with what the session has worked out about the flight so far.
+pub struct Update {
+    pub packet: Packet,
+    pub link: Counts,
+    pub max_altitude: Option<f32>,
+}
+
+impl<W: Write> Session<W> {
+    pub fn new(mut log: Option<W>) -> io::Result<Self> {
+        if let Some(log) = &mut log {
+            let mut header = String::new();
+            telemetry::write_csv_header(&mut header).expect("writing to a String");
+            writeln!(log, "{}", header)?;
+        }
+
+        Ok(Session {
+            link: Link::default(),
+            log,
+            max_altitude: None,
+            unreadable: 0,
+        })
+    }
+
+    /// Lines that weren't telemetry, the receiver's CSV header and anything else that got onto the port included.
+    pub fn unreadable(&self) -> u32 {
+        self.unreadable
+    }
+
+    pub fn handle_line(&mut self, line: &str) -> io::Result<Result<Update, ParseError>> {
+        let packet = match telemetry::parse_line(line) {
+            Ok(packet) => packet,
+            Err(e) => {
+                self.unreadable += 1;
+                return Ok(Err(e));
+            }
+        };
+
+        if let Some(log) = &mut self.log {
+            let mut line = String::new();
+            telemetry::write_line(&packet, Format::Csv, &mut line).expect("writing to a String");
+            writeln!(log, "{}", line)?;
+        }
+
+        if let Some(altitude) = altitude(&packet) {
+            self.max_altitude = Some(self.max_altitude.map_or(altitude, |max| max.max(altitude)));
+        }
+
+        let link = match packet.recovered {
+            true => self.link.recover(&packet.header),
+            false => self.link.record(&packet.header),
+        };
+
+        Ok(Ok(Update {
+            link,
+            max_altitude: self.max_altitude,
+            packet,
+        }))
+    }
+}
+
+/// Best altitude above the pad we have, the filtered one if the vehicle sent it.
+pub fn altitude(packet: &Packet) -> Option<f32> {
+    packet.state.fa.or(packet.state.aar)
+}
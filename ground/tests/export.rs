@@ -0,0 +1,79 @@
+use ground::{
+    export::{self, Fix, Waypoints},
+    session::Session,
+};
+use stack_ripper::{flight::Phase, state::State, timesync::Utc};
+
+const FLIGHT: &str = include_str!("flight.csv");
+
+fn states() -> Vec<State> {
+    let mut session = Session::new(None::<Vec<u8>>).unwrap();
+    FLIGHT
+        .lines()
+        .filter_map(|line| session.handle_line(line).unwrap().ok())
+        .map(|update| update.packet.state)
+        .collect()
+}
+
+#[test]
+fn track_skips_states_without_a_fix() {
+    let track = export::track(&states());
+
+    assert_eq!(states().len(), 10);
+    assert_eq!(track.len(), 8);
+    assert_eq!(
+        track[0],
+        Fix {
+            time: "2026-04-17T23:59:51.000Z".parse().ok(),
+            latitude: -41.2865,
+            longitude: 174.7762,
+            altitude: 35.0,
+            phase: Phase::Pad,
+        }
+    );
+}
+
+#[test]
+fn waypoints_are_launch_apogee_and_last_seen() {
+    let waypoints = Waypoints::from_track(&export::track(&states())).unwrap();
+
+    assert_eq!(
+        waypoints.launch.time,
+        Utc::from_date_time(2026, 4, 17, 86_394_000)
+    );
+    assert_eq!(waypoints.apogee.altitude, 887.0);
+    assert_eq!(waypoints.apogee.phase, Phase::Apogee);
+    assert_eq!(waypoints.last.phase, Phase::Landed);
+    assert_eq!(waypoints.last.latitude, -41.2985);
+
+    assert_eq!(Waypoints::from_track(&[]), None);
+}
+
+#[test]
+fn kml_has_an_extruded_absolute_track() {
+    let mut kml = Vec::new();
+    export::write_kml(&export::track(&states()), &mut kml).unwrap();
+    let kml = String::from_utf8(kml).unwrap();
+
+    assert!(kml.contains("<extrude>1</extrude>"));
+    assert!(kml.contains("<altitudeMode>absolute</altitudeMode>"));
+    assert!(kml.contains("174.7807,-41.2895,887\n"));
+    assert_eq!(kml.matches("<Placemark>").count(), 4);
+    assert!(kml.contains("<description>Apogee at 00:00:06 UTC, 887m</description>"));
+    assert!(kml.trim_end().ends_with("</kml>"));
+}
+
+#[test]
+fn gpx_has_the_waypoints_and_every_fix() {
+    let mut gpx = Vec::new();
+    export::write_gpx(&export::track(&states()), &mut gpx).unwrap();
+    let gpx = String::from_utf8(gpx).unwrap();
+
+    assert_eq!(gpx.matches("<wpt ").count(), 3);
+    assert_eq!(gpx.matches("<trkpt ").count(), 8);
+    assert!(gpx.contains("<time>2026-04-18T00:00:06.000Z</time>"));
+    assert!(gpx.contains(
+        r#"<wpt lat="-41.2985" lon="174.7942"><ele>35</ele><name>Last known position</name>"#
+    ));
+    assert!(gpx.trim_end().ends_with("</gpx>"));
+}
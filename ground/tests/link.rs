@@ -0,0 +1,72 @@
+use ground::link::Link;
+use stack_ripper::protocol::Header;
+
+fn record(link: &mut Link, vehicle: u8, seq: u16) {
+    link.record(&Header { vehicle, seq });
+}
+
+#[test]
+fn counts_gaps_as_missed() {
+    let mut link = Link::default();
+    for seq in [10, 11, 14, 15] {
+        record(&mut link, 1, seq);
+    }
+
+    let counts = link.vehicle(1).unwrap();
+    assert_eq!((counts.received, counts.missed), (4, 2));
+    assert!((counts.loss() - 2.0 / 6.0).abs() < 1e-6);
+}
+
+#[test]
+fn survives_the_sequence_wrapping() {
+    let mut link = Link::default();
+    for seq in [u16::MAX - 1, u16::MAX, 1] {
+        record(&mut link, 1, seq);
+    }
+
+    let counts = link.vehicle(1).unwrap();
+    assert_eq!((counts.received, counts.missed), (3, 1));
+}
+
+#[test]
+fn ignores_repeats_and_reboots() {
+    let mut link = Link::default();
+    for seq in [500, 501, 501, 0, 1] {
+        record(&mut link, 1, seq);
+    }
+
+    let counts = link.vehicle(1).unwrap();
+    assert_eq!((counts.received, counts.missed), (4, 0));
+}
+
+#[test]
+fn keeps_vehicles_apart() {
+    let mut link = Link::default();
+    record(&mut link, 1, 0);
+    record(&mut link, 2, 7);
+    record(&mut link, 1, 1);
+    record(&mut link, 2, 9);
+
+    assert_eq!(link.vehicle(1).unwrap().missed, 0);
+    assert_eq!(link.vehicle(2).unwrap().missed, 1);
+    assert_eq!(link.vehicle(3), None);
+}
+
+#[test]
+fn takes_recovered_packets_off_the_loss() {
+    let mut link = Link::default();
+    for seq in [0, 1, 4, 5] {
+        record(&mut link, 1, seq);
+    }
+    // 2 and 3 rebuilt from the parity after 5, then the next group carries on
+    link.recover(&Header { vehicle: 1, seq: 2 });
+    link.recover(&Header { vehicle: 1, seq: 3 });
+    record(&mut link, 1, 6);
+
+    let counts = link.vehicle(1).unwrap();
+    assert_eq!(
+        (counts.received, counts.missed, counts.recovered),
+        (5, 2, 2)
+    );
+    assert_eq!(counts.loss(), 0.0);
+}
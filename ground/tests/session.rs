@@ -0,0 +1,50 @@
+use ground::session::Session;
+use stack_ripper::{flight::Phase, telemetry};
+
+const CAPTURE: &str = include_str!("capture.csv");
+
+#[test]
+fn replays_a_capture() {
+    let mut log = Vec::new();
+    let mut session = Session::new(Some(&mut log)).unwrap();
+
+    let updates: Vec<_> = CAPTURE
+        .lines()
+        .filter_map(|line| session.handle_line(line).unwrap().ok())
+        .collect();
+
+    // The receiver's own CSV header and a stray defmt frame
+    assert_eq!(session.unreadable(), 2);
+    assert_eq!(updates.len(), 10);
+
+    let last = updates.last().unwrap();
+    assert_eq!(last.packet.state.fp, Phase::Landed);
+    assert_eq!(last.max_altitude, Some(850.9));
+    assert_eq!((last.link.received, last.link.missed), (10, 2));
+
+    let apogee = updates
+        .iter()
+        .find(|update| update.packet.state.fp == Phase::Apogee)
+        .unwrap();
+    assert_eq!(apogee.packet.header.seq, 107);
+}
+
+#[test]
+fn writes_a_csv_log_that_reads_back() {
+    let mut log = Vec::new();
+    let mut session = Session::new(Some(&mut log)).unwrap();
+    for line in CAPTURE.lines() {
+        session.handle_line(line).unwrap().ok();
+    }
+    drop(session);
+
+    let log = String::from_utf8(log).unwrap();
+    let mut lines = log.lines();
+    assert_eq!(lines.next(), CAPTURE.lines().next());
+
+    let packets: Vec<_> = lines
+        .map(|line| telemetry::parse_line(line).unwrap())
+        .collect();
+    assert_eq!(packets.len(), 10);
+    assert_eq!(packets[5].state.fa, Some(850.9));
+}
@@ -0,0 +1,294 @@
+#[cfg(feature = "esp32c3")]
+use defmt::{error, info};
+#[cfg(feature = "esp32c3")]
+use embassy_embedded_hal::shared_bus::asynch::spi::SpiDevice;
+#[cfg(feature = "esp32c3")]
+use embassy_executor::task;
+#[cfg(feature = "esp32c3")]
+use embassy_sync::{
+    blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex},
+    signal::Signal,
+};
+#[cfg(feature = "esp32c3")]
+use embassy_time::{Delay, Timer};
+use embedded_hal_async::{
+    delay::DelayNs,
+    spi::{Operation, SpiDevice as AsyncSpiDevice},
+};
+#[cfg(feature = "esp32c3")]
+use esp_hal::{
+    gpio::{AnyPin, Output},
+    spi::master::SpiDmaBus,
+    Async,
+};
+
+#[cfg(feature = "esp32c3")]
+use crate::state::STATE;
+
+// MS5611 commands, see the datasheet
+const CMD_RESET: u8 = 0x1E;
+const CMD_CONVERT_D1_OSR_4096: u8 = 0x48;
+const CMD_CONVERT_D2_OSR_4096: u8 = 0x58;
+const CMD_ADC_READ: u8 = 0x00;
+const CMD_PROM_READ: u8 = 0xA0;
+
+const RESET_TIME_US: u32 = 2_800;
+const CONVERSION_TIME_US: u32 = 9_040; // Worst case at OSR 4096
+
+const SEA_LEVEL_PRESSURE_PA: f32 = 101_325.0;
+
+// How many samples to average on the pad for the ground reference
+#[cfg(feature = "esp32c3")]
+const GROUND_REFERENCE_SAMPLES: u32 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error<E> {
+    Spi(E),
+    BadCalibration,
+}
+
+/// Factory calibration coefficients C1-C6 from the sensor PROM.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    c: [i64; 7],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Reading {
+    pub pressure_pa: i32,
+    pub temperature_centi_c: i32,
+}
+
+impl Calibration {
+    /// Builds the calibration from all eight PROM words, checking the CRC held in the last one.
+    pub fn from_prom(prom: &[u16; 8]) -> Option<Self> {
+        if crc4(prom) != (prom[7] & 0x000F) as u8 {
+            return None;
+        }
+
+        let mut c = [0i64; 7];
+        for (i, word) in prom.iter().enumerate().take(7).skip(1) {
+            c[i] = *word as i64;
+        }
+
+        Some(Calibration { c })
+    }
+
+    /// Second order temperature compensated pressure and temperature from the raw D1/D2 ADC values.
+    pub fn compensate(&self, d1: u32, d2: u32) -> Reading {
+        let c = &self.c;
+        let (d1, d2) = (d1 as i64, d2 as i64);
+
+        let dt = d2 - (c[5] << 8);
+        let mut temp = 2000 + ((dt * c[6]) >> 23);
+        let mut off = (c[2] << 16) + ((c[4] * dt) >> 7);
+        let mut sens = (c[1] << 15) + ((c[3] * dt) >> 8);
+
+        if temp < 2000 {
+            let t2 = (dt * dt) >> 31;
+            let mut off2 = 5 * (temp - 2000) * (temp - 2000) / 2;
+            let mut sens2 = 5 * (temp - 2000) * (temp - 2000) / 4;
+
+            if temp < -1500 {
+                off2 += 7 * (temp + 1500) * (temp + 1500);
+                sens2 += 11 * (temp + 1500) * (temp + 1500) / 2;
+            }
+
+            temp -= t2;
+            off -= off2;
+            sens -= sens2;
+        }
+
+        let pressure = (((d1 * sens) >> 21) - off) >> 15;
+
+        Reading {
+            pressure_pa: pressure as i32,
+            temperature_centi_c: temp as i32,
+        }
+    }
+}
+
+/// The 4 bit PROM CRC from MEAS application note AN520.
+pub fn crc4(prom: &[u16; 8]) -> u8 {
+    let mut remainder: u16 = 0;
+
+    for i in 0..16 {
+        // The CRC itself lives in the bottom nibble of the last word, and isn't part of the CRC
+        let word = if i / 2 == 7 {
+            prom[7] & 0xFF00
+        } else {
+            prom[i / 2]
+        };
+        remainder ^= if i % 2 == 1 { word & 0x00FF } else { word >> 8 };
+
+        for _ in 0..8 {
+            if remainder & 0x8000 != 0 {
+                remainder = (remainder << 1) ^ 0x3000;
+            } else {
+                remainder <<= 1;
+            }
+        }
+    }
+
+    ((remainder >> 12) & 0x000F) as u8
+}
+
+/// International standard atmosphere altitude for a pressure, relative to sea level, in meters.
+pub fn pressure_altitude(pressure_pa: f32) -> f32 {
+    44_330.0 * (1.0 - powf(pressure_pa / SEA_LEVEL_PRESSURE_PA, 1.0 / 5.255))
+}
+
+// micromath's powf is only good to a few percent, which is hundreds of meters by 1km up.
+// These are plain range-reduced series, good to well under a centimeter over any pressure we'll see.
+fn powf(base: f32, exponent: f32) -> f32 {
+    exp(exponent * ln(base))
+}
+
+fn ln(x: f32) -> f32 {
+    // x = m * 2^k, with m in [sqrt(2)/2, sqrt(2))
+    let bits = x.to_bits();
+    let mut k = ((bits >> 23) & 0xFF) as i32 - 127;
+    let mut m = f32::from_bits((bits & 0x007F_FFFF) | 0x3F80_0000);
+    if m > core::f32::consts::SQRT_2 {
+        m /= 2.0;
+        k += 1;
+    }
+
+    // ln(m) = 2 * atanh(z)
+    let z = (m - 1.0) / (m + 1.0);
+    let z2 = z * z;
+    let series = z * (1.0 + z2 * (1.0 / 3.0 + z2 * (1.0 / 5.0 + z2 * (1.0 / 7.0 + z2 / 9.0))));
+
+    k as f32 * core::f32::consts::LN_2 + 2.0 * series
+}
+
+fn exp(x: f32) -> f32 {
+    // e^x = 2^n * e^r, with |r| <= ln(2) / 2
+    let n = (x / core::f32::consts::LN_2 + if x < 0.0 { -0.5 } else { 0.5 }) as i32;
+    let r = x - n as f32 * core::f32::consts::LN_2;
+
+    let mut term = 1.0;
+    let mut sum = 1.0;
+    for i in 1..10 {
+        term *= r / i as f32;
+        sum += term;
+    }
+
+    sum * f32::from_bits(((n + 127) as u32) << 23)
+}
+
+pub struct Ms5611<SPI> {
+    spi: SPI,
+    calibration: Option<Calibration>,
+}
+
+impl<SPI: AsyncSpiDevice> Ms5611<SPI> {
+    pub fn new(spi: SPI) -> Self {
+        Ms5611 {
+            spi,
+            calibration: None,
+        }
+    }
+
+    /// Resets the sensor and loads its calibration, must be called before `sample`.
+    pub async fn init(&mut self, delay: &mut impl DelayNs) -> Result<(), Error<SPI::Error>> {
+        self.spi.write(&[CMD_RESET]).await.map_err(Error::Spi)?;
+        delay.delay_us(RESET_TIME_US).await;
+
+        let mut prom = [0u16; 8];
+        for (i, word) in prom.iter_mut().enumerate() {
+            let mut buf = [0u8; 2];
+            self.spi
+                .transaction(&mut [
+                    Operation::Write(&[CMD_PROM_READ + 2 * i as u8]),
+                    Operation::Read(&mut buf),
+                ])
+                .await
+                .map_err(Error::Spi)?;
+            *word = u16::from_be_bytes(buf);
+        }
+
+        self.calibration = Some(Calibration::from_prom(&prom).ok_or(Error::BadCalibration)?);
+        Ok(())
+    }
+
+    pub async fn sample(&mut self, delay: &mut impl DelayNs) -> Result<Reading, Error<SPI::Error>> {
+        let calibration = self.calibration.ok_or(Error::BadCalibration)?;
+
+        let d1 = self.convert(CMD_CONVERT_D1_OSR_4096, delay).await?;
+        let d2 = self.convert(CMD_CONVERT_D2_OSR_4096, delay).await?;
+
+        Ok(calibration.compensate(d1, d2))
+    }
+
+    async fn convert(
+        &mut self,
+        command: u8,
+        delay: &mut impl DelayNs,
+    ) -> Result<u32, Error<SPI::Error>> {
+        self.spi.write(&[command]).await.map_err(Error::Spi)?;
+        delay.delay_us(CONVERSION_TIME_US).await;
+
+        let mut buf = [0u8; 3];
+        self.spi
+            .transaction(&mut [Operation::Write(&[CMD_ADC_READ]), Operation::Read(&mut buf)])
+            .await
+            .map_err(Error::Spi)?;
+
+        Ok(u32::from_be_bytes([0, buf[0], buf[1], buf[2]]))
+    }
+}
+
+#[cfg(feature = "esp32c3")]
+#[task]
+pub async fn sample_pressure(
+    spi: SpiDevice<'static, NoopRawMutex, SpiDmaBus<'static, Async>, Output<'static, AnyPin>>,
+) -> ! {
+    let mut baro = Ms5611::new(spi);
+    let mut delay = Delay;
+
+    while baro.init(&mut delay).await.is_err() {
+        error!("Altimeter init failed");
+        Timer::after_millis(1_000).await;
+    }
+
+    // Whatever we see at boot is the pad, average a few samples so aar starts out near zero
+    let mut ground_altitude: Option<f32> = None;
+    let mut ground_sum = 0.0;
+    let mut ground_count = 0;
+
+    loop {
+        let reading = match baro.sample(&mut delay).await {
+            Ok(reading) => reading,
+            Err(_) => {
+                error!("Altimeter read failed");
+                Timer::after_millis(100).await;
+                continue;
+            }
+        };
+
+        let altitude = pressure_altitude(reading.pressure_pa as f32);
+
+        if ground_altitude.is_none() {
+            ground_sum += altitude;
+            ground_count += 1;
+            if ground_count == GROUND_REFERENCE_SAMPLES {
+                let ground = ground_sum / ground_count as f32;
+                info!("Altimeter ground reference set at {}m", ground);
+                ground_altitude = Some(ground);
+            }
+        }
+
+        let mut state = STATE.lock().await;
+        state.aaa = Some(altitude);
+        state.aar = ground_altitude.map(|ground| altitude - ground);
+        if let Some(aar) = state.aar {
+            ALTITUDE.signal(aar);
+        }
+    }
+}
+
+// Each new relative altitude, so the flight tracker only takes a sample in once however often it looks
+#[cfg(feature = "esp32c3")]
+pub static ALTITUDE: Signal<CriticalSectionRawMutex, f32> = Signal::new();
@@ -0,0 +1,206 @@
+// Keeps anyone else on the channel from commanding a vehicle.
+//
+// Both ends are built with the same 256-bit key. Every command frame carries a counter, bumped by the ground station
+// for each new command, and ends in the first `TAG_LEN` bytes of an HMAC-SHA256 over everything before it. The
+// vehicle drops a frame whose tag doesn't match, and won't act on one whose counter isn't past the last it acted on,
+// so playing back a recording of an old command does nothing. Both ends keep their counter in flash to carry it over
+// a reboot.
+//
+// A saved counter looks like:
+//
+//   | magic | counter (LE) | crc16 (LE) | 0xFF |
+//   |   1   |      4       |     2      |  1   |
+//
+// Each one goes in the next erased slot of one of two sectors, and the newest good one in either is the counter. A
+// sector's only erased once every slot's been used, rather than for every command, and then only after the next
+// counter's gone into the other sector, so losing power at any point still leaves a good counter behind. Counter
+// sectors that have been written to but hold no good counter can't be trusted to keep out a replay.
+use embedded_storage::nor_flash::NorFlash;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::protocol::{crc16, Error};
+
+pub const KEY_LEN: usize = 32;
+pub const TAG_LEN: usize = 8; // Forgeries have to be tried on air, one per listening window
+
+pub const COUNTER_MAGIC: u8 = 0x43;
+pub const COUNTER_RECORD_LEN: usize = 8;
+
+// Second and third sectors of the NVS partition, after lora::CONFIG_ADDRESS
+pub const COUNTER_ADDRESS: u32 = 0xA000;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CounterError<E> {
+    Flash(E),
+    Corrupt, // Written to, but there's no good counter left in it
+}
+
+/// The pre-shared key. Deliberately not `Debug`, so it can't end up in a log.
+#[derive(Clone, Copy)]
+pub struct Key([u8; KEY_LEN]);
+
+impl Key {
+    pub const fn new(bytes: [u8; KEY_LEN]) -> Self {
+        Key(bytes)
+    }
+
+    /// Parses 64 hex digits. It's `const` so a bad key fails the build rather than the flight.
+    pub const fn from_hex(hex: &str) -> Option<Self> {
+        let hex = hex.as_bytes();
+        if hex.len() != 2 * KEY_LEN {
+            return None;
+        }
+
+        let mut bytes = [0u8; KEY_LEN];
+        let mut i = 0;
+        while i < KEY_LEN {
+            let (Some(high), Some(low)) = (nibble(hex[2 * i]), nibble(hex[2 * i + 1])) else {
+                return None;
+            };
+            bytes[i] = high << 4 | low;
+            i += 1;
+        }
+
+        Some(Key(bytes))
+    }
+
+    /// The truncated HMAC that goes on the end of `message`.
+    pub fn tag(&self, message: &[u8]) -> [u8; TAG_LEN] {
+        let mut tag = [0u8; TAG_LEN];
+        tag.copy_from_slice(&hmac_sha256(&self.0, message)[..TAG_LEN]);
+        tag
+    }
+
+    /// Whether `tag` is the right one for `message`, compared in constant time.
+    pub fn verify(&self, message: &[u8], tag: &[u8]) -> bool {
+        tag.len() == TAG_LEN && mac(&self.0, message).verify_truncated_left(tag).is_ok()
+    }
+}
+
+const fn nibble(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+fn mac(key: &[u8], message: &[u8]) -> HmacSha256 {
+    // HMAC takes a key of any length, so this can't fail
+    let Ok(mut mac) = HmacSha256::new_from_slice(key) else {
+        unreachable!()
+    };
+    mac.update(message);
+    mac
+}
+
+/// The full, untruncated HMAC-SHA256 of `message`.
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    mac(key, message).finalize().into_bytes().into()
+}
+
+/// Frames `counter` into a record for flash.
+pub fn encode_counter(counter: u32) -> [u8; COUNTER_RECORD_LEN] {
+    let mut buf = [0xFF; COUNTER_RECORD_LEN];
+    buf[0] = COUNTER_MAGIC;
+    buf[1..5].copy_from_slice(&counter.to_le_bytes());
+    let crc = crc16(&buf[..5]).to_le_bytes();
+    buf[5..7].copy_from_slice(&crc);
+    buf
+}
+
+pub fn decode_counter(buf: &[u8; COUNTER_RECORD_LEN]) -> Result<u32, Error> {
+    if buf[0] != COUNTER_MAGIC {
+        return Err(Error::BadMagic(buf[0]));
+    }
+
+    if u16::from_le_bytes([buf[5], buf[6]]) != crc16(&buf[..5]) {
+        return Err(Error::BadCrc);
+    }
+
+    Ok(u32::from_le_bytes([buf[1], buf[2], buf[3], buf[4]]))
+}
+
+/// Reads the newest counter saved in the two sectors from `address`. `None` if they've never been written to.
+pub fn load_counter<F: NorFlash>(
+    flash: &mut F,
+    address: u32,
+) -> Result<Option<u32>, CounterError<F::Error>> {
+    let [first, second] = counter_sectors::<F>(address);
+    let first = scan_counters(flash, first).map_err(CounterError::Flash)?;
+    let second = scan_counters(flash, second).map_err(CounterError::Flash)?;
+
+    match first.last.max(second.last) {
+        None if first.used || second.used => Err(CounterError::Corrupt),
+        last => Ok(last),
+    }
+}
+
+/// Writes `counter` to the next free slot in the sector in use. Once that's full it goes at the start of the other
+/// sector, and the full one's erased after.
+pub fn save_counter<F: NorFlash>(
+    flash: &mut F,
+    address: u32,
+    counter: u32,
+) -> Result<(), F::Error> {
+    let [first, second] = counter_sectors::<F>(address);
+    let (first_scan, second_scan) = (scan_counters(flash, first)?, scan_counters(flash, second)?);
+
+    // The one with the newest counter is in use, whatever's left in the other is older
+    let (current, free, other, other_used) = if second_scan.last > first_scan.last {
+        (second, second_scan.free, first, first_scan.used)
+    } else {
+        (first, first_scan.free, second, second_scan.used)
+    };
+
+    if let Some(slot) = free {
+        return flash.write(slot, &encode_counter(counter));
+    }
+
+    // Left over from a reboot before the last erase finished, the full sector still has the newest counter
+    if other_used {
+        flash.erase(other, other + F::ERASE_SIZE as u32)?;
+    }
+    flash.write(other, &encode_counter(counter))?;
+    flash.erase(current, current + F::ERASE_SIZE as u32)
+}
+
+fn counter_sectors<F: NorFlash>(address: u32) -> [u32; 2] {
+    [address, address + F::ERASE_SIZE as u32]
+}
+
+struct Scan {
+    last: Option<u32>, // The last good counter
+    free: Option<u32>, // The slot after the last one written, if there's one left
+    used: bool,        // Anything at all written since the sector was erased
+}
+
+fn scan_counters<F: NorFlash>(flash: &mut F, address: u32) -> Result<Scan, F::Error> {
+    let mut scan = Scan {
+        last: None,
+        free: Some(address),
+        used: false,
+    };
+    for slot in
+        (0..F::ERASE_SIZE / COUNTER_RECORD_LEN).map(|i| address + (i * COUNTER_RECORD_LEN) as u32)
+    {
+        let mut buf = [0u8; COUNTER_RECORD_LEN];
+        flash.read(slot, &mut buf)?;
+        if buf == [0xFF; COUNTER_RECORD_LEN] {
+            continue;
+        }
+
+        scan.used = true;
+        let next = slot + COUNTER_RECORD_LEN as u32;
+        scan.free = (next < address + F::ERASE_SIZE as u32).then_some(next);
+        // One cut short by a reboot is passed over, the one before still stands
+        if let Ok(counter) = decode_counter(&buf) {
+            scan.last = Some(counter);
+        }
+    }
+    Ok(scan)
+}
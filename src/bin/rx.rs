@@ -11,9 +11,18 @@ use esp_hal::{
     gpio::{Input, Level, Output, Pull},
     peripherals::Peripherals,
     timer::timg::TimerGroup,
+    usb_serial_jtag::UsbSerialJtag,
 };
+use esp_storage::FlashStorage;
 
-use stack_ripper::{lora, pins, spi, state};
+use stack_ripper::{
+    lora, pins, spi, state,
+    telemetry::{self, Format},
+    uplink,
+};
+
+// What gets written to the USB serial port for each packet, see `telemetry` for the formats
+const OUTPUT_FORMAT: Format = Format::Csv;
 
 #[embassy_executor::task]
 async fn print_state() -> ! {
@@ -38,6 +47,13 @@ async fn main(spawner: Spawner) {
 
     info!("Initializing compete");
 
+    // Telemetry goes out over USB, commands for the vehicles come back in
+    let (usb_rx, usb_tx) = UsbSerialJtag::new(pins.usb).into_async().split();
+    spawner
+        .spawn(telemetry::forward(usb_tx, OUTPUT_FORMAT))
+        .unwrap();
+    spawner.spawn(uplink::listen(usb_rx)).unwrap();
+
     // Setup SPI bus
     let spi_bus = spi::init(
         pins.dma,
@@ -55,6 +71,11 @@ async fn main(spawner: Spawner) {
     let lora_irq = Input::new(pins.lora_irq, Pull::None);
 
     spawner
-        .spawn(lora::receive(lora_spi, lora_irq, lora_rst))
+        .spawn(lora::receive(
+            lora_spi,
+            lora_irq,
+            lora_rst,
+            FlashStorage::new(),
+        ))
         .ok();
 }
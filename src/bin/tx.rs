@@ -7,6 +7,7 @@ use embassy_executor::Spawner;
 
 use esp_hal::{
     gpio::{Input, Level, Output, Pull},
+    i2c::master::{self as i2c, I2c},
     peripherals::Peripherals,
     prelude::*,
     timer::timg::TimerGroup,
@@ -15,8 +16,23 @@ use esp_hal::{
 
 use defmt::info;
 use esp_backtrace as _;
+use esp_storage::FlashStorage;
 
-use stack_ripper::{gps, lora, pins, spi};
+use stack_ripper::{
+    altimeter, flight, gps, imu, logger,
+    lora::{self, fec::Scheme, rate::Rates},
+    pins, pyro, spi, timesync,
+};
+
+// Identifies this vehicle in every telemetry frame it sends
+const VEHICLE_ID: u8 = 0x01;
+
+// A parity frame after every 4 telemetry frames, so the ground station can rebuild any one of them it missed. None
+// turns it off.
+const FEC: Option<Scheme> = Scheme::new(4, 1);
+
+// How often to send in each phase of the flight, anything that implements `lora::rate::Policy` will do
+static RATES: Rates = Rates::new();
 
 #[main]
 async fn main(_spawner: Spawner) -> () {
@@ -34,16 +50,48 @@ async fn main(_spawner: Spawner) -> () {
     info!("Initializing compete");
 
     // Setup UART for GPS
-    let uart_config = Config::default().baudrate(9600);
-    let uart = Uart::new_with_config(pins.uart, uart_config, pins.uart_rx, pins.uart_tx)
+    let uart_config = Config::default().baudrate(gps::DEFAULT_BAUD);
+    let mut uart = Uart::new_with_config(pins.uart, uart_config, pins.uart_rx, pins.uart_tx)
         .unwrap()
         .into_async();
 
-    let (rx, _) = uart.split();
+    gps::set_baud(&mut uart).await;
+
+    let (rx, tx) = uart.split();
 
-    // Note that this task now owns the UART RX line completely
+    // Note that this task now owns the UART completely
     // UART is a 1:1 interface, so this is fine
-    _spawner.spawn(gps::sample_uart(rx)).unwrap();
+    _spawner.spawn(gps::sample_uart(rx, tx)).unwrap();
+
+    if let Some(pps) = pins.pps {
+        _spawner
+            .spawn(timesync::discipline(Input::new(pps, Pull::Down)))
+            .unwrap();
+    }
+
+    _spawner.spawn(flight::track()).unwrap();
+
+    if let Some(imu_pins) = pins.imu {
+        let i2c = I2c::new(pins.i2c, i2c::Config::default())
+            .with_sda(imu_pins.sda)
+            .with_scl(imu_pins.scl)
+            .into_async();
+
+        _spawner.spawn(imu::sample_imu(i2c)).unwrap();
+    }
+
+    if let Some(pyro_pins) = pins.pyro {
+        let drogue = pyro::Channel::new(
+            Output::new(pyro_pins.drogue_fire, Level::Low),
+            Input::new(pyro_pins.drogue_sense, Pull::Down),
+        );
+        let main = pyro::Channel::new(
+            Output::new(pyro_pins.main_fire, Level::Low),
+            Input::new(pyro_pins.main_sense, Pull::Down),
+        );
+
+        _spawner.spawn(pyro::deploy(drogue, main)).unwrap();
+    }
 
     // Setup SPI bus
     let spi_bus = spi::init(
@@ -57,10 +105,34 @@ async fn main(_spawner: Spawner) -> () {
     let lora_spi_csb = Output::new(pins.lora_nss, Level::High);
     let lora_spi = SpiDevice::new(spi_bus, lora_spi_csb);
 
+    if let Some(baro_nss) = pins.baro_nss {
+        let baro_spi_csb = Output::new(baro_nss, Level::High);
+        let baro_spi = SpiDevice::new(spi_bus, baro_spi_csb);
+
+        _spawner
+            .spawn(altimeter::sample_pressure(baro_spi))
+            .unwrap();
+    }
+
+    if let Some(flash_nss) = pins.flash_nss {
+        let flash_spi_csb = Output::new(flash_nss, Level::High);
+        let flash_spi = SpiDevice::new(spi_bus, flash_spi_csb);
+
+        _spawner.spawn(logger::record(flash_spi)).unwrap();
+    }
+
     let lora_rst = Output::new(pins.lora_rst, Level::High);
     let lora_irq = Input::new(pins.lora_irq, Pull::Down);
 
     _spawner
-        .spawn(lora::transmit(lora_spi, lora_irq, lora_rst))
+        .spawn(lora::transmit(
+            lora_spi,
+            lora_irq,
+            lora_rst,
+            VEHICLE_ID,
+            FEC,
+            &RATES,
+            FlashStorage::new(),
+        ))
         .ok();
 }
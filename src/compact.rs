@@ -0,0 +1,505 @@
+// The telemetry payload, packed to cost as little airtime as it can.
+//
+// Every `KEYFRAME_INTERVAL`th frame by sequence number is a keyframe with the whole state in it. The frames in between
+// only carry how far each value has moved since that keyframe, which is mostly a byte or two. They're taken from the
+// keyframe rather than the frame before so a lost frame doesn't take the ones after it along, but a lost keyframe
+// still costs the frames up to the next one, unless parity rebuilds it. The ground station holds on to those frames in
+// case it does, see `lora::fec`.
+//
+// Numbers go on air in fixed point: positions in 1e-7 degrees, altitudes in decimetres, vertical speed and
+// acceleration in whole m/s and m/s^2, ground speed, HDOP and course in tenths. Times and counts are exact. Each is a
+// zigzag varint, so a small one of either sign takes a single byte. NaN and infinity go as missing, like they're
+// written out on the ground. The IMU vectors and orientation stay on board, they're in the flash log.
+//
+//   keyframe  | flags (LE) | present (LE) | values |
+//             |     2      |      2       |        |
+//
+//   between   | head | check | changed (LE) | flags (LE) | present (LE) | values |
+//             |  1   |   1   |      2       |   0 / 2    |    0 / 2     |        |
+//
+// `flags` has bit 0 set on a keyframe, bit 1 for apogee, then three bits each for the flight phase, GPS fix, and
+// drogue and main pyro status. `present` has a bit for each value in `quantize` that's there, and those follow in the
+// same order. GPS time goes as the difference from the boot time and the other altitudes from the altimeter's, when
+// there's both.
+//
+// Between keyframes, `head` takes the place of the flags' low byte, with bit 0 clear. Its bit 1 is set when the flags
+// are sent, and bit 2 when `present` is, which is only when they differ from the keyframe's. Only the values with
+// their bit set in `changed` are sent at all, as the difference from the keyframe. The altimeter's altitude is taken
+// to have moved on at the keyframe's vertical speed and acceleration, and the followers above with their leader, so
+// it's only how far off that they are. `check` is the low byte of the keyframe's CRC, so after a reboot a frame isn't
+// taken against a keyframe from before.
+#[cfg(feature = "esp32c3")]
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+
+use crate::{
+    flight::Phase,
+    gps::Fix,
+    protocol::{crc16, Error, Header},
+    pyro::Status,
+    state::State,
+    timesync::Utc,
+};
+
+// Has to divide 65536, so keyframes stay lined up as the sequence number wraps
+pub const KEYFRAME_INTERVAL: u16 = 8;
+
+// Keyframes the receiver keeps, one for each vehicle id modulo this
+pub const MAX_VEHICLES: usize = 4;
+
+// Where each value goes in `quantize`, for the ones that need picking out
+const FIELDS: usize = 16;
+const BT: usize = 0;
+const T: usize = 1;
+const GA: usize = 4;
+const AAA: usize = 5;
+const AAR: usize = 6;
+const FA: usize = 7;
+const VV: usize = 8;
+const VA: usize = 9;
+
+// Values that move with another, so only the difference from that needs sending: GPS time with the boot time, and the
+// other altitudes with the altimeter's
+const FOLLOWS: [(usize, usize); 4] = [(T, BT), (GA, AAR), (AAA, AAR), (FA, AAR)];
+
+// Longest a payload can be, with every value present and as large as it goes
+pub const MAX_PAYLOAD_LEN: usize = 8 + FIELDS * 10;
+
+const KEYFRAME: u16 = 1 << 0;
+const APOGEE: u16 = 1 << 1;
+const PHASE_SHIFT: u16 = 2;
+const FIX_SHIFT: u16 = 5;
+const DROGUE_SHIFT: u16 = 8;
+const MAIN_SHIFT: u16 = 11;
+
+// In `head`, between keyframes
+const FLAGS_SENT: u8 = 1 << 1;
+const PRESENT_SENT: u8 = 1 << 2;
+
+// In declaration order, which is what `as u16` gives
+const PHASES: [Phase; 7] = [
+    Phase::Pad,
+    Phase::Boost,
+    Phase::Coast,
+    Phase::Apogee,
+    Phase::Descent,
+    Phase::Main,
+    Phase::Landed,
+];
+const FIXES: [Fix; 6] = [
+    Fix::None,
+    Fix::Gps,
+    Fix::Gps2d,
+    Fix::Dgps,
+    Fix::Rtk,
+    Fix::Estimated,
+];
+const STATUSES: [Status; 5] = [
+    Status::Open,
+    Status::Safe,
+    Status::Armed,
+    Status::Firing,
+    Status::Fired,
+];
+
+type Values = [Option<i64>; FIELDS];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Keyframe {
+    vehicle: u8,
+    first: u16, // Sequence number of the first frame it covers
+    flags: u16,
+    values: Values,
+    check: u8,
+}
+
+/// The vehicle's side: remembers the last keyframe sent, for the frames after it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Encoder {
+    keyframe: Option<Keyframe>,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Encoder {
+    pub const fn new() -> Self {
+        Encoder { keyframe: None }
+    }
+
+    /// Packs `state` into `buf` as the payload of the frame going out with `header`, returning how long it is.
+    pub fn encode(
+        &mut self,
+        header: &Header,
+        state: &State,
+        buf: &mut [u8],
+    ) -> Result<usize, Error> {
+        let values = quantize(state);
+        let present = mask(&values, |_, value| value.is_some());
+        let first = first(header.seq);
+        let flags = flags(state);
+        let mut out = Writer { buf, len: 0 };
+
+        // Normally only the first of a run is a keyframe, but there may not have been one yet
+        let keyframe = self
+            .keyframe
+            .filter(|keyframe| keyframe.first == first && header.seq != first);
+
+        let Some(keyframe) = keyframe else {
+            out.bytes(&(flags | KEYFRAME).to_le_bytes())?;
+            out.bytes(&present.to_le_bytes())?;
+            for value in relative(values).iter().flatten() {
+                out.varint(*value)?;
+            }
+
+            self.keyframe = Some(Keyframe {
+                vehicle: header.vehicle,
+                first,
+                flags,
+                values,
+                check: crc16(&out.buf[..out.len]) as u8,
+            });
+            return Ok(out.len);
+        };
+
+        // Anything the keyframe didn't have goes, even if it happens to match
+        let base = base(&keyframe.values, &values);
+        let changed = mask(&values, |i, value| {
+            value.is_some() && (keyframe.values[i].is_none() || *value != Some(base[i]))
+        });
+        let mut head = 0;
+        if flags != keyframe.flags {
+            head |= FLAGS_SENT;
+        }
+        if present != mask(&keyframe.values, |_, value| value.is_some()) {
+            head |= PRESENT_SENT;
+        }
+
+        out.bytes(&[head, keyframe.check])?;
+        out.bytes(&changed.to_le_bytes())?;
+        if head & FLAGS_SENT != 0 {
+            out.bytes(&flags.to_le_bytes())?;
+        }
+        if head & PRESENT_SENT != 0 {
+            out.bytes(&present.to_le_bytes())?;
+        }
+        for (i, value) in values.iter().enumerate() {
+            if let (Some(value), true) = (value, changed & 1 << i != 0) {
+                out.varint(value.wrapping_sub(base[i]))?;
+            }
+        }
+
+        Ok(out.len)
+    }
+}
+
+/// The ground station's side: keeps each vehicle's last keyframe, to unpack the frames after it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decoder {
+    keyframes: [Option<Keyframe>; MAX_VEHICLES],
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Decoder {
+            keyframes: [None; MAX_VEHICLES],
+        }
+    }
+
+    /// Unpacks the payload of the frame `header` came with. A frame between keyframes needs its keyframe to have
+    /// been through here first, otherwise it's `Error::NoKeyframe`.
+    pub fn decode(&mut self, header: &Header, payload: &[u8]) -> Result<State, Error> {
+        let mut input = Reader { buf: payload };
+        let head = input.byte()?;
+        let slot = &mut self.keyframes[header.vehicle as usize % MAX_VEHICLES];
+        let first = first(header.seq);
+
+        if head & KEYFRAME as u8 == 0 {
+            let check = input.byte()?;
+            let keyframe = slot
+                .filter(|k| k.vehicle == header.vehicle && k.first == first && k.check == check)
+                .ok_or(Error::NoKeyframe)?;
+            let (flags, values) = between(&keyframe, head, &mut input)?;
+            if !input.buf.is_empty() {
+                return Err(Error::Deserialize);
+            }
+            return restore(&values, flags);
+        }
+
+        let flags = u16::from_le_bytes([head, input.byte()?]) & !KEYFRAME;
+        let present = input.u16()?;
+        let mut values: Values = [None; FIELDS];
+        for (i, value) in values.iter_mut().enumerate() {
+            if present & 1 << i != 0 {
+                *value = Some(input.varint()?);
+            }
+        }
+        if !input.buf.is_empty() {
+            return Err(Error::Deserialize);
+        }
+
+        let values = absolute(values);
+        let state = restore(&values, flags)?;
+        *slot = Some(Keyframe {
+            vehicle: header.vehicle,
+            first,
+            flags,
+            values,
+            check: crc16(payload) as u8,
+        });
+
+        Ok(state)
+    }
+}
+
+// The flags and values in a frame between keyframes, from what's left of it after the check byte
+fn between(keyframe: &Keyframe, head: u8, input: &mut Reader) -> Result<(u16, Values), Error> {
+    let changed = input.u16()?;
+    let flags = match head & FLAGS_SENT {
+        0 => keyframe.flags,
+        _ => input.u16()?,
+    };
+    let keyframe = &keyframe.values;
+    let present = match head & PRESENT_SENT {
+        0 => mask(keyframe, |_, value| value.is_some()),
+        _ => input.u16()?,
+    };
+
+    let mut deltas = [0i64; FIELDS];
+    for (i, delta) in deltas.iter_mut().enumerate() {
+        if changed & 1 << i != 0 {
+            *delta = input.varint()?;
+        }
+    }
+
+    let value = |i: usize, base: i64| match (present & 1 << i != 0, changed & 1 << i != 0) {
+        (true, true) => Ok(Some(base.wrapping_add(deltas[i]))),
+        // Unchanged, which only works if there was something to be unchanged from
+        (true, false) => keyframe[i].map(|_| Some(base)).ok_or(Error::Deserialize),
+        (false, true) => Err(Error::Deserialize),
+        (false, false) => Ok(None),
+    };
+
+    // The ones the others follow first. The boot time comes first in `FOLLOWS`, the altitude's guess needs it
+    let mut leaders: Values = [None; FIELDS];
+    for (_, leader) in FOLLOWS {
+        leaders[leader] = value(leader, base(keyframe, &leaders)[leader])?;
+    }
+
+    let base = base(keyframe, &leaders);
+    let mut values: Values = [None; FIELDS];
+    for (i, slot) in values.iter_mut().enumerate() {
+        *slot = value(i, base[i])?;
+    }
+    Ok((flags, values))
+}
+
+// Keyframe values as they go on air, with the ones in `FOLLOWS` as the difference from their leader
+fn relative(mut values: Values) -> Values {
+    for (follower, leader) in FOLLOWS {
+        if let (Some(value), Some(by)) = (values[follower], values[leader]) {
+            values[follower] = Some(value.wrapping_sub(by));
+        }
+    }
+    values
+}
+
+fn absolute(mut values: Values) -> Values {
+    for (follower, leader) in FOLLOWS {
+        if let (Some(value), Some(by)) = (values[follower], values[leader]) {
+            values[follower] = Some(value.wrapping_add(by));
+        }
+    }
+    values
+}
+
+fn first(seq: u16) -> u16 {
+    seq - seq % KEYFRAME_INTERVAL
+}
+
+// What a frame between keyframes has its values sent as the difference from: the keyframe's, with the altimeter's
+// altitude carried on at the keyframe's vertical speed and acceleration, and the ones in `FOLLOWS` moved on by
+// however much their leader has
+fn base(keyframe: &Values, values: &Values) -> [i64; FIELDS] {
+    let mut base = keyframe.map(|value| value.unwrap_or(0));
+    if let (Some(now), Some(then)) = (values[BT], keyframe[BT]) {
+        // Decimetres from m/s over milliseconds, and half of m/s^2 over milliseconds squared
+        let ms = now.wrapping_sub(then);
+        let climb = (base[VV].wrapping_mul(ms) / 100)
+            .wrapping_add(base[VA].wrapping_mul(ms).wrapping_mul(ms) / 200_000);
+        base[AAR] = base[AAR].wrapping_add(climb);
+    }
+    for (follower, leader) in FOLLOWS {
+        if let (Some(now), Some(then)) = (values[leader], keyframe[leader]) {
+            base[follower] = base[follower].wrapping_add(now.wrapping_sub(then));
+        }
+    }
+    base
+}
+
+fn mask(values: &Values, pick: impl Fn(usize, &Option<i64>) -> bool) -> u16 {
+    values
+        .iter()
+        .enumerate()
+        .filter(|(i, value)| pick(*i, value))
+        .fold(0, |mask, (i, _)| mask | 1 << i)
+}
+
+fn flags(state: &State) -> u16 {
+    let mut flags = (state.fp as u16) << PHASE_SHIFT
+        | (state.fx as u16) << FIX_SHIFT
+        | (state.pd as u16) << DROGUE_SHIFT
+        | (state.pm as u16) << MAIN_SHIFT;
+    if state.ap {
+        flags |= APOGEE;
+    }
+    flags
+}
+
+fn quantize(state: &State) -> Values {
+    [
+        Some(state.bt as i64),
+        state.t.map(|t| t.0),
+        fixed(state.lt, 1e7),
+        fixed(state.ln, 1e7),
+        fixed(state.ga, 10.0),
+        fixed(state.aaa, 10.0),
+        fixed(state.aar, 10.0),
+        fixed(state.fa, 10.0),
+        fixed(state.vv, 1.0),
+        fixed(state.va, 1.0),
+        state.ns.map(i64::from),
+        fixed(state.hd, 10.0),
+        fixed(state.gs, 10.0),
+        fixed(state.gc, 10.0),
+        Some(state.ge.into()),
+        state.ak.map(i64::from),
+    ]
+}
+
+fn restore(values: &Values, flags: u16) -> Result<State, Error> {
+    let [bt, t, lt, ln, ga, aaa, aar, fa, vv, va, ns, hd, gs, gc, ge, ak] = *values;
+
+    Ok(State {
+        bt: bt.ok_or(Error::Deserialize)? as u64,
+        t: t.map(Utc),
+        lt: float(lt, 1e7),
+        ln: float(ln, 1e7),
+        ga: float(ga, 10.0),
+        aaa: float(aaa, 10.0),
+        aar: float(aar, 10.0),
+        fa: float(fa, 10.0),
+        vv: float(vv, 1.0),
+        va: float(va, 1.0),
+        ns: ns
+            .map(|ns| ns.try_into())
+            .transpose()
+            .map_err(|_| Error::Deserialize)?,
+        hd: float(hd, 10.0),
+        gs: float(gs, 10.0),
+        gc: float(gc, 10.0),
+        ge: ge
+            .and_then(|ge| ge.try_into().ok())
+            .ok_or(Error::Deserialize)?,
+        ak: ak
+            .map(|ak| ak.try_into())
+            .transpose()
+            .map_err(|_| Error::Deserialize)?,
+        fp: pick(&PHASES, flags, PHASE_SHIFT)?,
+        fx: pick(&FIXES, flags, FIX_SHIFT)?,
+        pd: pick(&STATUSES, flags, DROGUE_SHIFT)?,
+        pm: pick(&STATUSES, flags, MAIN_SHIFT)?,
+        ap: flags & APOGEE != 0,
+        ..State::default()
+    })
+}
+
+// Rounded to the nearest step, in f64 so latitude and longitude keep all of an f32's precision
+fn fixed(value: Option<f32>, scale: f64) -> Option<i64> {
+    let scaled = value.filter(|value| value.is_finite())? as f64 * scale;
+    Some(if scaled < 0.0 {
+        scaled - 0.5
+    } else {
+        scaled + 0.5
+    } as i64)
+}
+
+fn float(value: Option<i64>, scale: f64) -> Option<f32> {
+    value.map(|value| (value as f64 / scale) as f32)
+}
+
+fn pick<T: Copy>(table: &[T], flags: u16, shift: u16) -> Result<T, Error> {
+    table
+        .get((flags >> shift & 0b111) as usize)
+        .copied()
+        .ok_or(Error::Deserialize)
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let end = self.len + bytes.len();
+        self.buf
+            .get_mut(self.len..end)
+            .ok_or(Error::Serialize)?
+            .copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    // Zigzag, then LEB128
+    fn varint(&mut self, value: i64) -> Result<(), Error> {
+        let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+        loop {
+            let byte = (zigzag & 0x7F) as u8;
+            zigzag >>= 7;
+            if zigzag == 0 {
+                return self.bytes(&[byte]);
+            }
+            self.bytes(&[byte | 0x80])?;
+        }
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, Error> {
+        let (&byte, rest) = self.buf.split_first().ok_or(Error::Deserialize)?;
+        self.buf = rest;
+        Ok(byte)
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes([self.byte()?, self.byte()?]))
+    }
+
+    fn varint(&mut self) -> Result<i64, Error> {
+        let mut zigzag = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            zigzag |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64));
+            }
+        }
+        Err(Error::Deserialize)
+    }
+}
+
+#[cfg(feature = "esp32c3")]
+pub static DECODER: Mutex<CriticalSectionRawMutex, Decoder> = Mutex::new(Decoder::new());
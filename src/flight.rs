@@ -0,0 +1,247 @@
+use core::str::FromStr;
+
+#[cfg(feature = "esp32c3")]
+use defmt::info;
+#[cfg(feature = "esp32c3")]
+use embassy_executor::task;
+#[cfg(feature = "esp32c3")]
+use embassy_time::{Instant, Timer};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "esp32c3")]
+use crate::{
+    altimeter,
+    kalman::{self, AltitudeFilter},
+    state::STATE,
+};
+
+/// Where the rocket is in its flight. Phases only ever move forwards, so they can be compared with `>=`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Phase {
+    #[default]
+    Pad,
+    Boost,   // Motor burning
+    Coast,   // Motor burnt out, still going up
+    Apogee,  // Top of the flight, only held for a single sample
+    Descent, // Coming down under drogue (or nothing)
+    Main,    // Below the main deployment altitude
+    Landed,
+}
+
+/// Parses the variant name, as written by `Debug`.
+impl FromStr for Phase {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "Pad" => Ok(Phase::Pad),
+            "Boost" => Ok(Phase::Boost),
+            "Coast" => Ok(Phase::Coast),
+            "Apogee" => Ok(Phase::Apogee),
+            "Descent" => Ok(Phase::Descent),
+            "Main" => Ok(Phase::Main),
+            "Landed" => Ok(Phase::Landed),
+            _ => Err(()),
+        }
+    }
+}
+
+pub struct Config {
+    pub launch_acceleration: f32, // Acceleration that counts as motor ignition, m/s^2
+    pub launch_altitude: f32,     // Height that counts as launched without an accelerometer, meters
+    pub burnout_acceleration: f32, // Acceleration below which the motor has burnt out, m/s^2
+    pub apogee_drop: f32,         // Drop below the highest altitude seen that calls apogee, meters
+    pub main_altitude: f32,       // Height above the pad to deploy the main at, meters
+    pub landed_tolerance: f32,    // Altitude band we need to stay in to count as landed, meters
+    pub landed_window_ms: u64,    // How long we need to stay in that band, milliseconds
+    pub debounce: u8,             // Consecutive samples a transition condition has to hold for
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            launch_acceleration: 20.0,
+            launch_altitude: 20.0,
+            burnout_acceleration: 0.0,
+            apogee_drop: 5.0,
+            main_altitude: 150.0,
+            landed_tolerance: 2.0,
+            landed_window_ms: 5_000,
+            debounce: 3,
+        }
+    }
+}
+
+pub struct Sample {
+    pub time_ms: u64,              // Boot-relative time of the sample, milliseconds
+    pub altitude: f32,             // Height above the pad, meters
+    pub velocity: Option<f32>,     // Vertical velocity, if something better knows it, m/s
+    pub acceleration: Option<f32>, // Vertical acceleration, gravity removed, m/s^2
+}
+
+/// Deterministic flight phase detector. Feed it samples in time order and it reports the current phase.
+pub struct Flight {
+    config: Config,
+    phase: Phase,
+    max_altitude: f32,
+    pending: u8,
+    last: Option<(u64, f32)>,
+    last_velocity: Option<f32>,
+    landed_reference: Option<(u64, f32)>,
+}
+
+impl Flight {
+    pub fn new(config: Config) -> Self {
+        Flight {
+            config,
+            phase: Phase::Pad,
+            max_altitude: f32::MIN,
+            pending: 0,
+            last: None,
+            last_velocity: None,
+            landed_reference: None,
+        }
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    pub fn max_altitude(&self) -> f32 {
+        self.max_altitude
+    }
+
+    pub fn update(&mut self, sample: &Sample) -> Phase {
+        let estimate = self.estimate_velocity(sample);
+        let velocity = sample.velocity.or(estimate);
+        self.max_altitude = self.max_altitude.max(sample.altitude);
+
+        let condition = match self.phase {
+            Phase::Pad => {
+                sample
+                    .acceleration
+                    .is_some_and(|a| a > self.config.launch_acceleration)
+                    || sample.altitude > self.config.launch_altitude
+            }
+            Phase::Boost => match (sample.acceleration, velocity, self.last_velocity) {
+                (Some(a), _, _) => a < self.config.burnout_acceleration,
+                // Without an accelerometer the best we can do is notice we've stopped speeding up
+                (None, Some(v), Some(last_v)) => v < last_v,
+                _ => false,
+            },
+            Phase::Coast => {
+                sample.altitude < self.max_altitude - self.config.apogee_drop
+                    || sample.velocity.is_some_and(|v| v <= 0.0)
+            }
+            Phase::Apogee => true,
+            Phase::Descent => sample.altitude < self.config.main_altitude,
+            Phase::Main => self.is_landed(sample),
+            Phase::Landed => false,
+        };
+
+        if velocity.is_some() {
+            self.last_velocity = velocity;
+        }
+
+        // Apogee is an event rather than a phase we sit in, so it doesn't need debouncing on the way out
+        let debounce = match self.phase {
+            Phase::Apogee => 1,
+            _ => self.config.debounce.max(1),
+        };
+
+        if condition {
+            self.pending += 1;
+            if self.pending >= debounce {
+                self.pending = 0;
+                self.phase = self.next_phase(sample);
+            }
+        } else {
+            self.pending = 0;
+        }
+
+        self.phase
+    }
+
+    fn next_phase(&mut self, sample: &Sample) -> Phase {
+        match self.phase {
+            Phase::Pad => Phase::Boost,
+            Phase::Boost => Phase::Coast,
+            Phase::Coast => Phase::Apogee,
+            // If apogee was low enough we might already be under the main altitude
+            Phase::Apogee if sample.altitude < self.config.main_altitude => Phase::Main,
+            Phase::Apogee => Phase::Descent,
+            Phase::Descent => Phase::Main,
+            Phase::Main | Phase::Landed => Phase::Landed,
+        }
+    }
+
+    fn estimate_velocity(&mut self, sample: &Sample) -> Option<f32> {
+        let velocity = match self.last {
+            Some((time_ms, altitude)) if sample.time_ms > time_ms => {
+                let dt = (sample.time_ms - time_ms) as f32 / 1000.0;
+                Some((sample.altitude - altitude) / dt)
+            }
+            _ => None,
+        };
+        self.last = Some((sample.time_ms, sample.altitude));
+        velocity
+    }
+
+    fn is_landed(&mut self, sample: &Sample) -> bool {
+        match self.landed_reference {
+            Some((time_ms, altitude))
+                if (sample.altitude - altitude).abs() <= self.config.landed_tolerance =>
+            {
+                sample.time_ms.saturating_sub(time_ms) >= self.config.landed_window_ms
+            }
+            _ => {
+                self.landed_reference = Some((sample.time_ms, sample.altitude));
+                false
+            }
+        }
+    }
+}
+
+#[cfg(feature = "esp32c3")]
+#[task]
+pub async fn track() -> ! {
+    let mut flight = Flight::new(Config::default());
+    let mut filter = AltitudeFilter::new(kalman::Config::default());
+    let mut last = Instant::now();
+
+    loop {
+        Timer::after_millis(50).await;
+
+        let now = Instant::now();
+        let dt = (now - last).as_micros() as f32 / 1_000_000.0;
+        last = now;
+
+        let mut state = STATE.lock().await;
+
+        // The altimeter's last reading only counts once, going over it again would make the filter too sure of it
+        filter.step(dt, altimeter::ALTITUDE.try_take(), state.va);
+
+        // Nothing to go on until the altimeter has a ground reference
+        if state.aar.is_none() {
+            continue;
+        }
+
+        state.fa = Some(filter.altitude());
+        state.vv = Some(filter.velocity());
+        state.ap = filter.apogee_detected();
+
+        let sample = Sample {
+            time_ms: now.as_millis(),
+            altitude: filter.altitude(),
+            velocity: Some(filter.velocity()),
+            acceleration: state.va,
+        };
+
+        let phase = flight.update(&sample);
+        if phase != state.fp {
+            info!("Flight phase {:?} -> {:?}", state.fp, phase);
+            state.fp = phase;
+        }
+    }
+}
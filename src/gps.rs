@@ -1,77 +1,249 @@
-use defmt::{error, info};
+use core::str::FromStr;
+
+#[cfg(feature = "esp32c3")]
+use defmt::{error, info, warn};
+#[cfg(feature = "esp32c3")]
 use embassy_executor::task;
-use embedded_io_async::Read;
+#[cfg(feature = "esp32c3")]
+use embassy_time::Timer;
+#[cfg(feature = "esp32c3")]
+use embedded_io_async::{Read, Write};
+#[cfg(feature = "esp32c3")]
 use esp_hal::{
-    uart::{AnyUart, UartRx},
+    uart::{self, AnyUart, ClockSource, Uart, UartRx, UartTx},
     Async,
 };
-use nmea0183::{datetime::Time, ParseResult, Parser, Sentence};
+use nmea0183::{
+    datetime::{Date, Time},
+    GPSQuality, ParseResult,
+};
+#[cfg(feature = "esp32c3")]
+use nmea0183::{Parser, Sentence};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "esp32c3")]
+use crate::{
+    gps::reader::{Event, Fault, Reader},
+    state::STATE,
+};
+use crate::{state::State, timesync::Utc};
+
+pub mod reader;
+pub mod ubx;
+
+const KNOTS_TO_MPS: f32 = 0.514_444;
 
-use crate::state::STATE;
+// The NEO-M8 powers up talking NMEA at 9600, which is too slow for more than one solution a second
+pub const DEFAULT_BAUD: u32 = 9600;
+pub const BAUD: u32 = 115_200;
 
-fn get_time(time: Time) -> i32 {
-    let h = (time.hours as i32) * 10000;
-    let m = (time.minutes as i32) * 100;
-    let s = time.seconds as i32;
-    h + m + s
+// 5Hz is as fast as the NEO-M8 goes while tracking more than one constellation
+pub const NAV_PERIOD_MS: u16 = 200;
+
+/// How much to trust the position, from the GGA fix quality or the NAV-PVT fix type. Only NAV-PVT tells a 2D fix
+/// apart, GGA has nothing for it so over NMEA a 2D fix still comes through as `Gps`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Fix {
+    #[default]
+    None,
+    Gps,       // Standalone GNSS
+    Gps2d,     // Standalone, but without a trustworthy altitude
+    Dgps,      // Differential or SBAS corrected
+    Rtk,       // RTK fixed or float
+    Estimated, // Dead reckoning, manual or simulated, not a real fix
 }
 
-#[task]
-pub async fn sample_uart(mut rx: UartRx<'static, Async, AnyUart>) -> ! {
-    // Apparently NMEA sentences are always 79 bytes long, but that doesn't seem to be true
-    // We'll just go 1 byte at a time, and let the parser deal with it.
-    let mut read_buffer: [u8; 1] = [0u8; 1];
+impl From<GPSQuality> for Fix {
+    fn from(quality: GPSQuality) -> Self {
+        match quality {
+            GPSQuality::NoFix => Fix::None,
+            GPSQuality::GPS | GPSQuality::PPS => Fix::Gps,
+            GPSQuality::DGPS => Fix::Dgps,
+            GPSQuality::RTK | GPSQuality::FRTK => Fix::Rtk,
+            GPSQuality::Estimated | GPSQuality::Manual | GPSQuality::Simulated => Fix::Estimated,
+        }
+    }
+}
 
-    // We only want GGA/GLL sentences parsed, which contain the main GPS info we need
-    let mut parser: Parser = Parser::new().sentence_filter(Sentence::GGA | Sentence::GLL);
+/// Parses the variant name, as written by `Debug`.
+impl FromStr for Fix {
+    type Err = ();
 
-    loop {
-        // Read one byte
-        let read_result = Read::read(&mut rx, &mut read_buffer).await;
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "None" => Ok(Fix::None),
+            "Gps" => Ok(Fix::Gps),
+            "Gps2d" => Ok(Fix::Gps2d),
+            "Dgps" => Ok(Fix::Dgps),
+            "Rtk" => Ok(Fix::Rtk),
+            "Estimated" => Ok(Fix::Estimated),
+            _ => Err(()),
+        }
+    }
+}
 
-        match read_result {
-            Ok(_) => {
-                // I think we don't need to do this?
-                read_result.unwrap();
-            }
-            Err(_) => {
-                // Should we empty the buffer here?
-                error!("read error");
-                continue;
-            }
+/// Milliseconds since UTC midnight.
+pub fn time_of_day(time: Time) -> u32 {
+    let h = time.hours as u32 * 3_600_000;
+    let m = time.minutes as u32 * 60_000;
+    let ms = (time.seconds * 1_000.0) as u32;
+    h + m + ms
+}
+
+/// Full UTC time from an RMC date and time, None if the date is nonsense.
+pub fn get_datetime(date: Date, time: Time) -> Option<Utc> {
+    // Two digit years are this century
+    let year = match date.year {
+        year if year < 100 => 2000 + year,
+        year => year,
+    };
+    Utc::from_date_time(year, date.month, date.day, time_of_day(time))
+}
+
+/// Folds a parsed sentence into `state`. Losing the fix keeps the last position, so it's still there to walk to,
+/// but clears the fix type, the satellites and HDOP it came with, and anything that only makes sense while moving.
+/// Only RMC carries the date, so the time from GGA and GLL is only taken once an RMC has set it. GGA can't tell a 2D
+/// fix from a 3D one, see `Fix`.
+pub fn update(state: &mut State, result: ParseResult) {
+    match result {
+        ParseResult::GGA(Some(gga)) => {
+            state.lt = Some(gga.latitude.as_f64() as f32);
+            state.ln = Some(gga.longitude.as_f64() as f32);
+            state.ga = Some(gga.altitude.meters);
+            state.t = state.t.map(|t| t.with_time_of_day(time_of_day(gga.time)));
+            state.fx = gga.gps_quality.into();
+            state.ns = Some(gga.sat_in_use);
+            state.hd = Some(gga.hdop);
+        }
+        ParseResult::GGA(None) => {
+            state.fx = Fix::None;
+            state.ns = None;
+            state.hd = None;
+        }
+        ParseResult::GLL(Some(gll)) => {
+            state.lt = Some(gll.latitude.as_f64() as f32);
+            state.ln = Some(gll.longitude.as_f64() as f32);
+            state.t = state.t.map(|t| t.with_time_of_day(time_of_day(gll.time)));
+        }
+        ParseResult::RMC(Some(rmc)) => {
+            state.lt = Some(rmc.latitude.as_f64() as f32);
+            state.ln = Some(rmc.longitude.as_f64() as f32);
+            state.t = get_datetime(rmc.datetime.date, rmc.datetime.time).or(state.t);
+            state.gs = Some(rmc.speed.as_knots() * KNOTS_TO_MPS);
+            state.gc = rmc.course.map(|course| course.degrees);
         }
+        ParseResult::VTG(Some(vtg)) => {
+            state.gs = Some(vtg.speed.as_knots() * KNOTS_TO_MPS);
+            state.gc = vtg.course.map(|course| course.degrees);
+        }
+        ParseResult::RMC(None) | ParseResult::VTG(None) => {
+            state.gs = None;
+            state.gc = None;
+        }
+        _ => {}
+    }
+}
 
-        let message = parser.parse_from_byte(read_buffer[0]);
+/// Asks the receiver to move to `BAUD`, talking UBX only, then follows it there. Nothing comes back at the old
+/// rate to say whether it worked, so if it didn't `sample_uart` just won't hear anything.
+#[cfg(feature = "esp32c3")]
+pub async fn set_baud(uart: &mut Uart<'static, Async, AnyUart>) {
+    let mut buf = [0u8; 32];
 
-        if message.is_none() {
-            continue;
+    if let Ok(frame) = ubx::encode(
+        ubx::CLASS_CFG,
+        ubx::ID_CFG_PRT,
+        &ubx::cfg_prt_uart(BAUD),
+        &mut buf,
+    ) {
+        if uart.write_all(frame).await.is_err() || uart.flush().await.is_err() {
+            error!("GPS baud rate change failed");
         }
+    }
 
-        match message.unwrap() {
-            Ok(ParseResult::GGA(Some(gps_result))) => {
-                info!("GGA Location result parsed.");
-                let mut state = STATE.lock().await;
-                state.lt = Some(gps_result.latitude.as_f64() as f32);
-                state.ln = Some(gps_result.longitude.as_f64() as f32);
-                state.ga = Some(gps_result.altitude.meters);
-                state.t = Some(get_time(gps_result.time));
-            }
-            Ok(ParseResult::GLL(Some(gps_result))) => {
-                info!("GLL location result parsed.");
-                let mut state = STATE.lock().await;
-                state.lt = Some(gps_result.latitude.as_f64() as f32);
-                state.ln = Some(gps_result.longitude.as_f64() as f32);
-                state.t = Some(get_time(gps_result.time));
-            }
-            Ok(ParseResult::GGA(None)) => {
-                // info!("Location result recieved, but with empty information");
-            }
-            Ok(_) => {
-                // error!("Some other result recieved from GPS\n");
+    // Give the receiver time to finish with the frame before it switches
+    Timer::after_millis(100).await;
+
+    uart.change_baud(BAUD, ClockSource::Apb);
+}
+
+// Frames and sends one CFG message, the ACK or NAK turns up later in `sample_uart`
+#[cfg(feature = "esp32c3")]
+async fn configure(tx: &mut UartTx<'static, Async, AnyUart>, id: u8, payload: &[u8]) {
+    let mut buf = [0u8; 48];
+
+    match ubx::encode(ubx::CLASS_CFG, id, payload, &mut buf) {
+        Ok(frame) => {
+            if tx.write_all(frame).await.is_err() {
+                error!("GPS config write failed");
             }
-            Err(e) => {
-                // error!("NMEA Parse Error: {:?}\n", e);
+        }
+        Err(e) => error!("GPS config doesn't fit: {}", e),
+    }
+}
+
+#[cfg(feature = "esp32c3")]
+#[task]
+pub async fn sample_uart(
+    mut rx: UartRx<'static, Async, AnyUart>,
+    mut tx: UartTx<'static, Async, AnyUart>,
+) -> ! {
+    configure(&mut tx, ubx::ID_CFG_RATE, &ubx::cfg_rate(NAV_PERIOD_MS)).await;
+    configure(&mut tx, ubx::ID_CFG_NAV5, &ubx::cfg_nav5_airborne()).await;
+    for id in [ubx::ID_NAV_PVT, ubx::ID_NAV_DOP] {
+        configure(
+            &mut tx,
+            ubx::ID_CFG_MSG,
+            &ubx::cfg_msg(ubx::CLASS_NAV, id, 1),
+        )
+        .await;
+    }
+
+    // There's no UART DMA on the C3, so this leans on the 128 byte FIFO, emptied a chunk at a time
+    let mut read_buffer = [0u8; 64];
+
+    // The receiver should only be sending UBX now, but NMEA is still understood in case it never took the config
+    let mut reader = Reader::new();
+    let mut nmea_parser: Parser = Parser::new()
+        .sentence_filter(Sentence::GGA | Sentence::GLL | Sentence::RMC | Sentence::VTG);
+    let mut errors = 0;
+
+    loop {
+        match Read::read(&mut rx, &mut read_buffer).await {
+            Ok(len) => {
+                for byte in &read_buffer[..len] {
+                    match reader.feed(*byte) {
+                        Some(Event::Frame(frame)) => match ubx::Message::decode(&frame) {
+                            ubx::Message::Ack { class, id } => {
+                                info!("GPS took config {:x}/{:x}", class, id)
+                            }
+                            ubx::Message::Nak { class, id } => {
+                                warn!("GPS rejected config {:x}/{:x}", class, id)
+                            }
+                            message => ubx::update(&mut *STATE.lock().await, &message),
+                        },
+                        Some(Event::Sentence(sentence)) => {
+                            // Already checked, so anything the parser turns down is just a sentence we don't use
+                            for result in nmea_parser.parse_from_bytes(sentence.as_bytes()) {
+                                if let Ok(result) = result {
+                                    update(&mut *STATE.lock().await, result);
+                                }
+                            }
+                        }
+                        None => {}
+                    }
+                }
             }
+            Err(uart::Error::RxFifoOvf) => reader.fault(Fault::Overrun),
+            Err(_) => reader.fault(Fault::Framing),
+        }
+
+        let stats = reader.stats();
+        if stats.errors() != errors {
+            errors = stats.errors();
+            warn!("GPS link errors: {}", stats);
+            STATE.lock().await.ge = errors.min(u16::MAX as u32) as u16;
         }
     }
 }
@@ -0,0 +1,165 @@
+// Splits the byte stream from the receiver into NMEA sentences and UBX frames, keeping count of what went wrong.
+//
+// NMEA sentences are only handed on once their checksum has been checked, and a sentence that's cut short, runs too
+// long or has binary in it is dropped rather than left to confuse whatever parses it next. UBX bytes can look like
+// anything, including `$`, so nothing is treated as NMEA while a UBX frame is in progress.
+use heapless::Vec;
+
+use crate::{gps::ubx, telemetry::nmea_checksum};
+
+// NMEA allows 82, with the `$` and line ending, but proprietary sentences aren't always so polite
+pub const MAX_SENTENCE_LEN: usize = 120;
+
+/// What the UART reported instead of bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Fault {
+    Framing, // Framing, parity or glitch errors, the line is noisy or at the wrong baud rate
+    Overrun, // The FIFO filled up before we got to it, so bytes are missing
+}
+
+/// Counts since boot.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Stats {
+    pub sentences: u32,       // NMEA sentences that passed their checksum
+    pub frames: u32,          // UBX frames that passed their checksum
+    pub checksum_errors: u32, // Either kind
+    pub framing_errors: u32,  // UART framing errors, and sentences cut short or too long
+    pub overruns: u32,
+}
+
+impl Stats {
+    pub fn errors(&self) -> u32 {
+        self.checksum_errors + self.framing_errors + self.overruns
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Event<'a> {
+    Sentence(&'a str), // Checked, and still with its line ending
+    Frame(ubx::Frame<'a>),
+}
+
+pub struct Reader {
+    ubx: ubx::Parser,
+    line: Vec<u8, MAX_SENTENCE_LEN>,
+    complete: bool, // `line` was handed out, so the next byte starts afresh
+    stats: Stats,
+}
+
+impl Default for Reader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reader {
+    pub const fn new() -> Self {
+        Reader {
+            ubx: ubx::Parser::new(),
+            line: Vec::new(),
+            complete: false,
+            stats: Stats {
+                sentences: 0,
+                frames: 0,
+                checksum_errors: 0,
+                framing_errors: 0,
+                overruns: 0,
+            },
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.stats
+    }
+
+    /// Counts a UART fault and throws away anything half read, since bytes around it are missing or wrong.
+    pub fn fault(&mut self, fault: Fault) {
+        match fault {
+            Fault::Framing => self.stats.framing_errors += 1,
+            Fault::Overrun => self.stats.overruns += 1,
+        }
+        self.reset();
+    }
+
+    pub fn reset(&mut self) {
+        self.ubx = ubx::Parser::new();
+        self.line.clear();
+        self.complete = false;
+    }
+
+    pub fn feed(&mut self, byte: u8) -> Option<Event<'_>> {
+        if self.complete {
+            self.line.clear();
+            self.complete = false;
+        }
+
+        // Binary in the middle of a sentence, most likely the start of a UBX frame that cut it short
+        if !self.line.is_empty() && !byte.is_ascii() {
+            self.stats.framing_errors += 1;
+            self.line.clear();
+        }
+
+        if self.ubx.in_frame() || (self.line.is_empty() && byte == ubx::SYNC_1) {
+            return match self.ubx.feed(byte) {
+                Some(Ok(frame)) => {
+                    self.stats.frames += 1;
+                    Some(Event::Frame(frame))
+                }
+                Some(Err(ubx::Error::BadChecksum)) => {
+                    self.stats.checksum_errors += 1;
+                    None
+                }
+                // Too long for us to bother with, rather than broken
+                Some(Err(_)) | None => None,
+            };
+        }
+
+        if byte == b'$' {
+            if !self.line.is_empty() {
+                self.stats.framing_errors += 1;
+                self.line.clear();
+            }
+        } else if self.line.is_empty() {
+            // Between sentences
+            return None;
+        }
+
+        if self.line.push(byte).is_err() {
+            self.stats.framing_errors += 1;
+            self.line.clear();
+            return None;
+        }
+
+        if byte != b'\n' {
+            return None;
+        }
+
+        if !checksum_ok(&self.line) {
+            self.stats.checksum_errors += 1;
+            self.line.clear();
+            return None;
+        }
+
+        self.stats.sentences += 1;
+        self.complete = true;
+
+        // Everything in the line is ASCII, so this can't fail
+        core::str::from_utf8(&self.line).ok().map(Event::Sentence)
+    }
+}
+
+// `$BODY*HH`, with the line ending. u-blox always sends the checksum, so a sentence without one is broken.
+fn checksum_ok(sentence: &[u8]) -> bool {
+    let Some((body, checksum)) = core::str::from_utf8(sentence)
+        .ok()
+        .map(str::trim_end)
+        .and_then(|s| s.strip_prefix('$'))
+        .and_then(|s| s.split_once('*'))
+    else {
+        return false;
+    };
+
+    checksum.len() == 2 && u8::from_str_radix(checksum, 16) == Ok(nmea_checksum(body))
+}
@@ -0,0 +1,381 @@
+// u-blox UBX binary protocol, see the u-blox 8 / M8 receiver description (UBX-13003221).
+//
+// Every frame looks like:
+//
+//   | sync | class | id | len (LE) | payload | ck_a | ck_b |
+//   |  2   |   1   | 1  |    2     |   len   |  1   |  1   |
+//
+// The checksum is an 8 bit Fletcher over everything from the class to the end of the payload.
+use crate::{gps::Fix, state::State, timesync::Utc};
+
+pub const SYNC_1: u8 = 0xB5;
+pub const SYNC_2: u8 = 0x62;
+pub const HEADER_LEN: usize = 6;
+pub const CHECKSUM_LEN: usize = 2;
+
+// Nothing we read is longer than a NAV-PVT
+pub const MAX_PAYLOAD_LEN: usize = 100;
+
+pub const CLASS_NAV: u8 = 0x01;
+pub const CLASS_ACK: u8 = 0x05;
+pub const CLASS_CFG: u8 = 0x06;
+
+pub const ID_NAV_DOP: u8 = 0x04;
+pub const ID_NAV_PVT: u8 = 0x07;
+pub const ID_ACK_NAK: u8 = 0x00;
+pub const ID_ACK_ACK: u8 = 0x01;
+pub const ID_CFG_PRT: u8 = 0x00;
+pub const ID_CFG_MSG: u8 = 0x01;
+pub const ID_CFG_RATE: u8 = 0x08;
+pub const ID_CFG_NAV5: u8 = 0x24;
+
+const NAV_PVT_LEN: usize = 92;
+const NAV_DOP_LEN: usize = 18;
+
+// NAV-PVT valid
+const VALID_DATE: u8 = 0x01;
+const VALID_TIME: u8 = 0x02;
+
+// NAV-PVT flags
+const GNSS_FIX_OK: u8 = 0x01;
+const DIFF_SOLN: u8 = 0x02;
+const CARR_SOLN: u8 = 0xC0;
+
+// CFG-PRT
+const PORT_UART1: u8 = 1;
+const MODE_8N1: u32 = 0x0000_08D0;
+const PROTO_UBX: u16 = 0x0001;
+const PROTO_NMEA: u16 = 0x0002;
+
+// CFG-NAV5
+const NAV5_MASK_DYN_MODEL: u16 = 0x0001;
+const DYN_MODEL_AIRBORNE_4G: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    BufferTooSmall,
+    TooLong(u16),
+    BadChecksum,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame<'a> {
+    pub class: u8,
+    pub id: u8,
+    pub payload: &'a [u8],
+}
+
+/// The parts of NAV-PVT we use, already scaled to SI units and degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NavPvt {
+    pub time: Option<Utc>,   // Once the receiver is sure of it
+    pub fix_type: u8,        // 0 none, 1 DR, 2 2D, 3 3D, 4 GNSS + DR, 5 time
+    pub flags: u8,           // gnssFixOK, diffSoln, carrSoln and friends
+    pub satellites: u8,      // Used in the solution
+    pub latitude: f32,       // Degrees
+    pub longitude: f32,      // Degrees
+    pub altitude: f32,       // Above mean sea level, meters
+    pub vertical_speed: f32, // Up is positive, m/s
+    pub ground_speed: f32,   // m/s
+    pub heading: f32,        // Heading of motion, degrees
+    pub position_dop: f32,   // Position dilution of precision
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Message {
+    NavPvt(NavPvt),
+    NavDop { horizontal: f32 },
+    Ack { class: u8, id: u8 },
+    Nak { class: u8, id: u8 },
+    Other { class: u8, id: u8 },
+}
+
+/// 8 bit Fletcher checksum, over the class, id, length and payload.
+pub fn checksum(data: &[u8]) -> [u8; 2] {
+    let (mut a, mut b) = (0u8, 0u8);
+    for byte in data {
+        a = a.wrapping_add(*byte);
+        b = b.wrapping_add(a);
+    }
+    [a, b]
+}
+
+/// Frames `payload` into `buf`, returning the slice that should be written to the receiver.
+pub fn encode<'a>(class: u8, id: u8, payload: &[u8], buf: &'a mut [u8]) -> Result<&'a [u8], Error> {
+    let len = HEADER_LEN + payload.len() + CHECKSUM_LEN;
+    if buf.len() < len {
+        return Err(Error::BufferTooSmall);
+    }
+
+    buf[0] = SYNC_1;
+    buf[1] = SYNC_2;
+    buf[2] = class;
+    buf[3] = id;
+    buf[4..6].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+    buf[HEADER_LEN..len - CHECKSUM_LEN].copy_from_slice(payload);
+
+    let ck = checksum(&buf[2..len - CHECKSUM_LEN]);
+    buf[len - CHECKSUM_LEN..len].copy_from_slice(&ck);
+
+    Ok(&buf[..len])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Expect {
+    Sync1,
+    Sync2,
+    Header,
+    Payload,
+    Checksum,
+}
+
+/// Byte at a time UBX frame parser. Anything that isn't UBX (NMEA included) is skipped over.
+pub struct Parser {
+    expect: Expect,
+    header: [u8; 4],
+    payload: [u8; MAX_PAYLOAD_LEN],
+    ck: [u8; 2],
+    index: usize,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Parser {
+            expect: Expect::Sync1,
+            header: [0; 4],
+            payload: [0; MAX_PAYLOAD_LEN],
+            ck: [0; 2],
+            index: 0,
+        }
+    }
+
+    /// True once a sync has been seen, until the frame ends or turns out not to be one.
+    pub fn in_frame(&self) -> bool {
+        self.expect != Expect::Sync1
+    }
+
+    pub fn feed(&mut self, byte: u8) -> Option<Result<Frame<'_>, Error>> {
+        match self.expect {
+            Expect::Sync1 => {
+                if byte == SYNC_1 {
+                    self.expect = Expect::Sync2;
+                }
+            }
+            Expect::Sync2 => {
+                self.expect = match byte {
+                    SYNC_2 => Expect::Header,
+                    SYNC_1 => Expect::Sync2,
+                    _ => Expect::Sync1,
+                };
+                self.index = 0;
+            }
+            Expect::Header => {
+                self.header[self.index] = byte;
+                self.index += 1;
+
+                if self.index == self.header.len() {
+                    let len = u16::from_le_bytes([self.header[2], self.header[3]]);
+                    self.index = 0;
+
+                    if len as usize > MAX_PAYLOAD_LEN {
+                        self.expect = Expect::Sync1;
+                        return Some(Err(Error::TooLong(len)));
+                    }
+
+                    self.expect = if len == 0 {
+                        Expect::Checksum
+                    } else {
+                        Expect::Payload
+                    };
+                }
+            }
+            Expect::Payload => {
+                self.payload[self.index] = byte;
+                self.index += 1;
+
+                if self.index == self.len() {
+                    self.index = 0;
+                    self.expect = Expect::Checksum;
+                }
+            }
+            Expect::Checksum => {
+                self.ck[self.index] = byte;
+                self.index += 1;
+
+                if self.index == CHECKSUM_LEN {
+                    self.expect = Expect::Sync1;
+                    return Some(self.finish());
+                }
+            }
+        }
+
+        None
+    }
+
+    fn len(&self) -> usize {
+        u16::from_le_bytes([self.header[2], self.header[3]]) as usize
+    }
+
+    fn finish(&self) -> Result<Frame<'_>, Error> {
+        let (mut a, mut b) = (0u8, 0u8);
+        for byte in self.header.iter().chain(&self.payload[..self.len()]) {
+            a = a.wrapping_add(*byte);
+            b = b.wrapping_add(a);
+        }
+
+        if [a, b] != self.ck {
+            return Err(Error::BadChecksum);
+        }
+
+        Ok(Frame {
+            class: self.header[0],
+            id: self.header[1],
+            payload: &self.payload[..self.len()],
+        })
+    }
+}
+
+impl Message {
+    pub fn decode(frame: &Frame) -> Self {
+        let p = frame.payload;
+
+        match (frame.class, frame.id, p.len()) {
+            (CLASS_NAV, ID_NAV_PVT, NAV_PVT_LEN) => Message::NavPvt(NavPvt {
+                time: time(p),
+                fix_type: p[20],
+                flags: p[21],
+                satellites: p[23],
+                longitude: (i32_at(p, 24) as f64 / 1e7) as f32,
+                latitude: (i32_at(p, 28) as f64 / 1e7) as f32,
+                altitude: i32_at(p, 36) as f32 / 1_000.0,
+                // NED, so down is positive
+                vertical_speed: -i32_at(p, 56) as f32 / 1_000.0,
+                ground_speed: i32_at(p, 60) as f32 / 1_000.0,
+                heading: i32_at(p, 64) as f32 / 1e5,
+                position_dop: u16::from_le_bytes([p[76], p[77]]) as f32 / 100.0,
+            }),
+            (CLASS_NAV, ID_NAV_DOP, NAV_DOP_LEN) => Message::NavDop {
+                horizontal: u16::from_le_bytes([p[12], p[13]]) as f32 / 100.0,
+            },
+            (CLASS_ACK, ID_ACK_ACK, 2) => Message::Ack {
+                class: p[0],
+                id: p[1],
+            },
+            (CLASS_ACK, ID_ACK_NAK, 2) => Message::Nak {
+                class: p[0],
+                id: p[1],
+            },
+            (class, id, _) => Message::Other { class, id },
+        }
+    }
+}
+
+// Whole seconds plus a signed nanosecond correction, which can take it back into the previous second
+fn time(p: &[u8]) -> Option<Utc> {
+    if p[11] & (VALID_DATE | VALID_TIME) != VALID_DATE | VALID_TIME {
+        return None;
+    }
+
+    let seconds = (p[8] as u32 * 60 + p[9] as u32) * 60 + p[10] as u32;
+    let utc = Utc::from_date_time(
+        u16::from_le_bytes([p[4], p[5]]),
+        p[6],
+        p[7],
+        seconds * 1_000,
+    )?;
+    Some(Utc(utc.0 + i32_at(p, 16) as i64 / 1_000_000))
+}
+
+fn i32_at(payload: &[u8], offset: usize) -> i32 {
+    i32::from_le_bytes([
+        payload[offset],
+        payload[offset + 1],
+        payload[offset + 2],
+        payload[offset + 3],
+    ])
+}
+
+impl NavPvt {
+    pub fn fix(&self) -> Fix {
+        if self.flags & GNSS_FIX_OK == 0 {
+            return Fix::None;
+        }
+
+        match self.fix_type {
+            1 => Fix::Estimated,
+            2 => Fix::Gps2d,
+            3 | 4 if self.flags & CARR_SOLN != 0 => Fix::Rtk,
+            3 | 4 if self.flags & DIFF_SOLN != 0 => Fix::Dgps,
+            3 | 4 => Fix::Gps,
+            _ => Fix::None,
+        }
+    }
+}
+
+/// Folds a decoded message into `state`. Like NMEA, losing the fix keeps the last position.
+pub fn update(state: &mut State, message: &Message) {
+    match message {
+        Message::NavPvt(pvt) => {
+            state.fx = pvt.fix();
+            state.ns = Some(pvt.satellites);
+
+            if state.fx == Fix::None {
+                state.gs = None;
+                state.gc = None;
+                return;
+            }
+
+            state.t = pvt.time.or(state.t);
+            state.lt = Some(pvt.latitude);
+            state.ln = Some(pvt.longitude);
+            state.ga = Some(pvt.altitude);
+            state.gs = Some(pvt.ground_speed);
+            state.gc = Some(pvt.heading);
+        }
+        Message::NavDop { horizontal } => state.hd = Some(*horizontal),
+        _ => {}
+    }
+}
+
+/// CFG-PRT payload putting UART1 on `baud`, taking UBX and NMEA in but only sending UBX out.
+pub fn cfg_prt_uart(baud: u32) -> [u8; 20] {
+    let mut payload = [0u8; 20];
+    payload[0] = PORT_UART1;
+    payload[4..8].copy_from_slice(&MODE_8N1.to_le_bytes());
+    payload[8..12].copy_from_slice(&baud.to_le_bytes());
+    payload[12..14].copy_from_slice(&(PROTO_UBX | PROTO_NMEA).to_le_bytes());
+    payload[14..16].copy_from_slice(&PROTO_UBX.to_le_bytes());
+    payload
+}
+
+/// CFG-RATE payload for a navigation solution every `period_ms`, aligned to GPS time.
+pub fn cfg_rate(period_ms: u16) -> [u8; 6] {
+    let mut payload = [0u8; 6];
+    payload[0..2].copy_from_slice(&period_ms.to_le_bytes());
+    payload[2..4].copy_from_slice(&1u16.to_le_bytes());
+    payload[4..6].copy_from_slice(&1u16.to_le_bytes());
+    payload
+}
+
+/// CFG-NAV5 payload for the airborne <4g dynamic model, leaving every other setting alone.
+/// The default portable model gives up on anything going faster than a car.
+pub fn cfg_nav5_airborne() -> [u8; 36] {
+    let mut payload = [0u8; 36];
+    payload[0..2].copy_from_slice(&NAV5_MASK_DYN_MODEL.to_le_bytes());
+    payload[2] = DYN_MODEL_AIRBORNE_4G;
+    payload
+}
+
+/// CFG-MSG payload sending `class`/`id` on the current port once every `rate` solutions, 0 turns it off.
+pub fn cfg_msg(class: u8, id: u8, rate: u8) -> [u8; 3] {
+    [class, id, rate]
+}
@@ -0,0 +1,92 @@
+#[cfg(feature = "esp32c3")]
+use bno055::{BNO055OperationMode, Bno055};
+#[cfg(feature = "esp32c3")]
+use defmt::{error, info};
+#[cfg(feature = "esp32c3")]
+use embassy_executor::task;
+#[cfg(feature = "esp32c3")]
+use embassy_time::{Delay, Timer};
+#[cfg(feature = "esp32c3")]
+use esp_hal::{i2c::master::I2c, Async};
+
+#[cfg(feature = "esp32c3")]
+use crate::state::STATE;
+
+/// Projects the linear acceleration onto the gravity vector, giving acceleration along the vertical, up positive.
+/// The BNO055 reports both in the sensor frame, with gravity pointing up (it's what the accelerometer feels at rest).
+pub fn vertical_acceleration(linear: [f32; 3], gravity: [f32; 3]) -> Option<f32> {
+    let g_squared = dot(gravity, gravity);
+
+    // No (or a nonsense) gravity estimate means the fusion hasn't settled yet
+    if g_squared < 1.0 {
+        return None;
+    }
+
+    Some(dot(linear, gravity) / sqrt(g_squared))
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+// Newton's method from a bit-hack first guess, micromath's sqrt is only good to a few percent
+fn sqrt(x: f32) -> f32 {
+    let mut y = f32::from_bits((x.to_bits() >> 1) + 0x1FC0_0000);
+    for _ in 0..3 {
+        y = 0.5 * (y + x / y);
+    }
+    y
+}
+
+#[cfg(feature = "esp32c3")]
+#[task]
+pub async fn sample_imu(i2c: I2c<'static, Async>) -> ! {
+    // Most BNO055 breakouts pull ADR low, which is what the driver calls the alternative address
+    let mut imu = Bno055::new(i2c).with_alternative_address();
+    let mut delay = Delay;
+
+    loop {
+        match imu.init(&mut delay).await {
+            Ok(_) => {}
+            Err(_) => {
+                error!("IMU init failed");
+                Timer::after_millis(1_000).await;
+                continue;
+            }
+        }
+
+        // NDOF runs the full 9 DoF fusion on the BNO055 itself, giving us gravity and orientation for free
+        match imu.set_mode(BNO055OperationMode::NDOF, &mut delay).await {
+            Ok(_) => break,
+            Err(_) => {
+                error!("IMU mode change failed");
+                Timer::after_millis(1_000).await;
+            }
+        }
+    }
+
+    info!("IMU initialized");
+
+    loop {
+        // The fusion output updates at 100Hz, no point asking more often than half that
+        Timer::after_millis(20).await;
+
+        let linear = imu.linear_acceleration().await;
+        let gravity = imu.gravity().await;
+        let quaternion = imu.quaternion().await;
+
+        let (Ok(linear), Ok(gravity), Ok(quaternion)) = (linear, gravity, quaternion) else {
+            error!("IMU read failed");
+            continue;
+        };
+
+        let linear = [linear.x, linear.y, linear.z];
+        let gravity = [gravity.x, gravity.y, gravity.z];
+
+        let mut state = STATE.lock().await;
+        state.la = Some(linear);
+        state.gv = Some(gravity);
+        state.q = Some([quaternion.s, quaternion.v.x, quaternion.v.y, quaternion.v.z]);
+        state.va = vertical_acceleration(linear, gravity);
+    }
+}
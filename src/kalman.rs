@@ -0,0 +1,184 @@
+// Altitude / vertical velocity / vertical acceleration Kalman filter, using a constant acceleration
+// model driven by white jerk. The barometer observes altitude and the IMU observes acceleration,
+// each as a scalar update, so either can be missing on any given step.
+
+const ALTITUDE: usize = 0;
+const ACCELERATION: usize = 2;
+
+pub struct Config {
+    pub altitude_variance: f32,     // Barometer noise, m^2
+    pub acceleration_variance: f32, // Accelerometer noise, (m/s^2)^2
+    pub jerk_variance: f32, // How hard the model is allowed to change acceleration, (m/s^3)^2
+    pub arm_velocity: f32,  // Upwards velocity we need to see before looking for apogee, m/s
+    pub apogee_samples: u8, // Consecutive non-positive velocity steps that make an apogee
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            altitude_variance: 1.0,
+            acceleration_variance: 0.25,
+            jerk_variance: 100.0,
+            arm_velocity: 20.0,
+            apogee_samples: 3,
+        }
+    }
+}
+
+pub struct AltitudeFilter {
+    config: Config,
+    x: [f32; 3],
+    p: [[f32; 3]; 3],
+    initialized: bool,
+    armed: bool,
+    descending: u8,
+    apogee: bool,
+}
+
+impl AltitudeFilter {
+    pub fn new(config: Config) -> Self {
+        AltitudeFilter {
+            config,
+            x: [0.0; 3],
+            p: [[0.0; 3]; 3],
+            initialized: false,
+            armed: false,
+            descending: 0,
+            apogee: false,
+        }
+    }
+
+    pub fn altitude(&self) -> f32 {
+        self.x[0]
+    }
+
+    pub fn velocity(&self) -> f32 {
+        self.x[1]
+    }
+
+    pub fn acceleration(&self) -> f32 {
+        self.x[2]
+    }
+
+    /// Latches once the filter has seen us going up fast enough and then stop going up.
+    pub fn apogee_detected(&self) -> bool {
+        self.apogee
+    }
+
+    /// Advances the filter by `dt` seconds, then folds in whichever measurements are available.
+    pub fn step(&mut self, dt: f32, altitude: Option<f32>, acceleration: Option<f32>) {
+        // Nothing to anchor the state to until the first altitude comes in
+        if !self.initialized {
+            let Some(altitude) = altitude else {
+                return;
+            };
+            self.x = [altitude, 0.0, acceleration.unwrap_or(0.0)];
+            self.p = [
+                [self.config.altitude_variance, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 0.0, self.config.acceleration_variance],
+            ];
+            self.initialized = true;
+            return;
+        }
+
+        self.predict(dt);
+
+        if let Some(altitude) = altitude {
+            self.update(ALTITUDE, altitude, self.config.altitude_variance);
+        }
+
+        if let Some(acceleration) = acceleration {
+            self.update(
+                ACCELERATION,
+                acceleration,
+                self.config.acceleration_variance,
+            );
+        }
+
+        self.detect_apogee();
+    }
+
+    fn predict(&mut self, dt: f32) {
+        let f = [[1.0, dt, 0.5 * dt * dt], [0.0, 1.0, dt], [0.0, 0.0, 1.0]];
+
+        let x = self.x;
+        for (i, row) in f.iter().enumerate() {
+            self.x[i] = row[0] * x[0] + row[1] * x[1] + row[2] * x[2];
+        }
+
+        // P = F P F^T + Q
+        let mut fp = [[0.0f32; 3]; 3];
+        for (i, row) in fp.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| f[i][k] * self.p[k][j]).sum();
+            }
+        }
+
+        let q = self.process_noise(dt);
+        for (i, row) in self.p.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| fp[i][k] * f[j][k]).sum::<f32>() + q[i][j];
+            }
+        }
+    }
+
+    fn process_noise(&self, dt: f32) -> [[f32; 3]; 3] {
+        let q = self.config.jerk_variance;
+        let dt2 = dt * dt;
+        let dt3 = dt2 * dt;
+        let dt4 = dt3 * dt;
+        let dt5 = dt4 * dt;
+
+        [
+            [q * dt5 / 20.0, q * dt4 / 8.0, q * dt3 / 6.0],
+            [q * dt4 / 8.0, q * dt3 / 3.0, q * dt2 / 2.0],
+            [q * dt3 / 6.0, q * dt2 / 2.0, q * dt],
+        ]
+    }
+
+    // Scalar measurement of a single state element
+    fn update(&mut self, index: usize, measurement: f32, variance: f32) {
+        let innovation = measurement - self.x[index];
+        let s = self.p[index][index] + variance;
+        let k = [
+            self.p[0][index] / s,
+            self.p[1][index] / s,
+            self.p[2][index] / s,
+        ];
+
+        for (i, gain) in k.iter().enumerate() {
+            self.x[i] += gain * innovation;
+        }
+
+        let row = self.p[index];
+        for (i, gain) in k.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                self.p[i][j] -= gain * value;
+            }
+        }
+    }
+
+    fn detect_apogee(&mut self) {
+        if self.apogee {
+            return;
+        }
+
+        if self.velocity() > self.config.arm_velocity {
+            self.armed = true;
+        }
+
+        if !self.armed {
+            return;
+        }
+
+        if self.velocity() <= 0.0 {
+            self.descending += 1;
+            if self.descending >= self.config.apogee_samples {
+                self.apogee = true;
+            }
+        } else {
+            self.descending = 0;
+        }
+    }
+}
@@ -2,8 +2,23 @@
 #![no_main]
 #![no_std]
 
+pub mod altimeter;
+pub mod auth;
+pub mod compact;
+pub mod flight;
 pub mod gps;
+pub mod imu;
+pub mod kalman;
+pub mod link;
+pub mod logger;
 pub mod lora;
+#[cfg(feature = "esp32c3")]
 pub mod pins;
+pub mod protocol;
+pub mod pyro;
+#[cfg(feature = "esp32c3")]
 pub mod spi;
 pub mod state;
+pub mod telemetry;
+pub mod timesync;
+pub mod uplink;
@@ -0,0 +1,141 @@
+// How well the ground station is hearing the vehicles, kept up to date by the receiver as packets come in.
+//
+// Sequence numbers are tracked for whichever vehicle was heard last. With more than one vehicle on the channel the
+// gaps between them aren't counted, only gaps in a run from the same vehicle.
+#[cfg(feature = "esp32c3")]
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use heapless::HistoryBuffer;
+
+use crate::protocol::{Error, Header};
+
+// How many packets the RSSI and SNR figures are taken over
+pub const HISTORY_LEN: usize = 16;
+
+/// Running counts since boot, plus signal strength over the last `HISTORY_LEN` packets.
+#[derive(Debug, Default)]
+pub struct LinkStats {
+    received: u32,
+    missed: u32,
+    corrupt: u32,
+    rejected: u32,
+    last: Option<Header>,
+    last_heard_ms: Option<u64>,
+    rssi: HistoryBuffer<i16, HISTORY_LEN>,
+    snr: HistoryBuffer<i16, HISTORY_LEN>,
+}
+
+/// What the stats looked like as a packet came in, to go out alongside it.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Summary {
+    pub received: u32,       // Packets decoded since boot
+    pub missed: u32,         // Gaps in the sequence numbers since boot
+    pub corrupt: u32,        // Packets that failed a CRC since boot
+    pub rejected: u32, // Packets that didn't decode for any other reason since boot, foreign ones included
+    pub rssi_avg: i16, // dBm
+    pub rssi_min: i16, // dBm
+    pub snr_avg: i16,  // dB
+    pub snr_min: i16,  // dB
+    pub gap_ms: Option<u32>, // Since the packet before, None for the first
+}
+
+impl Summary {
+    /// Fraction of the packets sent that never made it, between 0 and 1.
+    pub fn loss(&self) -> f32 {
+        match self.received + self.missed {
+            0 => 0.0,
+            sent => self.missed as f32 / sent as f32,
+        }
+    }
+}
+
+impl LinkStats {
+    pub const fn new() -> Self {
+        LinkStats {
+            received: 0,
+            missed: 0,
+            corrupt: 0,
+            rejected: 0,
+            last: None,
+            last_heard_ms: None,
+            rssi: HistoryBuffer::new(),
+            snr: HistoryBuffer::new(),
+        }
+    }
+
+    /// A packet from `header` decoded fine, heard at `now_ms` since boot.
+    pub fn record(&mut self, header: &Header, rssi: i16, snr: i16, now_ms: u64) -> Summary {
+        let gap_ms = self
+            .last_heard_ms
+            .map(|last| now_ms.saturating_sub(last).min(u32::MAX as u64) as u32);
+
+        if let Some(last) = self.last.filter(|last| last.vehicle == header.vehicle) {
+            match header.seq.wrapping_sub(last.seq) {
+                // A repeat of the last packet, it still tells us about the signal
+                0 => {}
+                gap if gap < 0x8000 => self.missed += (gap - 1) as u32,
+                // Going backwards means the vehicle rebooted, there's no telling what was lost
+                _ => {}
+            }
+        }
+
+        self.received += 1;
+        self.last = Some(*header);
+        self.last_heard_ms = Some(now_ms);
+        self.rssi.write(rssi);
+        self.snr.write(snr);
+
+        Summary {
+            gap_ms,
+            ..self.summary()
+        }
+    }
+
+    /// A packet came in but failed the radio's CRC.
+    pub fn corrupt(&mut self) {
+        self.corrupt += 1;
+    }
+
+    /// A packet came in but wouldn't decode, for the reason in `err`. One that's only waiting on its keyframe was
+    /// heard fine, and goes through `record` instead.
+    pub fn reject(&mut self, err: &Error) {
+        match err {
+            Error::BadCrc => self.corrupt += 1,
+            Error::NoKeyframe => {}
+            _ => self.rejected += 1,
+        }
+    }
+
+    pub fn summary(&self) -> Summary {
+        Summary {
+            received: self.received,
+            missed: self.missed,
+            corrupt: self.corrupt,
+            rejected: self.rejected,
+            rssi_avg: average(&self.rssi),
+            rssi_min: self.rssi.iter().copied().min().unwrap_or(0),
+            snr_avg: average(&self.snr),
+            snr_min: self.snr.iter().copied().min().unwrap_or(0),
+            gap_ms: None,
+        }
+    }
+
+    /// How long it's been since anything was heard, or None if nothing has been yet.
+    pub fn since_last_ms(&self, now_ms: u64) -> Option<u64> {
+        self.last_heard_ms.map(|last| now_ms.saturating_sub(last))
+    }
+}
+
+// Rounded to the nearest, halves going up
+fn average(samples: &[i16]) -> i16 {
+    if samples.is_empty() {
+        return 0;
+    }
+
+    let sum: i32 = samples.iter().map(|&sample| sample as i32).sum();
+    let len = samples.len() as i32;
+    (2 * sum + len).div_euclid(2 * len) as i16
+}
+
+#[cfg(feature = "esp32c3")]
+pub static LINK: Mutex<CriticalSectionRawMutex, LinkStats> = Mutex::new(LinkStats::new());
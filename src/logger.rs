@@ -0,0 +1,474 @@
+#[cfg(feature = "esp32c3")]
+use defmt::{error, info, warn};
+#[cfg(feature = "esp32c3")]
+use embassy_embedded_hal::shared_bus::asynch::spi::SpiDevice;
+#[cfg(feature = "esp32c3")]
+use embassy_executor::task;
+#[cfg(feature = "esp32c3")]
+use embassy_sync::{
+    blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex},
+    channel::Channel,
+};
+#[cfg(feature = "esp32c3")]
+use embassy_time::{Delay, Instant, Timer};
+use embedded_hal_async::{
+    delay::DelayNs,
+    spi::{Operation, SpiDevice as AsyncSpiDevice},
+};
+#[cfg(feature = "esp32c3")]
+use esp_hal::{
+    gpio::{AnyPin, Output},
+    spi::master::SpiDmaBus,
+    Async,
+};
+use heapless::{HistoryBuffer, Vec};
+use postcard::{from_bytes, to_slice};
+#[cfg(feature = "esp32c3")]
+use static_cell::StaticCell;
+
+#[cfg(feature = "esp32c3")]
+use crate::{flight::Phase, state::STATE};
+use crate::{
+    protocol::{crc16, Error},
+    state::State,
+};
+
+// Every record in flash looks like:
+//
+//   | len | time_ms (LE) | payload (postcard State) | crc16 (LE) |
+//   |  1  |      4       |           len            |     2      |
+//
+// The CRC covers everything from the length byte to the end of the payload. Erased flash reads 0xFF, so a
+// length byte of 0xFF marks the end of the log.
+pub const RECORD_HEADER_LEN: usize = 5;
+pub const RECORD_CRC_LEN: usize = 2;
+pub const MAX_RECORD_LEN: usize = 160;
+pub const ERASED: u8 = 0xFF;
+
+// How many records the RAM buffer keeps while we sit on the pad, at 10Hz that's the last few seconds
+pub const PRE_LAUNCH_RECORDS: usize = 32;
+
+// W25Qxx series SPI NOR flash commands, see the datasheet
+const CMD_WRITE_ENABLE: u8 = 0x06;
+const CMD_READ_STATUS: u8 = 0x05;
+const CMD_PAGE_PROGRAM: u8 = 0x02;
+const CMD_READ_DATA: u8 = 0x03;
+const CMD_CHIP_ERASE: u8 = 0xC7;
+const CMD_JEDEC_ID: u8 = 0x9F;
+
+const STATUS_BUSY: u8 = 0x01;
+pub const PAGE_SIZE: u32 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlashError<E> {
+    Spi(E),
+    UnknownChip,
+    Full,
+}
+
+/// Things that can be asked of the logger while it's running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    Erase, // Wipe the whole log, only honoured on the pad
+    Dump,  // Print every record in the log
+    Start, // Log from now on, rather than waiting for a launch
+}
+
+#[cfg(feature = "esp32c3")]
+pub static COMMANDS: Channel<CriticalSectionRawMutex, Command, 2> = Channel::new();
+
+/// Frames a timestamped `state` into `buf`, returning the slice that should be written to flash.
+pub fn encode_record<'a>(
+    time_ms: u32,
+    state: &State,
+    buf: &'a mut [u8],
+) -> Result<&'a [u8], Error> {
+    if buf.len() < RECORD_HEADER_LEN + RECORD_CRC_LEN {
+        return Err(Error::BufferTooSmall);
+    }
+
+    // 0xFF is taken by erased flash
+    let payload_space = (buf.len() - RECORD_HEADER_LEN - RECORD_CRC_LEN).min(ERASED as usize - 1);
+    let payload_len = to_slice(
+        state,
+        &mut buf[RECORD_HEADER_LEN..RECORD_HEADER_LEN + payload_space],
+    )
+    .map_err(|_| Error::Serialize)?
+    .len();
+
+    buf[0] = payload_len as u8;
+    buf[1..RECORD_HEADER_LEN].copy_from_slice(&time_ms.to_le_bytes());
+
+    let crc_start = RECORD_HEADER_LEN + payload_len;
+    let crc = crc16(&buf[..crc_start]).to_le_bytes();
+    buf[crc_start] = crc[0];
+    buf[crc_start + 1] = crc[1];
+
+    Ok(&buf[..crc_start + RECORD_CRC_LEN])
+}
+
+/// Full length of the record starting with `first`, or `None` if it's erased flash and the log ends here.
+pub fn record_len(first: u8) -> Option<usize> {
+    match first {
+        ERASED => None,
+        len => Some(RECORD_HEADER_LEN + len as usize + RECORD_CRC_LEN),
+    }
+}
+
+/// Validates and unpacks a record. `buf` must be exactly `record_len` bytes long.
+pub fn decode_record(buf: &[u8]) -> Result<(u32, State), Error> {
+    let len = record_len(*buf.first().ok_or(Error::Truncated)?).ok_or(Error::Truncated)?;
+    if buf.len() != len {
+        return Err(Error::LengthMismatch);
+    }
+
+    let crc_start = len - RECORD_CRC_LEN;
+    let crc = u16::from_le_bytes([buf[crc_start], buf[crc_start + 1]]);
+    if crc != crc16(&buf[..crc_start]) {
+        return Err(Error::BadCrc);
+    }
+
+    let time_ms = u32::from_le_bytes([buf[1], buf[2], buf[3], buf[4]]);
+    let state = from_bytes(&buf[RECORD_HEADER_LEN..crc_start]).map_err(|_| Error::Deserialize)?;
+
+    Ok((time_ms, state))
+}
+
+/// How many bytes can be programmed at `address` before running into the next page.
+pub fn page_remaining(address: u32) -> u32 {
+    PAGE_SIZE - address % PAGE_SIZE
+}
+
+/// The last few encoded records from the pad, so the log starts a little before launch
+/// without the whole idle period filling the flash.
+pub struct PreLaunch {
+    records: HistoryBuffer<Vec<u8, MAX_RECORD_LEN>, PRE_LAUNCH_RECORDS>,
+}
+
+impl Default for PreLaunch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PreLaunch {
+    pub const fn new() -> Self {
+        PreLaunch {
+            records: HistoryBuffer::new(),
+        }
+    }
+
+    /// Keeps `record`, dropping the oldest one if we're full.
+    pub fn push(&mut self, record: &[u8]) {
+        if let Ok(record) = Vec::from_slice(record) {
+            self.records.write(record);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.len() == 0
+    }
+
+    /// Oldest first, ready to be written out.
+    pub fn records(&self) -> impl Iterator<Item = &[u8]> {
+        self.records
+            .oldest_ordered()
+            .map(|record| record.as_slice())
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+}
+
+/// Winbond W25Qxx (and friends) SPI NOR flash.
+pub struct W25q<SPI> {
+    spi: SPI,
+    capacity: u32,
+}
+
+impl<SPI: AsyncSpiDevice> W25q<SPI> {
+    pub fn new(spi: SPI) -> Self {
+        W25q { spi, capacity: 0 }
+    }
+
+    /// Identifies the chip and works out its size, must be called before anything else.
+    pub async fn init(&mut self) -> Result<(), FlashError<SPI::Error>> {
+        let mut id = [0u8; 3];
+        self.spi
+            .transaction(&mut [Operation::Write(&[CMD_JEDEC_ID]), Operation::Read(&mut id)])
+            .await
+            .map_err(FlashError::Spi)?;
+
+        // The last ID byte is log2 of the size in bytes, anything silly means there's nothing there
+        match id[2] {
+            16..=28 => {
+                self.capacity = 1 << id[2];
+                Ok(())
+            }
+            _ => Err(FlashError::UnknownChip),
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    pub async fn read(
+        &mut self,
+        address: u32,
+        buf: &mut [u8],
+    ) -> Result<(), FlashError<SPI::Error>> {
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&command(CMD_READ_DATA, address)),
+                Operation::Read(buf),
+            ])
+            .await
+            .map_err(FlashError::Spi)
+    }
+
+    /// Programs `data` at `address`, which must already be erased. Splits the write at page boundaries.
+    pub async fn write(
+        &mut self,
+        mut address: u32,
+        mut data: &[u8],
+        delay: &mut impl DelayNs,
+    ) -> Result<(), FlashError<SPI::Error>> {
+        if address as usize + data.len() > self.capacity as usize {
+            return Err(FlashError::Full);
+        }
+
+        while !data.is_empty() {
+            let (chunk, rest) = data.split_at(data.len().min(page_remaining(address) as usize));
+
+            self.write_enable().await?;
+            self.spi
+                .transaction(&mut [
+                    Operation::Write(&command(CMD_PAGE_PROGRAM, address)),
+                    Operation::Write(chunk),
+                ])
+                .await
+                .map_err(FlashError::Spi)?;
+
+            // Page programs take under a millisecond
+            self.wait_idle(delay, 100).await?;
+
+            address += chunk.len() as u32;
+            data = rest;
+        }
+
+        Ok(())
+    }
+
+    /// Erases the whole chip, which can take minutes on the bigger parts.
+    pub async fn erase(&mut self, delay: &mut impl DelayNs) -> Result<(), FlashError<SPI::Error>> {
+        self.write_enable().await?;
+        self.spi
+            .write(&[CMD_CHIP_ERASE])
+            .await
+            .map_err(FlashError::Spi)?;
+        self.wait_idle(delay, 100_000).await
+    }
+
+    async fn write_enable(&mut self) -> Result<(), FlashError<SPI::Error>> {
+        self.spi
+            .write(&[CMD_WRITE_ENABLE])
+            .await
+            .map_err(FlashError::Spi)
+    }
+
+    async fn wait_idle(
+        &mut self,
+        delay: &mut impl DelayNs,
+        poll_us: u32,
+    ) -> Result<(), FlashError<SPI::Error>> {
+        loop {
+            let mut status = [0u8; 1];
+            self.spi
+                .transaction(&mut [
+                    Operation::Write(&[CMD_READ_STATUS]),
+                    Operation::Read(&mut status),
+                ])
+                .await
+                .map_err(FlashError::Spi)?;
+
+            if status[0] & STATUS_BUSY == 0 {
+                return Ok(());
+            }
+
+            delay.delay_us(poll_us).await;
+        }
+    }
+}
+
+fn command(command: u8, address: u32) -> [u8; 4] {
+    let address = address.to_be_bytes();
+    [command, address[1], address[2], address[3]]
+}
+
+/// Walks the log from the start, handing each record to `f`, and returns where the next record should go.
+pub async fn scan<SPI: AsyncSpiDevice>(
+    flash: &mut W25q<SPI>,
+    mut f: impl FnMut(u32, Result<(u32, State), Error>),
+) -> Result<u32, FlashError<SPI::Error>> {
+    let mut address = 0;
+    let mut buf = [0u8; MAX_RECORD_LEN];
+
+    while address < flash.capacity() {
+        flash.read(address, &mut buf[..1]).await?;
+        let Some(len) = record_len(buf[0]) else {
+            break;
+        };
+
+        if address as usize + len > flash.capacity() as usize {
+            break;
+        }
+
+        // Torn or corrupted, and longer than any record could be, so there's no telling where the next one starts.
+        // Pick up again on the next page, past the end of the log that's still erased.
+        if len > MAX_RECORD_LEN {
+            f(address, Err(Error::LengthMismatch));
+            address += page_remaining(address);
+            continue;
+        }
+
+        flash.read(address, &mut buf[..len]).await?;
+        f(address, decode_record(&buf[..len]));
+        address += len as u32;
+    }
+
+    Ok(address)
+}
+
+#[cfg(feature = "esp32c3")]
+static PRE_LAUNCH: StaticCell<PreLaunch> = StaticCell::new();
+
+#[cfg(feature = "esp32c3")]
+#[task]
+pub async fn record(
+    spi: SpiDevice<'static, NoopRawMutex, SpiDmaBus<'static, Async>, Output<'static, AnyPin>>,
+) -> ! {
+    let mut flash = W25q::new(spi);
+    let mut delay = Delay;
+    let pre_launch = PRE_LAUNCH.init(PreLaunch::new());
+
+    while flash.init().await.is_err() {
+        error!("Flash init failed");
+        Timer::after_millis(1_000).await;
+    }
+
+    // Pick up where the last boot left off
+    let mut address = loop {
+        match scan(&mut flash, |_, _| {}).await {
+            Ok(address) => break address,
+            Err(_) => {
+                error!("Flash scan failed");
+                Timer::after_millis(1_000).await;
+            }
+        }
+    };
+
+    info!("Flash log at {} of {} bytes", address, flash.capacity());
+
+    let mut buf = [0u8; MAX_RECORD_LEN];
+    let mut full = false;
+    let mut started = false;
+
+    loop {
+        // The SPI bus is shared with the radio at a crawl, so 10Hz is about as much as we can write
+        Timer::after_millis(100).await;
+
+        let (phase, record) = {
+            let mut state = STATE.lock().await;
+            state.bt = Instant::now().as_millis();
+            (state.fp, encode_record(state.bt as u32, &state, &mut buf))
+        };
+
+        if let Ok(command) = COMMANDS.try_receive() {
+            match command {
+                Command::Erase if phase == Phase::Pad => {
+                    info!("Erasing flash log");
+                    match flash.erase(&mut delay).await {
+                        Ok(()) => {
+                            address = 0;
+                            full = false;
+                            started = false;
+                            pre_launch.clear();
+                            info!("Flash log erased");
+                        }
+                        Err(_) => error!("Flash erase failed"),
+                    }
+                }
+                Command::Erase => warn!("Not erasing the flash log in flight"),
+                Command::Dump => {
+                    let dumped = scan(&mut flash, |address, record| match record {
+                        Ok((time_ms, state)) => info!("{} {}: {:?}", address, time_ms, state),
+                        Err(e) => error!("{}: bad record {:?}", address, e),
+                    });
+                    if dumped.await.is_err() {
+                        error!("Flash dump failed");
+                    }
+                }
+                Command::Start => {
+                    info!("Logging to flash from now on");
+                    started = true;
+                }
+            }
+            continue;
+        }
+
+        let Ok(record) = record else {
+            error!("Failed to encode log record");
+            continue;
+        };
+
+        if phase == Phase::Pad && !started {
+            pre_launch.push(record);
+            continue;
+        }
+
+        if full {
+            continue;
+        }
+
+        // First record after launch (or being told to start), get the last few seconds on the pad down first
+        if !pre_launch.is_empty() {
+            for pad_record in pre_launch.records() {
+                full |= !append(&mut flash, &mut address, pad_record, &mut delay).await;
+            }
+            pre_launch.clear();
+        }
+
+        full |= !append(&mut flash, &mut address, record, &mut delay).await;
+    }
+}
+
+// Writes a record at the end of the log, returning false once the flash is full
+#[cfg(feature = "esp32c3")]
+async fn append(
+    flash: &mut W25q<
+        SpiDevice<'static, NoopRawMutex, SpiDmaBus<'static, Async>, Output<'static, AnyPin>>,
+    >,
+    address: &mut u32,
+    record: &[u8],
+    delay: &mut Delay,
+) -> bool {
+    match flash.write(*address, record, delay).await {
+        Ok(()) => {}
+        Err(FlashError::Full) => {
+            warn!("Flash log full");
+            return false;
+        }
+        // Whatever made it into flash is there to stay, skip over it and let the CRC catch it on the way out
+        Err(_) => error!("Flash write failed"),
+    }
+
+    *address += record.len() as u32;
+    true
+}
@@ -1,67 +1,634 @@
-use defmt::{error, info};
+// Radio link, and the modulation profiles it switches between as the flight goes on.
+//
+// Each profile trades range for rate. The transmitter picks the profile for its next packet from the phase in the
+// last one it sent, so a receiver that heard that packet knows where to listen next. A receiver that hears nothing
+// for a while (missed the change, or the transmitter was told to use something else) hunts through the profiles. In
+// flight it keeps coming back to the flight's own profile, and gives the others no longer than that one would take.
+//
+// Where on the dial, how loud and how wide is a `RadioConfig`, checked against the rules for the region we're
+// flying in and kept in the ESP32's own flash so it survives a reboot. Both ends need the same one.
+//
+// Optionally the transmitter follows every few telemetry frames with parity frames, so the receiver can rebuild
+// ones it missed, see `fec`. How often it sends within each profile is down to a policy, see `rate`.
+use core::str::FromStr;
+
+#[cfg(feature = "esp32c3")]
+use defmt::{error, info, warn};
+#[cfg(feature = "esp32c3")]
 use embassy_embedded_hal::shared_bus::asynch::spi::SpiDevice;
+#[cfg(feature = "esp32c3")]
 use embassy_executor::task;
-use embassy_sync::blocking_mutex::raw::NoopRawMutex;
-use embassy_time::{with_timeout, Delay, Duration, Timer};
+#[cfg(feature = "esp32c3")]
+use embassy_sync::{
+    blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex},
+    channel::Channel,
+};
+#[cfg(feature = "esp32c3")]
+use embassy_time::{with_timeout, Delay, Duration, Instant, Timer};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+#[cfg(feature = "esp32c3")]
 use esp_hal::{
     gpio::{AnyPin, Input, Output},
     spi::master::SpiDmaBus,
     Async,
 };
+#[cfg(feature = "esp32c3")]
+use esp_storage::FlashStorage;
+#[cfg(feature = "esp32c3")]
 use lora_phy::{
     iv::GenericSx127xInterfaceVariant,
-    mod_params::{Bandwidth, CodingRate, ModulationParams, SpreadingFactor},
+    mod_params::{Bandwidth, CodingRate, ModulationParams, RadioError, SpreadingFactor},
     mod_traits::RadioKind,
     sx127x::{self, Sx127x},
     DelayNs, LoRa, RxMode,
 };
+
 use postcard::{from_bytes, to_slice};
+use serde::{Deserialize, Serialize};
 
-use crate::state::{State, STATE};
+#[cfg(feature = "esp32c3")]
+use crate::{
+    auth::{self, Key},
+    compact,
+    link::LINK,
+    protocol::{self, Header},
+    state::STATE,
+    telemetry::{self, Packet},
+    uplink::{self, Inbox, Reject, Uplink},
+};
+use crate::{
+    flight::Phase,
+    protocol::{crc16, Error, MAX_FRAME_LEN},
+};
 
-const LORA_FREQUENCY_IN_HZ: u32 = 433_000_000;
+pub mod fec;
+pub mod rate;
+
+#[cfg(feature = "esp32c3")]
+use fec::{Encoder, Scheme};
+#[cfg(feature = "esp32c3")]
+use rate::{Cadence, Policy};
+
+// How often the transmitter looks in on the flight phase while it waits for the next slot
+#[cfg(feature = "esp32c3")]
+const PHASE_POLL_MS: u64 = 50;
+
+#[cfg(feature = "esp32c3")]
 const LORA_MAX_PACKET_SIZE_BYTES: usize = 255;
 
+// Both ends have to agree on this, along with everything in `Modulation`
+pub const PREAMBLE_LEN: u16 = 16;
+
+// Symbols longer than this need the low data rate optimisation, which lora-phy turns on by itself
+const LOW_DATA_RATE_SYMBOL_US: u32 = 16_000;
+
+// What the SX127x PA_BOOST output can do, whatever the region allows
+pub const MIN_POWER_DBM: i8 = 2;
+pub const MAX_POWER_DBM: i8 = 20;
+
+// Bandwidths we know how to ask lora-phy for
+pub const BANDWIDTHS_HZ: [u32; 4] = [62_500, 125_000, 250_000, 500_000];
+
+// A saved config in the ESP32's flash looks like:
+//
+//   | magic | len | payload (postcard RadioConfig) | crc16 (LE) | erased ... |
+//   |   1   |  1  |              len               |     2      |            |
+//
+// The CRC covers everything from the magic byte to the end of the payload. The whole record is read and written
+// at once, padded out to a size the flash driver is happy with.
+pub const CONFIG_MAGIC: u8 = 0x52;
+pub const CONFIG_RECORD_LEN: usize = 32;
+
+// First sector of the NVS partition in espflash's default partition table, which nothing else here uses
+pub const CONFIG_ADDRESS: u32 = 0x9000;
+
+/// What goes on air. Coding rate is the denominator of 4/5 to 4/8.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Modulation {
+    pub spreading_factor: u8, // 7 to 12
+    pub bandwidth_hz: u32,
+    pub coding_rate: u8, // 5 to 8
+}
+
+impl Modulation {
+    pub fn symbol_us(&self) -> u32 {
+        ((1u64 << self.spreading_factor) * 1_000_000 / self.bandwidth_hz as u64) as u32
+    }
+
+    pub fn low_data_rate_optimize(&self) -> bool {
+        self.symbol_us() > LOW_DATA_RATE_SYMBOL_US
+    }
+
+    /// Raw bit rate, before the header, preamble and CRC.
+    pub fn bitrate(&self) -> u32 {
+        let sf = self.spreading_factor as u64;
+        (sf * self.bandwidth_hz as u64 * 4 / ((1 << sf) * self.coding_rate as u64)) as u32
+    }
+
+    /// Time on air for a packet of `payload_len` bytes with an explicit header and CRC, as in the SX127x datasheet.
+    pub fn airtime_us(&self, payload_len: usize) -> u32 {
+        let sf = self.spreading_factor as i64;
+        let de = self.low_data_rate_optimize() as i64;
+
+        // 8 symbols, then as many blocks of coded symbols as the payload, CRC and header need
+        let bits = 8 * payload_len as i64 - 4 * sf + 28 + 16;
+        let per_block = 4 * (sf - 2 * de);
+        let blocks = if bits > 0 {
+            (bits + per_block - 1) / per_block
+        } else {
+            0
+        };
+        let payload_symbols = 8 + blocks * self.coding_rate as i64;
+
+        // The preamble is however many symbols we ask for, plus 4.25 for the sync word
+        let quarter_symbols = 4 * (PREAMBLE_LEN as i64 + payload_symbols) + 17;
+
+        (quarter_symbols * self.symbol_us() as i64 / 4) as u32
+    }
+}
+
+/// Named link settings, from slow and far reaching to fast and short.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Profile {
+    // Sitting on the pad, or range testing before the flight
+    #[default]
+    LongRange,
+    Ascent, // Fast updates while things are happening quickly
+    Beacon, // Low and slow, for finding it on the ground
+}
+
+impl Profile {
+    pub const ALL: [Profile; 3] = [Profile::LongRange, Profile::Ascent, Profile::Beacon];
+
+    pub fn modulation(self) -> Modulation {
+        match self {
+            Profile::LongRange => Modulation {
+                spreading_factor: 10,
+                bandwidth_hz: 125_000,
+                coding_rate: 8,
+            },
+            Profile::Ascent => Modulation {
+                spreading_factor: 7,
+                bandwidth_hz: 125_000,
+                coding_rate: 5,
+            },
+            Profile::Beacon => Modulation {
+                spreading_factor: 11,
+                bandwidth_hz: 125_000,
+                coding_rate: 8,
+            },
+        }
+    }
+
+    /// The longest the transmitter goes between packets at 125 kHz, leaving the channel quiet well over half the
+    /// time. A narrower channel stretches it, see `RadioConfig::interval_ms`.
+    pub fn interval_ms(self) -> u32 {
+        match self {
+            Profile::LongRange => 5_000,
+            Profile::Ascent => 500,
+            Profile::Beacon => 10_000,
+        }
+    }
+
+    /// Where the flight phase says we should be.
+    pub fn for_phase(phase: Phase) -> Self {
+        match phase {
+            Phase::Pad => Profile::LongRange,
+            Phase::Boost | Phase::Coast | Phase::Apogee | Phase::Descent => Profile::Ascent,
+            Phase::Main | Phase::Landed => Profile::Beacon,
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            Profile::LongRange => Profile::Ascent,
+            Profile::Ascent => Profile::Beacon,
+            Profile::Beacon => Profile::LongRange,
+        }
+    }
+}
+
+/// Parses the variant name, as written by `Debug`.
+impl FromStr for Profile {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "LongRange" => Ok(Profile::LongRange),
+            "Ascent" => Ok(Profile::Ascent),
+            "Beacon" => Ok(Profile::Beacon),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Which profile to use next, shared by both ends of the link.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Schedule {
+    current: Profile,
+    forced: Option<Profile>,
+    phase: Option<Phase>,
+    away: Profile, // Where a hunt in flight last went, other than back to the flight's own profile
+}
+
+impl Schedule {
+    pub const fn new() -> Self {
+        Schedule {
+            current: Profile::LongRange,
+            forced: None,
+            phase: None,
+            away: Profile::LongRange,
+        }
+    }
+
+    pub fn profile(&self) -> Profile {
+        self.forced.unwrap_or(self.current)
+    }
+
+    /// Pins the profile until it's released with `None`, whatever the phase.
+    pub fn force(&mut self, profile: Option<Profile>) {
+        self.forced = profile;
+    }
+
+    /// After sending, or hearing, a packet from a vehicle in `phase`.
+    pub fn follow(&mut self, phase: Phase) -> Profile {
+        self.current = Profile::for_phase(phase);
+        self.phase = Some(phase);
+        self.profile()
+    }
+
+    /// How long to listen on `profile` before hunting. While the vehicle was last heard on its way up or down, the
+    /// other profiles only get as long as it takes to hear the flight's own, or missing a couple of packets could cost
+    /// most of the flight waiting out the slow ones.
+    pub fn listen_ms(&self, config: &RadioConfig) -> u32 {
+        let profile = self.profile();
+        match self.flying() {
+            Some(flying) => config
+                .listen_ms(profile)
+                .min(config.listen_for_ms(profile, config.interval_ms(flying))),
+            None => config.listen_ms(profile),
+        }
+    }
+
+    /// After listening for a whole `listen_ms` and hearing nothing. In flight the vehicle's most likely still where
+    /// it was, so the hunt goes back there between each of the others.
+    pub fn hunt(&mut self) -> Profile {
+        match self.flying() {
+            Some(flying) if self.current != flying => self.current = flying,
+            Some(flying) => {
+                self.away = self.away.next();
+                if self.away == flying {
+                    self.away = self.away.next();
+                }
+                self.current = self.away;
+            }
+            None if self.forced.is_none() => self.current = self.current.next(),
+            None => {}
+        }
+        self.profile()
+    }
+
+    // The profile for the flight, if that's where the vehicle was last heard and nothing's forced
+    fn flying(&self) -> Option<Profile> {
+        self.phase
+            .filter(|phase| {
+                (Phase::Boost..=Phase::Descent).contains(phase) && self.forced.is_none()
+            })
+            .map(Profile::for_phase)
+    }
+}
+
+/// Where we're allowed to transmit, and how hard. The SX1278 on our boards only covers the 433 MHz regions, the
+/// others need an SX1276.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Region {
+    // The 70cm amateur band, for a licensed operator
+    #[default]
+    Amateur433,
+    Ism433, // Licence free 433 MHz ISM band, 10 mW
+    Eu868,  // EU 863-870 MHz SRD band, 25 mW
+    Us915,  // US 902-928 MHz ISM band
+}
+
+impl Region {
+    pub const ALL: [Region; 4] = [
+        Region::Amateur433,
+        Region::Ism433,
+        Region::Eu868,
+        Region::Us915,
+    ];
+
+    /// Lowest and highest frequency the whole channel has to fit between.
+    pub fn band_hz(self) -> (u32, u32) {
+        match self {
+            Region::Amateur433 => (430_000_000, 440_000_000),
+            Region::Ism433 => (433_050_000, 434_790_000),
+            Region::Eu868 => (863_000_000, 870_000_000),
+            Region::Us915 => (902_000_000, 928_000_000),
+        }
+    }
+
+    pub fn max_power_dbm(self) -> i8 {
+        match self {
+            Region::Amateur433 | Region::Us915 => MAX_POWER_DBM,
+            Region::Ism433 => 10,
+            Region::Eu868 => 14,
+        }
+    }
+
+    pub fn max_bandwidth_hz(self) -> u32 {
+        match self {
+            Region::Amateur433 | Region::Us915 => 500_000,
+            Region::Ism433 | Region::Eu868 => 250_000,
+        }
+    }
+
+    /// A channel that's legal at full power.
+    pub fn default_config(self) -> RadioConfig {
+        let frequency_hz = match self {
+            Region::Amateur433 => 433_000_000,
+            Region::Ism433 => 433_920_000,
+            Region::Eu868 => 868_100_000,
+            Region::Us915 => 915_000_000,
+        };
+
+        RadioConfig {
+            region: self,
+            frequency_hz,
+            power_dbm: self.max_power_dbm(),
+            bandwidth_hz: 125_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigError {
+    Frequency, // Some of the channel is outside the region's band
+    Power,     // More than the region allows, or the radio can do
+    Bandwidth, // Wider than the region allows, or not one we can ask for
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SaveError<E> {
+    Encode(Error),
+    Flash(E),
+}
+
+/// Channel and power for the link, the profiles decide everything else.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RadioConfig {
+    pub region: Region,
+    pub frequency_hz: u32, // Centre of the channel
+    pub power_dbm: i8,
+    pub bandwidth_hz: u32, // Used by every profile
+}
+
+impl Default for RadioConfig {
+    fn default() -> Self {
+        Region::default().default_config()
+    }
+}
+
+impl RadioConfig {
+    pub fn new(
+        region: Region,
+        frequency_hz: u32,
+        power_dbm: i8,
+        bandwidth_hz: u32,
+    ) -> Result<Self, ConfigError> {
+        let config = RadioConfig {
+            region,
+            frequency_hz,
+            power_dbm,
+            bandwidth_hz,
+        };
+        config.validate().map(|()| config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let region = self.region;
+
+        if !BANDWIDTHS_HZ.contains(&self.bandwidth_hz)
+            || self.bandwidth_hz > region.max_bandwidth_hz()
+        {
+            return Err(ConfigError::Bandwidth);
+        }
+
+        let (low, high) = region.band_hz();
+        let half = self.bandwidth_hz / 2;
+        if self.frequency_hz < low + half || self.frequency_hz > high - half {
+            return Err(ConfigError::Frequency);
+        }
+
+        if self.power_dbm < MIN_POWER_DBM || self.power_dbm > region.max_power_dbm() {
+            return Err(ConfigError::Power);
+        }
+
+        Ok(())
+    }
+
+    /// What `profile` looks like on this channel.
+    pub fn modulation(&self, profile: Profile) -> Modulation {
+        Modulation {
+            bandwidth_hz: self.bandwidth_hz,
+            ..profile.modulation()
+        }
+    }
+
+    /// `Profile::interval_ms`, stretched as far as a narrower channel stretches the packets, so it's still as quiet.
+    /// A wider one keeps the same pace. It can go up to twice as often, see `rate`.
+    pub fn interval_ms(&self, profile: Profile) -> u32 {
+        let at_125k = profile.modulation().airtime_us(MAX_FRAME_LEN) as u64;
+        let here = self.modulation(profile).airtime_us(MAX_FRAME_LEN) as u64;
+        (profile.interval_ms() as u64 * here.max(at_125k) / at_125k) as u32
+    }
+
+    /// How long a receiver waits for a packet on `profile` before it decides it's listening in the wrong place.
+    pub fn listen_ms(&self, profile: Profile) -> u32 {
+        self.listen_for_ms(profile, self.interval_ms(profile))
+    }
+
+    // Long enough to miss a couple of packets `interval_ms` apart, and still hear the next one out
+    fn listen_for_ms(&self, profile: Profile, interval_ms: u32) -> u32 {
+        let airtime_ms = self.modulation(profile).airtime_us(MAX_FRAME_LEN) / 1_000;
+        3 * interval_ms + airtime_ms
+    }
+
+    /// Frames the config into a record for flash, padded with erased bytes.
+    pub fn encode(&self) -> Result<[u8; CONFIG_RECORD_LEN], Error> {
+        let mut buf = [0xFF; CONFIG_RECORD_LEN];
+
+        let payload_len = to_slice(self, &mut buf[2..CONFIG_RECORD_LEN - 2])
+            .map_err(|_| Error::Serialize)?
+            .len();
+
+        buf[0] = CONFIG_MAGIC;
+        buf[1] = payload_len as u8;
+
+        let crc_start = 2 + payload_len;
+        let crc = crc16(&buf[..crc_start]).to_le_bytes();
+        buf[crc_start] = crc[0];
+        buf[crc_start + 1] = crc[1];
+
+        Ok(buf)
+    }
+
+    /// Unpacks a record written by `encode`. Something that fails validation is as bad as a broken one, the rules
+    /// may have changed since it was saved.
+    pub fn decode(buf: &[u8; CONFIG_RECORD_LEN]) -> Result<Self, Error> {
+        if buf[0] != CONFIG_MAGIC {
+            return Err(Error::BadMagic(buf[0]));
+        }
+
+        let crc_start = 2 + buf[1] as usize;
+        if crc_start + 2 > CONFIG_RECORD_LEN {
+            return Err(Error::LengthMismatch);
+        }
+
+        let crc = u16::from_le_bytes([buf[crc_start], buf[crc_start + 1]]);
+        if crc != crc16(&buf[..crc_start]) {
+            return Err(Error::BadCrc);
+        }
+
+        let config: RadioConfig = from_bytes(&buf[2..crc_start]).map_err(|_| Error::Deserialize)?;
+        config.validate().map_err(|_| Error::Deserialize)?;
+
+        Ok(config)
+    }
+
+    /// Reads the config saved at `address`, if there is one and it's still good.
+    pub fn load<F: ReadNorFlash>(flash: &mut F, address: u32) -> Option<Self> {
+        let mut buf = [0u8; CONFIG_RECORD_LEN];
+        flash.read(address, &mut buf).ok()?;
+        RadioConfig::decode(&buf).ok()
+    }
+
+    /// Erases the sector at `address` and writes the config there. Nothing's erased if it won't encode.
+    pub fn save<F: NorFlash>(
+        &self,
+        flash: &mut F,
+        address: u32,
+    ) -> Result<(), SaveError<F::Error>> {
+        let record = self.encode().map_err(SaveError::Encode)?;
+
+        flash
+            .erase(address, address + F::ERASE_SIZE as u32)
+            .map_err(SaveError::Flash)?;
+        flash.write(address, &record).map_err(SaveError::Flash)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    Force(Profile),
+    Schedule,               // Back to following the flight phase
+    Configure(RadioConfig), // Change channel or power, and save it for next time
+}
+
+#[cfg(feature = "esp32c3")]
+pub static COMMANDS: Channel<CriticalSectionRawMutex, Command, 2> = Channel::new();
+
+#[cfg(feature = "esp32c3")]
+fn take_commands(schedule: &mut Schedule, config: &mut RadioConfig, flash: &mut FlashStorage) {
+    while let Ok(command) = COMMANDS.try_receive() {
+        info!("LoRa command: {}", command);
+        match command {
+            Command::Force(profile) => schedule.force(Some(profile)),
+            Command::Schedule => schedule.force(None),
+            Command::Configure(new) => match new.validate() {
+                Ok(()) => {
+                    *config = new;
+                    if new.save(flash, CONFIG_ADDRESS).is_err() {
+                        error!("Saving radio config failed, it'll be gone after a reboot");
+                    }
+                }
+                Err(e) => error!("Rejecting radio config: {}", e),
+            },
+        }
+    }
+}
+
+// Whatever was saved last, or the defaults if that's missing or no longer allowed
+#[cfg(feature = "esp32c3")]
+fn load_config(flash: &mut FlashStorage) -> RadioConfig {
+    let config = RadioConfig::load(flash, CONFIG_ADDRESS).unwrap_or_else(|| {
+        warn!("No saved radio config, using the defaults");
+        RadioConfig::default()
+    });
+    info!("Radio config: {}", config);
+    config
+}
+
+#[cfg(feature = "esp32c3")]
 #[task]
 pub async fn receive(
     spi: SpiDevice<'static, NoopRawMutex, SpiDmaBus<'static, Async>, Output<'static, AnyPin>>,
     lora_irq: Input<'static, AnyPin>,
     lora_rst: Output<'static, AnyPin>,
+    mut flash: FlashStorage,
 ) -> ! {
     // We're using an SX1278, but the SX1276 variant seems to work
-    let config = sx127x::Config {
+    // PA_BOOST for the odd command going up, it's the only output most SX1278 modules wire up
+    let sx127x_config = sx127x::Config {
         chip: sx127x::Sx1276,
         tcxo_used: false,
         rx_boost: true,
-        tx_boost: false,
+        tx_boost: true,
     };
 
     let interface_variant =
         GenericSx127xInterfaceVariant::new(lora_rst, lora_irq, None, None).unwrap();
 
-    let mut lora = LoRa::new(Sx127x::new(spi, interface_variant, config), false, Delay)
-        .await
-        .unwrap();
-
-    let modulation_parameters = create_lora_modulation_parameters(&mut lora);
-
-    let rx_packet_parameters = {
-        match lora.create_rx_packet_params(
-            16,
-            false,
-            LORA_MAX_PACKET_SIZE_BYTES as u8,
-            true,
-            false,
-            &modulation_parameters,
-        ) {
-            Ok(pp) => pp,
-            Err(err) => {
-                panic!("RX Packet Parameters Error: {:?}", err);
-            }
+    let mut lora = LoRa::new(
+        Sx127x::new(spi, interface_variant, sx127x_config),
+        false,
+        Delay,
+    )
+    .await
+    .unwrap();
+
+    let mut config = load_config(&mut flash);
+    let mut schedule = Schedule::new();
+
+    // The last counter a command went up with, saved before it's first sent
+    let mut counter = match auth::load_counter(&mut flash, auth::COUNTER_ADDRESS) {
+        Ok(counter) => counter.unwrap_or(0),
+        Err(_) => {
+            // The vehicle will take these for replays until the counter's back past where it was
+            error!("Uplink counter unreadable, starting again from 0");
+            0
         }
     };
+    uplink::OUTBOX.lock().await.resume(counter);
 
     loop {
+        take_commands(&mut schedule, &mut config, &mut flash);
+        let profile = schedule.profile();
+
+        let modulation_parameters = create_lora_modulation_parameters(&mut lora, &config, profile);
+
+        let rx_packet_parameters = {
+            match lora.create_rx_packet_params(
+                PREAMBLE_LEN,
+                false,
+                LORA_MAX_PACKET_SIZE_BYTES as u8,
+                true,
+                false,
+                &modulation_parameters,
+            ) {
+                Ok(pp) => pp,
+                Err(err) => {
+                    panic!("RX Packet Parameters Error: {:?}", err);
+                }
+            }
+        };
+
         // TODO: Can we move this out of the loop?
         let mut rx_buff = [0u8; LORA_MAX_PACKET_SIZE_BYTES];
 
@@ -87,42 +654,288 @@ pub async fn receive(
             }
         };
 
-        info!("Waiting up to 60s for LoRA message...");
+        let listen_ms = schedule.listen_ms(&config);
+        info!(
+            "Waiting up to {}ms for LoRA message on {}...",
+            listen_ms, profile
+        );
         let rx_timeout_result = with_timeout(
-            Duration::from_secs(60),
+            Duration::from_millis(listen_ms as u64),
             lora.rx(&rx_packet_parameters, &mut rx_buff),
         );
 
         match rx_timeout_result.await {
             Ok(Ok((received_len, _rx_pkt_status))) => {
+                let heard = Instant::now();
                 info!("RX successful, with {} bytes", received_len);
-                // Deserialize and print
-                let out: State = from_bytes(&rx_buff).unwrap();
-                info!(
-                    "Received state: {:?}, RSSI: {}, SNR: {}",
-                    out, _rx_pkt_status.rssi, _rx_pkt_status.snr
-                );
+                // Only decode what we actually received, anything else on the channel is dropped here
+                let received = &rx_buff[..received_len as usize];
+                if received.first() == Some(&fec::MAGIC) {
+                    recover(received, _rx_pkt_status.rssi, _rx_pkt_status.snr).await;
+                    continue;
+                }
+
+                let decoded = match protocol::unframe(received) {
+                    Ok((header, payload)) => {
+                        // Even if it can't be read without its keyframe, it helps rebuild the frames around it
+                        let mut held = fec::DECODER.lock().await;
+                        held.data(header.vehicle, header.seq, received);
+                        let state = compact::DECODER.lock().await.decode(&header, payload);
+                        match state {
+                            // Heard fine, it just has to wait for its keyframe to be rebuilt from parity
+                            Err(Error::NoKeyframe) => {
+                                held.hold(header.vehicle, header.seq);
+                                drop(held);
+                                info!(
+                                    "Holding frame {} from vehicle {} until its keyframe turns up",
+                                    header.seq, header.vehicle
+                                );
+                                LINK.lock().await.record(
+                                    &header,
+                                    _rx_pkt_status.rssi,
+                                    _rx_pkt_status.snr,
+                                    heard.as_millis(),
+                                );
+                                continue;
+                            }
+                            state => state.map(|state| (header, state)),
+                        }
+                    }
+                    Err(err) => Err(err),
+                };
+
+                match decoded {
+                    Ok((header, out)) => {
+                        info!(
+                            "Received state from vehicle {} (seq {}): {:?}, RSSI: {}, SNR: {}",
+                            header.vehicle,
+                            header.seq,
+                            out,
+                            _rx_pkt_status.rssi,
+                            _rx_pkt_status.snr
+                        );
+
+                        // The next packet comes on whatever this one's phase calls for
+                        schedule.follow(out.fp);
+
+                        let link = LINK.lock().await.record(
+                            &header,
+                            _rx_pkt_status.rssi,
+                            _rx_pkt_status.snr,
+                            heard.as_millis(),
+                        );
+                        info!("Link: {}", link);
+
+                        let due = {
+                            let mut outbox = uplink::OUTBOX.lock().await;
+                            if let Some(acked) = outbox.acked(header.vehicle, out.ak) {
+                                info!("Vehicle {} took {}", acked.vehicle, acked.command);
+                                // It switched once this packet was out, so we follow from the next one
+                                if let uplink::Command::Profile(profile) = acked.command {
+                                    schedule.force(profile);
+                                }
+                            }
+                            outbox.due(header.vehicle)
+                        };
+
+                        let packet = Packet {
+                            header,
+                            rssi: _rx_pkt_status.rssi,
+                            snr: _rx_pkt_status.snr,
+                            link,
+                            recovered: false,
+                            state: out,
+                        };
+
+                        // Whoever is on the other end of the serial port has fallen behind, it'll miss this one
+                        if telemetry::PACKETS.try_send(packet).is_err() {
+                            error!("Telemetry output full, dropping packet");
+                        }
+
+                        // The vehicle's listening on what it just sent on, so answer straight back on that
+                        if let (Some(command), Some(key)) = (due, &uplink::KEY) {
+                            if command.counter > counter {
+                                counter = command.counter;
+                                if auth::save_counter(&mut flash, auth::COUNTER_ADDRESS, counter)
+                                    .is_err()
+                                {
+                                    error!("Saving the uplink counter failed");
+                                }
+                            }
+
+                            send_uplink(
+                                &mut lora,
+                                &modulation_parameters,
+                                config.power_dbm,
+                                key,
+                                &command,
+                                heard,
+                            )
+                            .await;
+                        }
+
+                        // After answering, there's no rush for these
+                        release(
+                            &mut *fec::DECODER.lock().await,
+                            &header,
+                            _rx_pkt_status.rssi,
+                            _rx_pkt_status.snr,
+                        )
+                        .await;
+                    }
+                    // Anything else on the channel, or our own packets mangled on the way, ends up here
+                    Err(err) => {
+                        error!(
+                            "Dropping undecodable packet: {:?}, {=[u8]:x}",
+                            err, received
+                        );
+                        LINK.lock().await.reject(&err);
+                        continue;
+                    }
+                }
+            }
+            Ok(Err(RadioError::CRCErrorOnReceive)) => {
+                error!("RX failed, bad CRC");
+                LINK.lock().await.corrupt();
+                continue;
             }
             Ok(Err(_)) => {
                 error!("RX failed");
                 continue;
             }
             Err(_) => {
-                error!("RX timed out after 60 seconds");
+                // Packets carry their own gap, this is the only place a silence shows up
+                let silent_ms = LINK.lock().await.since_last_ms(Instant::now().as_millis());
+                match silent_ms {
+                    Some(silent_ms) => error!(
+                        "Nothing heard on {} for {}ms, {}ms since the last packet, trying the next profile",
+                        profile, listen_ms, silent_ms
+                    ),
+                    None => error!(
+                        "Nothing heard on {} for {}ms, trying the next profile",
+                        profile, listen_ms
+                    ),
+                }
+                schedule.hunt();
                 continue;
             }
         };
     }
 }
 
+// Passes on any telemetry frames a parity frame lets us rebuild, as if they'd been heard along with it
+#[cfg(feature = "esp32c3")]
+async fn recover(received: &[u8], rssi: i16, snr: i16) {
+    let mut decoder = fec::DECODER.lock().await;
+    let rebuilt = match decoder.parity(received) {
+        Ok(rebuilt) => rebuilt,
+        Err(err) => {
+            error!(
+                "Dropping undecodable parity frame: {:?}, {=[u8]:x}",
+                err, received
+            );
+            LINK.lock().await.reject(&err);
+            return;
+        }
+    };
+
+    for rebuilt in rebuilt {
+        let Some(frame) = decoder.frame(rebuilt.vehicle, rebuilt.seq) else {
+            continue;
+        };
+
+        let decoded = protocol::decode(frame, &mut *compact::DECODER.lock().await);
+        let (header, out) = match decoded {
+            Ok(decoded) => decoded,
+            // Its keyframe might be next
+            Err(Error::NoKeyframe) => {
+                decoder.hold(rebuilt.vehicle, rebuilt.seq);
+                continue;
+            }
+            Err(err) => {
+                error!("Rebuilt frame won't decode: {:?}", err);
+                LINK.lock().await.reject(&err);
+                continue;
+            }
+        };
+        info!(
+            "Rebuilt state from vehicle {} (seq {}): {:?}",
+            header.vehicle, header.seq, out
+        );
+
+        // It was counted missed when the frames either side came in, so it isn't recorded into the link stats
+        let packet = Packet {
+            header,
+            rssi,
+            snr,
+            link: LINK.lock().await.summary(),
+            recovered: true,
+            state: out,
+        };
+
+        if telemetry::PACKETS.try_send(packet).is_err() {
+            error!("Telemetry output full, dropping packet");
+        }
+
+        release(&mut decoder, &header, rssi, snr).await;
+    }
+}
+
+// Passes on the frames held for want of the keyframe `header` came with. They were counted into the link stats as
+// they came in, and go out with the signal of whatever brought the keyframe.
+#[cfg(feature = "esp32c3")]
+async fn release(decoder: &mut fec::Decoder, header: &Header, rssi: i16, snr: i16) {
+    if header.seq % compact::KEYFRAME_INTERVAL != 0 {
+        return;
+    }
+
+    for seq in (1..compact::KEYFRAME_INTERVAL).map(|i| header.seq.wrapping_add(i)) {
+        let Some(frame) = decoder.release(header.vehicle, seq) else {
+            continue;
+        };
+
+        let (held, out) = match protocol::decode(frame, &mut *compact::DECODER.lock().await) {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                error!("Held frame won't decode: {:?}", err);
+                LINK.lock().await.reject(&err);
+                continue;
+            }
+        };
+        info!(
+            "Caught up on state from vehicle {} (seq {}): {:?}",
+            held.vehicle, held.seq, out
+        );
+
+        let packet = Packet {
+            header: held,
+            rssi,
+            snr,
+            link: LINK.lock().await.summary(),
+            recovered: false,
+            state: out,
+        };
+
+        // Not worth waiting on with the decoder locked, the receiver can't hear anything meanwhile
+        if telemetry::PACKETS.try_send(packet).is_err() {
+            error!("Telemetry output full, dropping packet");
+        }
+    }
+}
+
+#[cfg(feature = "esp32c3")]
 #[task]
 pub async fn transmit(
     spi: SpiDevice<'static, NoopRawMutex, SpiDmaBus<'static, Async>, Output<'static, AnyPin>>,
     lora_irq: Input<'static, AnyPin>,
     lora_rst: Output<'static, AnyPin>,
+    vehicle: u8,
+    fec: Option<Scheme>,
+    rates: &'static (dyn Policy + Sync),
+    mut flash: FlashStorage,
 ) -> ! {
     // We're using an SX1278, but the SX1276 variant seems to work
-    let config = sx127x::Config {
+    let sx127x_config = sx127x::Config {
         chip: sx127x::Sx1276,
         tcxo_used: false,
         rx_boost: false,
@@ -132,38 +945,131 @@ pub async fn transmit(
     let interface_variant =
         GenericSx127xInterfaceVariant::new(lora_rst, lora_irq, None, None).unwrap();
 
-    let sx_device = Sx127x::new(spi, interface_variant, config);
+    let sx_device = Sx127x::new(spi, interface_variant, sx127x_config);
 
     let mut lora = LoRa::new(sx_device, false, Delay).await.unwrap();
 
     // Do we need to init??
     lora.init().await.unwrap();
 
-    let modulation_parameters = create_lora_modulation_parameters(&mut lora);
-
-    let mut tx_packet_parameters = {
-        match lora.create_tx_packet_params(16, false, true, false, &modulation_parameters) {
-            Ok(pp) => pp,
-            Err(err) => {
-                panic!("TX Param Setup: {:?}", err);
-            }
+    let mut config = load_config(&mut flash);
+    let mut schedule = Schedule::new();
+    // Without the last counter acted on there's no telling a replay, so nothing's taken until the flash is sorted out
+    let mut inbox = match auth::load_counter(&mut flash, auth::COUNTER_ADDRESS) {
+        Ok(counter) => Some(Inbox::new(vehicle, counter.unwrap_or(0))),
+        Err(_) => {
+            error!("Uplink counter unreadable, not taking commands");
+            None
         }
     };
+    if uplink::KEY.is_none() {
+        warn!("Built without UPLINK_KEY, not taking commands");
+    }
+    let mut seq: u16 = 0;
+
+    let mut compact = compact::Encoder::new();
+    let mut cadence = Cadence::new(rates, uplink::KEY.is_some());
+
+    // Parity frames still to go out for the group just finished
+    let mut encoder = fec.map(Encoder::new);
+    let mut parity = 0..0;
+
+    // A profile change from the ground, held back until the packet acknowledging it has gone out
+    let mut switch: Option<Option<Profile>> = None;
 
     loop {
-        Timer::after_millis(3_000).await;
+        // Checking on the phase while we wait, a change goes out straight away
+        let now_phase = loop {
+            let phase = STATE.lock().await.fp;
+            match cadence.wait_ms(
+                Instant::now().as_millis(),
+                phase,
+                &config,
+                schedule.profile(),
+            ) {
+                0 => break phase,
+                wait => Timer::after_millis(wait.min(PHASE_POLL_MS)).await,
+            }
+        };
+        // Ahead of whatever parity was left for the group before, which goes without
+        if cadence.changed(now_phase) {
+            parity = 0..0;
+        }
+
+        take_commands(&mut schedule, &mut config, &mut flash);
+        let profile = schedule.profile();
+
+        // Taken even if the frame doesn't make it out, so a failing radio isn't tried over and over
+        let sending_parity = encoder.is_some() && !parity.is_empty();
+        cadence.take(
+            Instant::now().as_millis(),
+            (!sending_parity).then_some(now_phase),
+            &config,
+            profile,
+        );
+
+        let modulation_parameters = create_lora_modulation_parameters(&mut lora, &config, profile);
+
+        let mut tx_packet_parameters = {
+            match lora.create_tx_packet_params(
+                PREAMBLE_LEN,
+                false,
+                true,
+                false,
+                &modulation_parameters,
+            ) {
+                Ok(pp) => pp,
+                Err(err) => {
+                    panic!("TX Param Setup: {:?}", err);
+                }
+            }
+        };
 
         // TODO: Can we move setting up this beff to outside the loop?
         let mut buff = [0u8; LORA_MAX_PACKET_SIZE_BYTES];
-        let output = to_slice(&*STATE.lock().await, &mut buff).unwrap();
+        let header = Header { vehicle, seq };
+        // Parity doesn't carry a phase, the next packet goes out on the same profile
+        let (output, phase) = match (&encoder, parity.next()) {
+            (Some(encoder), Some(index)) => match encoder.parity(vehicle, index, &mut buff) {
+                Ok(output) => (output, None),
+                Err(err) => {
+                    error!("Encoding parity frame failed: {:?}", err);
+                    continue;
+                }
+            },
+            _ => {
+                let (encoded, phase) = {
+                    let mut state = STATE.lock().await;
+                    state.bt = Instant::now().as_millis();
+                    (
+                        protocol::encode(&header, &state, &mut compact, &mut buff),
+                        state.fp,
+                    )
+                };
+                match encoded {
+                    Ok(output) => {
+                        seq = seq.wrapping_add(1);
+                        (output, Some(phase))
+                    }
+                    Err(err) => {
+                        error!("Encoding telemetry frame failed: {:?}", err);
+                        continue;
+                    }
+                }
+            }
+        };
 
-        info!("Transmitting {:?} bytes over LoRA", output.len());
+        info!(
+            "Transmitting {:?} bytes over LoRA on {}",
+            output.len(),
+            profile
+        );
         let prepare_tx_timeout_result = with_timeout(
             Duration::from_millis(100),
             lora.prepare_for_tx(
                 &modulation_parameters,
                 &mut tx_packet_parameters,
-                20,
+                config.power_dbm as i32,
                 &output,
             ),
         );
@@ -187,6 +1093,13 @@ pub async fn transmit(
         match tx_timeout_result.await {
             Ok(Ok(r)) => {
                 info!("TX succeeded");
+                // Anyone who heard that will be listening for the next one wherever its phase says
+                if let Some(phase) = phase {
+                    schedule.follow(phase);
+                    if let Some(profile) = switch.take() {
+                        schedule.force(profile);
+                    }
+                }
                 r
             }
             Ok(Err(_)) => {
@@ -199,23 +1112,195 @@ pub async fn transmit(
             }
         };
 
-        info!("LoRA complete");
+        // The ground station doesn't answer parity, so there's nothing to listen for
+        if phase.is_none() {
+            continue;
+        }
+
+        if let Some(encoder) = &mut encoder {
+            if encoder.push(header.seq, output) {
+                parity = 0..encoder.scheme().parity();
+            }
+        }
+
+        let (Some(key), Some(inbox)) = (&uplink::KEY, &mut inbox) else {
+            continue;
+        };
+
+        let window_ms = uplink::window_ms(&config.modulation(profile));
+        let Some(received) =
+            listen_for_uplink(&mut lora, &modulation_parameters, key, window_ms).await
+        else {
+            continue;
+        };
+
+        match inbox.accept(&received) {
+            Ok(command) => {
+                info!("Taking {} from the ground", received);
+                // Before acting on it, so a reboot straight after can't have it taken twice
+                if auth::save_counter(&mut flash, auth::COUNTER_ADDRESS, inbox.counter()).is_err() {
+                    error!("Saving the uplink counter failed");
+                }
+                match command {
+                    uplink::Command::Profile(profile) => switch = Some(profile),
+                    command => uplink::dispatch(command),
+                }
+            }
+            Err(Reject::Replayed) => warn!("Ignoring replayed {}", received),
+            Err(_) => {}
+        }
+
+        // A resend of something we've already done still wants acknowledging
+        STATE.lock().await.ak = inbox.ack();
     }
 }
 
+// Listens for `window_ms` after a packet, on the same settings it went out on, for a command addressed to anyone
+#[cfg(feature = "esp32c3")]
+async fn listen_for_uplink<T: RadioKind, U: DelayNs>(
+    lora: &mut LoRa<T, U>,
+    modulation_parameters: &ModulationParams,
+    key: &Key,
+    window_ms: u32,
+) -> Option<Uplink> {
+    let rx_packet_parameters = match lora.create_rx_packet_params(
+        PREAMBLE_LEN,
+        false,
+        uplink::MAX_FRAME_LEN as u8,
+        true,
+        false,
+        modulation_parameters,
+    ) {
+        Ok(pp) => pp,
+        Err(err) => {
+            error!("Uplink RX Packet Parameters Error: {:?}", err);
+            return None;
+        }
+    };
+
+    if lora
+        .prepare_for_rx(
+            RxMode::Continuous,
+            modulation_parameters,
+            &rx_packet_parameters,
+        )
+        .await
+        .is_err()
+    {
+        error!("Prepare uplink RX failed");
+        return None;
+    }
+
+    let mut rx_buff = [0u8; uplink::MAX_FRAME_LEN];
+    let received = with_timeout(
+        Duration::from_millis(window_ms as u64),
+        lora.rx(&rx_packet_parameters, &mut rx_buff),
+    );
+
+    // Nothing heard is the usual case, nobody has anything to say
+    let Ok(Ok((received_len, _))) = received.await else {
+        return None;
+    };
+
+    let received = &rx_buff[..received_len as usize];
+    match uplink::decode(received, key) {
+        Ok(uplink) => Some(uplink),
+        Err(err) => {
+            error!(
+                "Dropping undecodable uplink: {:?}, {=[u8]:x}",
+                err, received
+            );
+            None
+        }
+    }
+}
+
+// Answers a packet heard at `heard` with a command, once the vehicle has had time to start listening
+#[cfg(feature = "esp32c3")]
+async fn send_uplink<T: RadioKind, U: DelayNs>(
+    lora: &mut LoRa<T, U>,
+    modulation_parameters: &ModulationParams,
+    power_dbm: i8,
+    key: &Key,
+    command: &Uplink,
+    heard: Instant,
+) {
+    let mut buff = [0u8; uplink::MAX_FRAME_LEN];
+    let output = match uplink::encode(command, key, &mut buff) {
+        Ok(output) => output,
+        Err(err) => {
+            error!("Encoding uplink frame failed: {:?}", err);
+            return;
+        }
+    };
+
+    let mut tx_packet_parameters =
+        match lora.create_tx_packet_params(PREAMBLE_LEN, false, true, false, modulation_parameters)
+        {
+            Ok(pp) => pp,
+            Err(err) => {
+                error!("Uplink TX Param Setup: {:?}", err);
+                return;
+            }
+        };
+
+    Timer::at(heard + Duration::from_millis(uplink::TURNAROUND_MS as u64)).await;
+
+    if lora
+        .prepare_for_tx(
+            modulation_parameters,
+            &mut tx_packet_parameters,
+            power_dbm as i32,
+            output,
+        )
+        .await
+        .is_err()
+    {
+        error!("Prepare uplink TX failed");
+        return;
+    }
+
+    match with_timeout(Duration::from_secs(5), lora.tx()).await {
+        Ok(Ok(())) => info!("Sent {}", command),
+        _ => error!("Sending {} failed", command),
+    }
+}
+
+#[cfg(feature = "esp32c3")]
 fn create_lora_modulation_parameters<T: RadioKind, U: DelayNs>(
     lora: &mut LoRa<T, U>,
+    config: &RadioConfig,
+    profile: Profile,
 ) -> ModulationParams {
-    // These settings result in roughly 977 bps
-    // The coding rate can be changed to 4/5 to get to 1.6kbps
-    // But this is about as reliable as we can get without seriosuly harming
-    // bitrate, without having an external TCXO reference clock required
-    // for the lower bandwidths to be reliable.
+    // Below 62.5kHz we'd need an external TCXO reference clock to be reliable, so none of the configs go there
+    let modulation = config.modulation(profile);
+
+    let spreading_factor = match modulation.spreading_factor {
+        7 => SpreadingFactor::_7,
+        8 => SpreadingFactor::_8,
+        9 => SpreadingFactor::_9,
+        10 => SpreadingFactor::_10,
+        11 => SpreadingFactor::_11,
+        _ => SpreadingFactor::_12,
+    };
+    let bandwidth = match modulation.bandwidth_hz {
+        62_500 => Bandwidth::_62KHz,
+        125_000 => Bandwidth::_125KHz,
+        250_000 => Bandwidth::_250KHz,
+        _ => Bandwidth::_500KHz,
+    };
+    let coding_rate = match modulation.coding_rate {
+        5 => CodingRate::_4_5,
+        6 => CodingRate::_4_6,
+        7 => CodingRate::_4_7,
+        _ => CodingRate::_4_8,
+    };
+
     let params = lora.create_modulation_params(
-        SpreadingFactor::_8,
-        Bandwidth::_62KHz,
-        CodingRate::_4_8,
-        LORA_FREQUENCY_IN_HZ,
+        spreading_factor,
+        bandwidth,
+        coding_rate,
+        config.frequency_hz,
     );
 
     match params {
@@ -0,0 +1,440 @@
+// Forward error correction across groups of telemetry frames, for the range where whole packets go missing.
+//
+// Frames are grouped by sequence number, `k` at a time starting on a multiple of `k`. Once the last frame of a group
+// has gone out, the vehicle sends `m` parity frames in the slots after it. Between them they're a systematic
+// Reed-Solomon erasure code over GF(256): any `k` of the `k + m` frames in a group are enough to rebuild the rest, so
+// the ground station can get back up to `m` telemetry frames it never heard.
+//
+// Each frame is treated as a shard of its length byte followed by the frame, zero padded to the longest in the group.
+// A frame that never went out is all zeros, which rebuilds to length 0 and is dropped.
+//
+// Every parity frame on air looks like:
+//
+//   | magic | version | vehicle | first (LE) | k | m | index | len | parity shard | crc16 (LE) |
+//   |   1   |    1    |    1    |     2      | 1 | 1 |   1   |  1  |     len      |     2      |
+//
+// `first` is the sequence number the group starts on. The CRC covers everything from the magic byte to the end of
+// the shard.
+#[cfg(feature = "esp32c3")]
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use heapless::Vec;
+
+use crate::protocol::{self, crc16, Error, Header, VERSION};
+
+pub const MAGIC: u8 = 0xA9;
+
+pub const HEADER_LEN: usize = 9;
+pub const CRC_LEN: usize = 2;
+pub const MAX_SHARD_LEN: usize = protocol::MAX_FRAME_LEN - HEADER_LEN - CRC_LEN;
+
+// Longest telemetry frame that can be protected, one less than a shard for the length byte
+pub const MAX_PROTECTED_LEN: usize = MAX_SHARD_LEN - 1;
+
+pub const MAX_DATA: usize = 8;
+pub const MAX_PARITY: usize = 4;
+
+/// How many telemetry frames go in a group, and how many parity frames follow them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Scheme {
+    k: u8,
+    m: u8,
+}
+
+impl Scheme {
+    /// `k` has to be 2, 4 or 8, so groups line up across the sequence number wrapping, and `m` 1 to 4.
+    pub const fn new(k: u8, m: u8) -> Option<Self> {
+        match (k, m) {
+            (2 | 4 | 8, 1..=4) => Some(Scheme { k, m }),
+            _ => None,
+        }
+    }
+
+    pub fn data(&self) -> u8 {
+        self.k
+    }
+
+    pub fn parity(&self) -> u8 {
+        self.m
+    }
+
+    /// Sequence number of the first frame in the group `seq` belongs to.
+    pub fn first(&self, seq: u16) -> u16 {
+        seq & !(self.k as u16 - 1)
+    }
+}
+
+// GF(256) with the usual 0x11D polynomial, multiplication done through log and antilog tables
+const GF: ([u8; 256], [u8; 512]) = {
+    let mut log = [0u8; 256];
+    let mut exp = [0u8; 512];
+    let mut x: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = x as u8;
+        exp[i + 255] = x as u8;
+        log[x as usize] = i as u8;
+        x <<= 1;
+        if x & 0x100 != 0 {
+            x ^= 0x11D;
+        }
+        i += 1;
+    }
+    (log, exp)
+};
+
+fn mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    GF.1[GF.0[a as usize] as usize + GF.0[b as usize] as usize]
+}
+
+fn inv(a: u8) -> u8 {
+    GF.1[255 - GF.0[a as usize] as usize]
+}
+
+// Cauchy matrix, 1 / (x_j + y_i) with x_j = MAX_DATA + j and y_i = i. Every square piece of it can be inverted,
+// which is what makes any k shards enough.
+fn coefficient(parity: usize, data: usize) -> u8 {
+    inv(((MAX_DATA + parity) ^ data) as u8)
+}
+
+// dst += c * src
+fn mul_add(dst: &mut [u8], c: u8, src: &[u8]) {
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d ^= mul(c, *s);
+    }
+}
+
+/// The vehicle's side: builds up the parity for a group as its frames go out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Encoder {
+    scheme: Scheme,
+    first: u16,
+    len: usize,
+    parity: [[u8; MAX_SHARD_LEN]; MAX_PARITY],
+}
+
+impl Encoder {
+    pub const fn new(scheme: Scheme) -> Self {
+        Encoder {
+            scheme,
+            first: 0,
+            len: 0,
+            parity: [[0; MAX_SHARD_LEN]; MAX_PARITY],
+        }
+    }
+
+    pub fn scheme(&self) -> Scheme {
+        self.scheme
+    }
+
+    /// Adds a telemetry frame that's gone out with `seq`. True once it's the last of its group, and the parity
+    /// frames should follow. Frames too long to protect are left out, they'll just never be rebuilt.
+    pub fn push(&mut self, seq: u16, frame: &[u8]) -> bool {
+        let first = self.scheme.first(seq);
+        if first != self.first {
+            self.first = first;
+            self.len = 0;
+            self.parity = [[0; MAX_SHARD_LEN]; MAX_PARITY];
+        }
+
+        let index = seq.wrapping_sub(first) as usize;
+        if frame.len() <= MAX_PROTECTED_LEN {
+            for (j, parity) in self.parity[..self.scheme.m as usize].iter_mut().enumerate() {
+                let c = coefficient(j, index);
+                parity[0] ^= mul(c, frame.len() as u8);
+                mul_add(&mut parity[1..], c, frame);
+            }
+            self.len = self.len.max(1 + frame.len());
+        }
+
+        index == self.scheme.k as usize - 1
+    }
+
+    /// Frames parity shard `index` of the group just finished into `buf`, returning the slice that should go on air.
+    pub fn parity<'a>(&self, vehicle: u8, index: u8, buf: &'a mut [u8]) -> Result<&'a [u8], Error> {
+        // Nothing in the group could be protected
+        if index >= self.scheme.m || self.len == 0 {
+            return Err(Error::Serialize);
+        }
+
+        let crc_start = HEADER_LEN + self.len;
+        if buf.len() < crc_start + CRC_LEN {
+            return Err(Error::BufferTooSmall);
+        }
+
+        let first = self.first.to_le_bytes();
+        buf[..HEADER_LEN].copy_from_slice(&[
+            MAGIC,
+            VERSION,
+            vehicle,
+            first[0],
+            first[1],
+            self.scheme.k,
+            self.scheme.m,
+            index,
+            self.len as u8,
+        ]);
+        buf[HEADER_LEN..crc_start].copy_from_slice(&self.parity[index as usize][..self.len]);
+
+        let crc = crc16(&buf[..crc_start]).to_le_bytes();
+        buf[crc_start..crc_start + CRC_LEN].copy_from_slice(&crc);
+
+        Ok(&buf[..crc_start + CRC_LEN])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Group {
+    vehicle: u8,
+    first: u16,
+    k: u8,
+    m: u8,
+    len: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Slot {
+    vehicle: u8,
+    seq: u16,
+    len: Option<usize>, // None while empty
+    held: bool,         // Couldn't be read when it came in, see `Decoder::hold`
+    frame: [u8; MAX_PROTECTED_LEN],
+}
+
+const EMPTY: Slot = Slot {
+    vehicle: 0,
+    seq: 0,
+    len: None,
+    held: false,
+    frame: [0; MAX_PROTECTED_LEN],
+};
+
+impl Slot {
+    fn holds(&self, vehicle: u8, seq: u16) -> Option<&[u8]> {
+        match self.len {
+            Some(len) if self.vehicle == vehicle && self.seq == seq => Some(&self.frame[..len]),
+            _ => None,
+        }
+    }
+}
+
+/// The ground station's side: keeps the last few frames heard, and rebuilds the missing ones once enough parity
+/// has come in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decoder {
+    data: [Slot; MAX_DATA],
+    group: Option<Group>,
+    heard: [bool; MAX_PARITY],
+    parity: [[u8; MAX_SHARD_LEN]; MAX_PARITY],
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    pub const fn new() -> Self {
+        Decoder {
+            data: [EMPTY; MAX_DATA],
+            group: None,
+            heard: [false; MAX_PARITY],
+            parity: [[0; MAX_SHARD_LEN]; MAX_PARITY],
+        }
+    }
+
+    fn slot(&mut self, seq: u16) -> &mut Slot {
+        &mut self.data[seq as usize % MAX_DATA]
+    }
+
+    /// A telemetry frame heard from `vehicle` with `seq`, exactly as it was received.
+    pub fn data(&mut self, vehicle: u8, seq: u16, frame: &[u8]) {
+        let slot = self.slot(seq);
+        if frame.len() > MAX_PROTECTED_LEN {
+            slot.len = None;
+            return;
+        }
+
+        slot.vehicle = vehicle;
+        slot.seq = seq;
+        slot.len = Some(frame.len());
+        slot.held = false;
+        slot.frame[..frame.len()].copy_from_slice(frame);
+    }
+
+    /// A frame we've heard or rebuilt, if it's still around.
+    pub fn frame(&self, vehicle: u8, seq: u16) -> Option<&[u8]> {
+        self.data[seq as usize % MAX_DATA].holds(vehicle, seq)
+    }
+
+    /// Keeps hold of a frame that's been through `data` but can't be read yet, because the keyframe before it never
+    /// came. Once that's rebuilt, or turns up, it can be had back from `release`.
+    pub fn hold(&mut self, vehicle: u8, seq: u16) {
+        let slot = self.slot(seq);
+        if slot.holds(vehicle, seq).is_some() {
+            slot.held = true;
+        }
+    }
+
+    /// A frame from `hold`, if it's still around. Only once, it's read by then.
+    pub fn release(&mut self, vehicle: u8, seq: u16) -> Option<&[u8]> {
+        let slot = self.slot(seq);
+        if !slot.held {
+            return None;
+        }
+        slot.held = false;
+        slot.holds(vehicle, seq)
+    }
+
+    /// Takes a parity frame, exactly as it was received. Returns who sent any frames it let us rebuild, and with what
+    /// sequence numbers, see `frame`.
+    pub fn parity(&mut self, buf: &[u8]) -> Result<Vec<Header, MAX_PARITY>, Error> {
+        let (group, index) = check_parity(buf)?;
+        let mut rebuilt = Vec::new();
+
+        if self.group != Some(group) {
+            self.group = Some(group);
+            self.heard = [false; MAX_PARITY];
+        }
+        self.heard[index] = true;
+        self.parity[index][..group.len].copy_from_slice(&buf[HEADER_LEN..HEADER_LEN + group.len]);
+
+        let seq = |i: usize| group.first.wrapping_add(i as u16);
+        let missing: Vec<usize, MAX_DATA> = (0..group.k as usize)
+            .filter(|&i| self.frame(group.vehicle, seq(i)).is_none())
+            .collect();
+        let heard: Vec<usize, MAX_PARITY> =
+            (0..group.m as usize).filter(|&j| self.heard[j]).collect();
+
+        if missing.is_empty() || missing.len() > heard.len() {
+            return Ok(rebuilt);
+        }
+        let rows = &heard[..missing.len()];
+
+        // Take what we did hear out of the parity, leaving just the missing frames mixed together
+        let mut mixed = [[0u8; MAX_SHARD_LEN]; MAX_PARITY];
+        for (r, &j) in rows.iter().enumerate() {
+            mixed[r][..group.len].copy_from_slice(&self.parity[j][..group.len]);
+            for i in (0..group.k as usize).filter(|i| !missing.contains(i)) {
+                let frame = self.frame(group.vehicle, seq(i)).unwrap_or(&[]);
+                if 1 + frame.len() > group.len {
+                    return Err(Error::LengthMismatch);
+                }
+                let c = coefficient(j, i);
+                mixed[r][0] ^= mul(c, frame.len() as u8);
+                mul_add(&mut mixed[r][1..], c, frame);
+            }
+        }
+
+        // Then unmix them
+        let mut matrix = [[0u8; MAX_PARITY]; MAX_PARITY];
+        for (r, &j) in rows.iter().enumerate() {
+            for (c, &i) in missing.iter().enumerate() {
+                matrix[r][c] = coefficient(j, i);
+            }
+        }
+        let inverse = invert(matrix, missing.len());
+
+        for (c, &i) in missing.iter().enumerate() {
+            let mut shard = [0u8; MAX_SHARD_LEN];
+            for (r, row) in mixed[..missing.len()].iter().enumerate() {
+                mul_add(&mut shard[..group.len], inverse[c][r], &row[..group.len]);
+            }
+
+            // Zero length is a frame that never went out
+            let len = shard[0] as usize;
+            if len == 0 || 1 + len > group.len {
+                continue;
+            }
+
+            self.data(group.vehicle, seq(i), &shard[1..1 + len]);
+            let _ = rebuilt.push(Header {
+                vehicle: group.vehicle,
+                seq: seq(i),
+            });
+        }
+
+        Ok(rebuilt)
+    }
+}
+
+// Validates a parity frame, returning the group it's for and which parity shard it is
+fn check_parity(buf: &[u8]) -> Result<(Group, usize), Error> {
+    if buf.len() < HEADER_LEN + CRC_LEN {
+        return Err(Error::Truncated);
+    }
+
+    if buf[0] != MAGIC {
+        return Err(Error::BadMagic(buf[0]));
+    }
+
+    if buf[1] != VERSION {
+        return Err(Error::UnsupportedVersion(buf[1]));
+    }
+
+    let len = buf[8] as usize;
+    let crc_start = HEADER_LEN + len;
+    if buf.len() != crc_start + CRC_LEN {
+        return Err(Error::LengthMismatch);
+    }
+
+    let crc = u16::from_le_bytes([buf[crc_start], buf[crc_start + 1]]);
+    if crc != crc16(&buf[..crc_start]) {
+        return Err(Error::BadCrc);
+    }
+
+    let scheme = Scheme::new(buf[5], buf[6]).ok_or(Error::Deserialize)?;
+    let first = u16::from_le_bytes([buf[3], buf[4]]);
+    let index = buf[7];
+    if index >= scheme.m || scheme.first(first) != first || len == 0 {
+        return Err(Error::Deserialize);
+    }
+
+    let group = Group {
+        vehicle: buf[2],
+        first,
+        k: scheme.k,
+        m: scheme.m,
+        len,
+    };
+    Ok((group, index as usize))
+}
+
+// Gauss-Jordan on the top left n by n of `matrix`, which has to be invertible, as any piece of a Cauchy matrix is
+fn invert(mut matrix: [[u8; MAX_PARITY]; MAX_PARITY], n: usize) -> [[u8; MAX_PARITY]; MAX_PARITY] {
+    let mut inverse = [[0u8; MAX_PARITY]; MAX_PARITY];
+    for (i, row) in inverse.iter_mut().enumerate().take(n) {
+        row[i] = 1;
+    }
+
+    for col in 0..n {
+        let Some(pivot) = (col..n).find(|&r| matrix[r][col] != 0) else {
+            continue;
+        };
+        matrix.swap(col, pivot);
+        inverse.swap(col, pivot);
+
+        let scale = inv(matrix[col][col]);
+        for c in 0..n {
+            matrix[col][c] = mul(matrix[col][c], scale);
+            inverse[col][c] = mul(inverse[col][c], scale);
+        }
+
+        for r in (0..n).filter(|&r| r != col) {
+            let factor = matrix[r][col];
+            for c in 0..n {
+                matrix[r][c] ^= mul(factor, matrix[col][c]);
+                inverse[r][c] ^= mul(factor, inverse[col][c]);
+            }
+        }
+    }
+
+    inverse
+}
+
+#[cfg(feature = "esp32c3")]
+pub static DECODER: Mutex<CriticalSectionRawMutex, Decoder> = Mutex::new(Decoder::new());
@@ -0,0 +1,170 @@
+// How often the vehicle sends, as the flight goes on.
+//
+// A `Policy` picks the gap between frames from the flight phase and the profile they're going out on. `Rates` is the
+// one the vehicle flies with: quick from launch until the drogue's out, slower under the main, and a slow beacon for
+// finding it once it's down. A `Cadence` keeps the frames to a policy against whatever clock it's handed, so the host
+// tests can run a whole flight against a made up one.
+//
+// Whatever a policy asks for, the gap is kept between half the profile's `interval_ms` on the channel in use and all of
+// it. Any longer and a receiver listening on that profile would take the vehicle for lost and go hunting, any shorter
+// and the channel isn't quiet half the time any more. It's never shorter than a keyframe takes to go out either, plus
+// the vehicle's listening window after it if it takes commands, see `uplink`. Those don't fit in the 250 ms `Rates`
+// asks for on the way up, so a vehicle that takes commands goes a bit over three times a second rather than four.
+//
+// Slots are counted on from when the last one was due rather than when it went out, so the time spent sending, and
+// listening for the ground station after, doesn't stretch the gap. Falling a whole gap behind starts the count again
+// from now, rather than sending a burst to catch up. A change of phase goes out straight away, so the ground station
+// hears about it, and follows the vehicle onto the profile for it, as soon as it can.
+use crate::{
+    flight::Phase,
+    lora::{Profile, RadioConfig},
+    uplink,
+};
+
+// A keyframe in flight as it goes on air, with a few bytes to spare. The frames between are well under half that and a
+// parity frame a bit more, which the slots soak up.
+const KEYFRAME_LEN: usize = 56;
+
+/// Picks how long to leave between frames.
+pub trait Policy {
+    /// Milliseconds from one frame to the next for a vehicle in `phase`, sending on `profile`.
+    fn interval_ms(&self, phase: Phase, profile: Profile) -> u32;
+}
+
+/// Any closure will do, for trying something else out.
+impl<F: Fn(Phase, Profile) -> u32> Policy for F {
+    fn interval_ms(&self, phase: Phase, profile: Profile) -> u32 {
+        self(phase, profile)
+    }
+}
+
+/// A gap for each stretch of the flight, whatever the profile.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Rates {
+    pub pad_ms: u32,
+    pub ascent_ms: u32,  // Boost, coast and apogee
+    pub descent_ms: u32, // Under drogue, or nothing
+    pub main_ms: u32,
+    pub landed_ms: u32, // The beacon for finding it
+}
+
+impl Default for Rates {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rates {
+    /// Four a second on the way up, two under drogue and one every 5 s under the main. On the pad and once it's
+    /// down, as slow as the profile goes to save the battery.
+    pub const fn new() -> Self {
+        Rates {
+            pad_ms: 5_000,
+            ascent_ms: 250,
+            descent_ms: 500,
+            main_ms: 5_000,
+            landed_ms: 10_000,
+        }
+    }
+}
+
+impl Policy for Rates {
+    fn interval_ms(&self, phase: Phase, _profile: Profile) -> u32 {
+        match phase {
+            Phase::Pad => self.pad_ms,
+            Phase::Boost | Phase::Coast | Phase::Apogee => self.ascent_ms,
+            Phase::Descent => self.descent_ms,
+            Phase::Main => self.main_ms,
+            Phase::Landed => self.landed_ms,
+        }
+    }
+}
+
+/// Keeps the frames to a policy's pace, against a clock in milliseconds since boot.
+pub struct Cadence<'a> {
+    policy: &'a dyn Policy,
+    listening: bool,          // For the ground station, after every frame
+    last_ms: Option<u64>,     // When the last slot was due
+    announced: Option<Phase>, // In the last telemetry frame
+}
+
+impl<'a> Cadence<'a> {
+    /// `listening` if the vehicle listens for commands after every frame, and so can't send again until it's done.
+    pub const fn new(policy: &'a dyn Policy, listening: bool) -> Self {
+        Cadence {
+            policy,
+            listening,
+            last_ms: None,
+            announced: None,
+        }
+    }
+
+    /// The gap the policy asks for, kept to what `profile` allows on `config`'s channel.
+    pub fn interval_ms(&self, phase: Phase, config: &RadioConfig, profile: Profile) -> u64 {
+        let longest = config.interval_ms(profile);
+        let shortest = (longest / 2)
+            .max(self.busy_ms(config, profile))
+            .min(longest);
+        self.policy
+            .interval_ms(phase, profile)
+            .clamp(shortest, longest) as u64
+    }
+
+    // How long a keyframe keeps the radio to itself, going out and then listening
+    fn busy_ms(&self, config: &RadioConfig, profile: Profile) -> u32 {
+        let modulation = config.modulation(profile);
+        let window_ms = if self.listening {
+            uplink::window_ms(&modulation)
+        } else {
+            0
+        };
+        modulation.airtime_us(KEYFRAME_LEN) / 1_000 + window_ms
+    }
+
+    /// Whether `phase` is news to the ground station, so the next frame has to be telemetry.
+    pub fn changed(&self, phase: Phase) -> bool {
+        self.announced != Some(phase)
+    }
+
+    /// How long from `now_ms` until the next slot for a vehicle in `phase` sending on `profile`, 0 if it's now.
+    pub fn wait_ms(
+        &self,
+        now_ms: u64,
+        phase: Phase,
+        config: &RadioConfig,
+        profile: Profile,
+    ) -> u64 {
+        self.due_ms(phase, config, profile)
+            .map_or(0, |due| due.saturating_sub(now_ms))
+    }
+
+    /// A frame is going out at `now_ms` on `profile`, with the phase in it, or None for parity.
+    pub fn take(
+        &mut self,
+        now_ms: u64,
+        phase: Option<Phase>,
+        config: &RadioConfig,
+        profile: Profile,
+    ) {
+        // Parity carries on from whatever the telemetry before it said
+        let slot = phase.or(self.announced).and_then(|phase| {
+            let due = self.due_ms(phase, config, profile)?;
+            // On time, or not so late that it's better to start over
+            (due..due + self.interval_ms(phase, config, profile))
+                .contains(&now_ms)
+                .then_some(due)
+        });
+
+        self.last_ms = Some(slot.unwrap_or(now_ms));
+        if phase.is_some() {
+            self.announced = phase;
+        }
+    }
+
+    // When the next slot is due, or None for straight away
+    fn due_ms(&self, phase: Phase, config: &RadioConfig, profile: Profile) -> Option<u64> {
+        let last = self.last_ms?;
+        (!self.changed(phase)).then(|| last + self.interval_ms(phase, config, profile))
+    }
+}
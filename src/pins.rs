@@ -2,9 +2,21 @@
 
 use esp_hal::{
     gpio::{AnyPin, Pin},
-    peripherals::{Peripherals, DMA, SPI2, TIMG0, UART0},
+    peripherals::{Peripherals, DMA, I2C0, SPI2, TIMG0, UART0, USB_DEVICE},
 };
 
+pub struct I2cPins {
+    pub sda: AnyPin,
+    pub scl: AnyPin,
+}
+
+pub struct PyroPins {
+    pub drogue_fire: AnyPin,
+    pub drogue_sense: AnyPin,
+    pub main_fire: AnyPin,
+    pub main_sense: AnyPin,
+}
+
 pub struct TxPins {
     pub uart_rx: AnyPin,
     pub uart_tx: AnyPin,
@@ -17,10 +29,18 @@ pub struct TxPins {
     pub lora_miso: AnyPin,
     pub lora_clk: AnyPin,
 
+    pub baro_nss: Option<AnyPin>,  // Shares the LoRa SPI bus, optional
+    pub flash_nss: Option<AnyPin>, // Log flash, also on the LoRa SPI bus
+    pub imu: Option<I2cPins>,      // Not fitted on every revision
+    pub pyro: Option<PyroPins>,    // Not fitted on any revision yet
+    pub pps: Option<AnyPin>,       // GPS timepulse
+
     pub timg: TIMG0,
     pub uart: UART0,
     pub dma: DMA,
     pub spi: SPI2,
+    pub i2c: I2C0,
+    pub usb: USB_DEVICE,
 }
 
 pub struct RxPins {
@@ -35,6 +55,7 @@ pub struct RxPins {
     pub timg: TIMG0,
     pub dma: DMA,
     pub spi: SPI2,
+    pub usb: USB_DEVICE,
 }
 
 pub fn get_tx_pins_v003(p: Peripherals) -> TxPins {
@@ -50,10 +71,18 @@ pub fn get_tx_pins_v003(p: Peripherals) -> TxPins {
         lora_miso: p.GPIO20.degrade(), // Yep
         lora_mosi: p.GPIO10.degrade(), // Yep
 
+        baro_nss: None,
+        flash_nss: None,
+        imu: None,
+        pyro: None,
+        pps: None,
+
         timg: p.TIMG0,
         uart: p.UART0,
         dma: p.DMA,
         spi: p.SPI2,
+        i2c: p.I2C0,
+        usb: p.USB_DEVICE,
     }
 }
 
@@ -70,10 +99,21 @@ pub fn get_tx_pins_v004_bread(p: Peripherals) -> TxPins {
         lora_miso: p.GPIO20.degrade(),
         lora_mosi: p.GPIO10.degrade(),
 
+        baro_nss: Some(p.GPIO3.degrade()),
+        flash_nss: Some(p.GPIO2.degrade()), // Strapping pin, idles high behind a pull-up, see the pin map
+        imu: Some(I2cPins {
+            sda: p.GPIO6.degrade(),
+            scl: p.GPIO7.degrade(),
+        }),
+        pyro: None,
+        pps: Some(p.GPIO0.degrade()),
+
         timg: p.TIMG0,
         uart: p.UART0,
         dma: p.DMA,
         spi: p.SPI2,
+        i2c: p.I2C0,
+        usb: p.USB_DEVICE,
     }
 }
 
@@ -90,6 +130,7 @@ pub fn get_rx_pins_og(p: Peripherals) -> RxPins {
         timg: p.TIMG0,
         dma: p.DMA,
         spi: p.SPI2,
+        usb: p.USB_DEVICE,
     }
 }
 
@@ -106,5 +147,6 @@ pub fn get_rx_pins_v001(p: Peripherals) -> RxPins {
         timg: p.TIMG0,
         dma: p.DMA,
         spi: p.SPI2,
+        usb: p.USB_DEVICE,
     }
 }
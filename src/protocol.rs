@@ -0,0 +1,130 @@
+use crate::{compact, state::State};
+
+// Every telemetry frame on air looks like:
+//
+//   | magic | version | vehicle | seq (LE) | len | payload (compact State) | crc16 (LE) |
+//   |   1   |    1    |    1    |    2     |  1  |           len           |     2      |
+//
+// The CRC covers everything from the magic byte to the end of the payload.
+pub const MAGIC: u8 = 0xA7;
+pub const VERSION: u8 = 2;
+
+pub const HEADER_LEN: usize = 6;
+pub const CRC_LEN: usize = 2;
+pub const MAX_FRAME_LEN: usize = 255; // Largest LoRa payload the SX127x will send
+pub const MAX_PAYLOAD_LEN: usize = MAX_FRAME_LEN - HEADER_LEN - CRC_LEN;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Header {
+    pub vehicle: u8, // Which vehicle sent this frame
+    pub seq: u16,    // Wrapping per-vehicle frame counter
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    BufferTooSmall,
+    Serialize,
+    Truncated,
+    BadMagic(u8),
+    UnsupportedVersion(u8),
+    LengthMismatch,
+    BadCrc,
+    BadTag,     // Authenticated frames only, see auth
+    NoKeyframe, // Telemetry between keyframes that never came, see compact
+    Deserialize,
+}
+
+/// Frames `state` into `buf`, returning the slice that should go on air. `compact` keeps track of the keyframes
+/// for the frames after this one.
+pub fn encode<'a>(
+    header: &Header,
+    state: &State,
+    compact: &mut compact::Encoder,
+    buf: &'a mut [u8],
+) -> Result<&'a [u8], Error> {
+    if buf.len() < HEADER_LEN + CRC_LEN {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let payload_space = (buf.len() - HEADER_LEN - CRC_LEN).min(MAX_PAYLOAD_LEN);
+    let payload_len = compact.encode(
+        header,
+        state,
+        &mut buf[HEADER_LEN..HEADER_LEN + payload_space],
+    )?;
+
+    let seq = header.seq.to_le_bytes();
+    buf[0] = MAGIC;
+    buf[1] = VERSION;
+    buf[2] = header.vehicle;
+    buf[3] = seq[0];
+    buf[4] = seq[1];
+    buf[5] = payload_len as u8;
+
+    let crc_start = HEADER_LEN + payload_len;
+    let crc = crc16(&buf[..crc_start]).to_le_bytes();
+    buf[crc_start] = crc[0];
+    buf[crc_start + 1] = crc[1];
+
+    Ok(&buf[..crc_start + CRC_LEN])
+}
+
+/// Validates and unpacks a received frame. `buf` must be exactly the bytes the radio received, and `compact` has to
+/// have seen the keyframe before it.
+pub fn decode(buf: &[u8], compact: &mut compact::Decoder) -> Result<(Header, State), Error> {
+    let (header, payload) = unframe(buf)?;
+    let state = compact.decode(&header, payload)?;
+
+    Ok((header, state))
+}
+
+/// Checks a received frame's header, length and CRC, returning the header and the payload still to be unpacked.
+pub fn unframe(buf: &[u8]) -> Result<(Header, &[u8]), Error> {
+    if buf.len() < HEADER_LEN + CRC_LEN {
+        return Err(Error::Truncated);
+    }
+
+    if buf[0] != MAGIC {
+        return Err(Error::BadMagic(buf[0]));
+    }
+
+    if buf[1] != VERSION {
+        return Err(Error::UnsupportedVersion(buf[1]));
+    }
+
+    let payload_len = buf[5] as usize;
+    let crc_start = HEADER_LEN + payload_len;
+    if buf.len() != crc_start + CRC_LEN {
+        return Err(Error::LengthMismatch);
+    }
+
+    let crc = u16::from_le_bytes([buf[crc_start], buf[crc_start + 1]]);
+    if crc != crc16(&buf[..crc_start]) {
+        return Err(Error::BadCrc);
+    }
+
+    let header = Header {
+        vehicle: buf[2],
+        seq: u16::from_le_bytes([buf[3], buf[4]]),
+    };
+
+    Ok((header, &buf[HEADER_LEN..crc_start]))
+}
+
+/// CRC-16/CCITT-FALSE (poly 0x1021, init 0xFFFF).
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            if crc & 0x8000 != 0 {
+                crc = (crc << 1) ^ 0x1021;
+            } else {
+                crc <<= 1;
+            }
+        }
+    }
+    crc
+}
@@ -0,0 +1,260 @@
+use core::str::FromStr;
+
+#[cfg(feature = "esp32c3")]
+use defmt::{error, info};
+#[cfg(feature = "esp32c3")]
+use embassy_executor::task;
+#[cfg(feature = "esp32c3")]
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel as CommandChannel,
+};
+#[cfg(feature = "esp32c3")]
+use embassy_time::{Instant, Timer};
+use embedded_hal::digital::{InputPin, OutputPin};
+#[cfg(feature = "esp32c3")]
+use esp_hal::gpio::{AnyPin, Input, Output};
+use serde::{Deserialize, Serialize};
+
+use crate::flight::Phase;
+
+#[cfg(feature = "esp32c3")]
+use crate::state::STATE;
+
+/// What a single pyro channel is doing, as reported in the telemetry.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Status {
+    // No continuity through the e-match, so it won't be fired
+    #[default]
+    Open,
+    Safe,   // Continuity, but we haven't launched yet
+    Armed,  // Continuity and launched, waiting for the deployment event
+    Firing, // Output on
+    Fired,  // Output was held on for the full fire duration
+}
+
+/// Parses the variant name, as written by `Debug`.
+impl FromStr for Status {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "Open" => Ok(Status::Open),
+            "Safe" => Ok(Status::Safe),
+            "Armed" => Ok(Status::Armed),
+            "Firing" => Ok(Status::Firing),
+            "Fired" => Ok(Status::Fired),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Things that can be asked of the pyro channels while they're running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    Arm,    // Let the channels arm at launch, which they do from power on
+    Disarm, // Hold them safe, whatever the flight phase says
+}
+
+#[cfg(feature = "esp32c3")]
+pub static COMMANDS: CommandChannel<CriticalSectionRawMutex, Command, 2> = CommandChannel::new();
+
+pub struct Config {
+    pub main_altitude: f32, // Height above the pad to deploy the main at, on the way down, meters
+    pub fire_ms: u64,       // How long to hold an output on for, milliseconds
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            main_altitude: 150.0,
+            fire_ms: 1_000,
+        }
+    }
+}
+
+/// One deployment charge: an output that fires it, and an input that reads high when there's continuity through it.
+pub struct Channel<FIRE, SENSE> {
+    fire: FIRE,
+    sense: SENSE,
+    status: Status,
+    fired_at: Option<u64>,
+}
+
+impl<FIRE: OutputPin, SENSE: InputPin> Channel<FIRE, SENSE> {
+    pub fn new(fire: FIRE, sense: SENSE) -> Self {
+        Channel {
+            fire,
+            sense,
+            status: Status::Open,
+            fired_at: None,
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    fn update(
+        &mut self,
+        time_ms: u64,
+        armed: bool,
+        deploy: bool,
+        fire_ms: u64,
+    ) -> Result<(), FIRE::Error> {
+        match self.fired_at {
+            // Once a channel has fired it's done, whatever the continuity says afterwards
+            Some(fired_at) if time_ms.saturating_sub(fired_at) >= fire_ms => {
+                self.fire.set_low()?;
+                self.status = Status::Fired;
+            }
+            Some(_) => {}
+            None => {
+                // A sense line we can't read is as good as no continuity
+                let continuity = self.sense.is_high().unwrap_or(false);
+
+                self.status = match (continuity, armed, deploy) {
+                    (false, _, _) => Status::Open,
+                    (true, false, _) => Status::Safe,
+                    (true, true, false) => Status::Armed,
+                    (true, true, true) => {
+                        self.fire.set_high()?;
+                        self.fired_at = Some(time_ms);
+                        Status::Firing
+                    }
+                };
+
+                if self.status != Status::Firing {
+                    self.fire.set_low()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops the output and keeps it from firing. A channel that already fired (even cut short) still says so.
+    fn safe(&mut self) -> Result<(), FIRE::Error> {
+        self.fire.set_low()?;
+        self.status = if self.fired_at.is_some() {
+            Status::Fired
+        } else {
+            Status::Safe
+        };
+
+        Ok(())
+    }
+}
+
+/// Drogue and main deployment. Feed it the flight phase and altitude and it decides when each channel fires.
+pub struct Pyro<FIRE, SENSE> {
+    config: Config,
+    drogue: Channel<FIRE, SENSE>,
+    main: Channel<FIRE, SENSE>,
+    armed: bool,
+    disarmed: bool, // Held safe from the ground
+}
+
+impl<FIRE: OutputPin, SENSE: InputPin> Pyro<FIRE, SENSE> {
+    pub fn new(config: Config, drogue: Channel<FIRE, SENSE>, main: Channel<FIRE, SENSE>) -> Self {
+        Pyro {
+            config,
+            drogue,
+            main,
+            armed: false,
+            disarmed: false,
+        }
+    }
+
+    pub fn armed(&self) -> bool {
+        self.armed && !self.disarmed
+    }
+
+    /// Lets the channels arm once there's been a launch, undoing `disarm`.
+    pub fn arm(&mut self) {
+        self.disarmed = false;
+    }
+
+    /// Holds both channels safe until `arm`. A charge that's already firing is left to finish.
+    pub fn disarm(&mut self) {
+        self.disarmed = true;
+    }
+
+    pub fn drogue(&self) -> Status {
+        self.drogue.status()
+    }
+
+    pub fn main(&self) -> Status {
+        self.main.status()
+    }
+
+    /// Steps both channels, call this often enough that the fire duration is respected.
+    pub fn update(&mut self, time_ms: u64, phase: Phase, altitude: f32) -> Result<(), FIRE::Error> {
+        // Nothing fires until we've seen a launch, and once we have there's no going back
+        if phase >= Phase::Boost {
+            self.armed = true;
+        }
+
+        // On the ground nothing should fire, even if it's armed again or continuity only turns up now
+        if phase == Phase::Landed {
+            self.drogue.safe()?;
+            self.main.safe()?;
+            return Ok(());
+        }
+
+        let armed = self.armed();
+        let past_apogee = (Phase::Apogee..=Phase::Main).contains(&phase);
+        let fire_ms = self.config.fire_ms;
+
+        self.drogue.update(time_ms, armed, past_apogee, fire_ms)?;
+        self.main.update(
+            time_ms,
+            armed,
+            past_apogee && altitude < self.config.main_altitude,
+            fire_ms,
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "esp32c3")]
+#[task]
+pub async fn deploy(
+    drogue: Channel<Output<'static, AnyPin>, Input<'static, AnyPin>>,
+    main: Channel<Output<'static, AnyPin>, Input<'static, AnyPin>>,
+) -> ! {
+    let mut pyro = Pyro::new(Config::default(), drogue, main);
+
+    loop {
+        Timer::after_millis(10).await;
+
+        while let Ok(command) = COMMANDS.try_receive() {
+            info!("Pyro command: {}", command);
+            match command {
+                Command::Arm => pyro.arm(),
+                Command::Disarm => pyro.disarm(),
+            }
+        }
+
+        let mut state = STATE.lock().await;
+
+        // Without a filtered altitude we can't place the main, but the drogue only cares about the phase
+        let altitude = state.fa.unwrap_or(f32::MAX);
+
+        if pyro
+            .update(Instant::now().as_millis(), state.fp, altitude)
+            .is_err()
+        {
+            error!("Pyro output failed");
+        }
+
+        if pyro.drogue() != state.pd || pyro.main() != state.pm {
+            info!("Pyro drogue {:?}, main {:?}", pyro.drogue(), pyro.main());
+        }
+
+        state.pd = pyro.drogue();
+        state.pm = pyro.main();
+    }
+}
@@ -22,7 +22,7 @@ pub fn init(
     sck: AnyPin,
     mosi: AnyPin,
     miso: AnyPin,
-) -> &'static mut Mutex<NoopRawMutex, SpiDmaBus<'static, Async>> {
+) -> &'static Mutex<NoopRawMutex, SpiDmaBus<'static, Async>> {
     let dma = Dma::new(dma);
     let dma_channel = dma.channel0;
 
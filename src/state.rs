@@ -1,16 +1,36 @@
-use defmt::Format;
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
 use serde::{Deserialize, Serialize};
 
+use crate::{flight::Phase, gps, pyro, timesync::Utc};
+
 // Define and setup the system state
-#[derive(Debug, Format, Serialize, Deserialize)]
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct State {
-    pub ln: Option<f32>,  // GPS reported longitude
-    pub lt: Option<f32>,  // GPS reported latitude
-    pub ga: Option<f32>,  // GPS reported altitude, meters
-    pub aaa: Option<f32>, // Altimeter-reported altitude absolute, meters
-    pub aar: Option<f32>, // Altimeter-reported altitude reletive from starting height, delta meters
-    pub t: Option<i32>,   // Time, in HHMMSS (UTC)
+    pub ln: Option<f32>,      // GPS reported longitude
+    pub lt: Option<f32>,      // GPS reported latitude
+    pub ga: Option<f32>,      // GPS reported altitude, meters
+    pub aaa: Option<f32>,     // Altimeter-reported altitude absolute, meters
+    pub aar: Option<f32>,     // Altimeter-reported altitude relative to starting height, meters
+    pub t: Option<Utc>,       // GPS time, UTC to the millisecond
+    pub bt: u64,              // Boot-relative time this snapshot was taken, ms
+    pub fx: gps::Fix,         // GPS fix type, None once the fix is lost
+    pub ns: Option<u8>,       // GPS satellites in use
+    pub hd: Option<f32>,      // GPS horizontal dilution of precision
+    pub gs: Option<f32>,      // GPS ground speed, m/s
+    pub gc: Option<f32>,      // GPS course over ground, degrees true
+    pub ge: u16,              // GPS link errors since boot, see gps::reader::Stats
+    pub fp: Phase,            // Flight phase
+    pub la: Option<[f32; 3]>, // IMU linear acceleration (gravity removed), sensor frame, m/s^2
+    pub gv: Option<[f32; 3]>, // IMU gravity vector, sensor frame, m/s^2
+    pub q: Option<[f32; 4]>,  // IMU orientation quaternion, w x y z
+    pub va: Option<f32>,      // Vertical acceleration, up is positive, m/s^2
+    pub fa: Option<f32>,      // Filtered altitude relative to starting height, meters
+    pub vv: Option<f32>,      // Filtered vertical velocity, up is positive, m/s
+    pub ap: bool,             // Apogee detected by the altitude filter
+    pub pd: pyro::Status,     // Drogue pyro channel
+    pub pm: pyro::Status,     // Main pyro channel
+    pub ak: Option<u32>,      // Counter of the last uplink command taken, see uplink::Inbox
 }
 
 pub static STATE: Mutex<CriticalSectionRawMutex, State> = Mutex::new(State {
@@ -20,4 +40,22 @@ pub static STATE: Mutex<CriticalSectionRawMutex, State> = Mutex::new(State {
     aaa: None,
     aar: None,
     t: None,
+    bt: 0,
+    fx: gps::Fix::None,
+    ns: None,
+    hd: None,
+    gs: None,
+    gc: None,
+    ge: 0,
+    fp: Phase::Pad,
+    la: None,
+    gv: None,
+    q: None,
+    va: None,
+    fa: None,
+    vv: None,
+    ap: false,
+    pd: pyro::Status::Open,
+    pm: pyro::Status::Open,
+    ak: None,
 });
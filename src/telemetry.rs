@@ -0,0 +1,400 @@
+// Line formats the ground station receiver writes to its USB serial port, one line per received packet.
+//
+// Every format carries the same fields in the same order (see `COLUMNS`): the frame header, the radio
+// link quality, then the decoded `State`. Missing values are left empty in CSV and NMEA, and are `null`
+// in JSON.
+//
+//   CSV:  vehicle,seq,rssi,snr,rx,lost,crc,rej,ravg,rmin,savg,smin,gap,fec,t,bt,lt,ln,ga,fx,...,pm,ak
+//         1,42,-87,6,40,2,1,0,-85,-91,7,5,1003,false,2026-04-18T23:59:59.200Z,61200,-41.2865,...,Armed,3
+//   JSON: {"vehicle":1,"seq":42,"rssi":-87,"snr":6,"rx":40,...,"t":"2026-04-18T23:59:59.200Z",...,"ak":3}
+//   NMEA: $PSRTL,1,42,-87,6,40,2,1,0,-85,-91,7,5,1003,false,2026-04-18T23:59:59.200Z,...,Armed,3*HH
+//
+// `rx` to `gap` are the receiver's `link::Summary` as this packet came in: packets received, lost, corrupt and
+// otherwise undecodable since it booted, average and worst RSSI and SNR over the last few packets, and the
+// milliseconds since the one before. `fec` is true for a packet that was never heard, but rebuilt from parity
+// frames (see `lora::fec`). `t` is the GPS time in ISO 8601, `bt` the milliseconds since the vehicle booted.
+//
+// The NMEA sentence is a proprietary one, with the usual XOR checksum over everything between `$` and `*`.
+//
+// The defmt log goes out on the same port, and a line is written a FIFO's worth at a time, so a log frame can land
+// partway through one. Those frames are never text: each starts with 0xFF 0x00 and runs to the next 0x00, with no
+// zero bytes in between, which is how `Demux` takes them back out again before the lines are split.
+use core::{
+    fmt::{self, Debug, Display, Write},
+    str::FromStr,
+};
+
+#[cfg(feature = "esp32c3")]
+use defmt::error;
+#[cfg(feature = "esp32c3")]
+use embassy_executor::task;
+#[cfg(feature = "esp32c3")]
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+#[cfg(feature = "esp32c3")]
+use embedded_io_async::Write as _;
+#[cfg(feature = "esp32c3")]
+use esp_hal::{usb_serial_jtag::UsbSerialJtagTx, Async};
+#[cfg(feature = "esp32c3")]
+use heapless::String;
+
+use crate::{link::Summary, protocol::Header, state::State};
+
+pub const COLUMNS: [&str; 35] = [
+    "vehicle", "seq", "rssi", "snr", "rx", "lost", "crc", "rej", "ravg", "rmin", "savg", "smin",
+    "gap", "fec", "t", "bt", "lt", "ln", "ga", "fx", "ns", "hd", "gs", "gc", "ge", "aaa", "aar",
+    "fp", "va", "fa", "vv", "ap", "pd", "pm", "ak",
+];
+
+pub const NMEA_TALKER: &str = "PSRTL";
+
+// Longest line we'll ever write, JSON with every field present
+#[cfg(feature = "esp32c3")]
+const MAX_LINE_LEN: usize = 624;
+
+/// A decoded frame, plus how well it was heard.
+#[derive(Debug, PartialEq)]
+pub struct Packet {
+    pub header: Header,
+    pub rssi: i16, // dBm
+    pub snr: i16,  // dB
+    pub link: Summary,
+    pub recovered: bool, // Rebuilt from parity rather than heard
+    pub state: State,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Format {
+    Csv,
+    Json,
+    Nmea,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ParseError {
+    UnknownFormat,
+    BadChecksum,
+    FieldCount,
+    BadField(&'static str),
+}
+
+// Room for the frames held back for a lost keyframe, which all come out together once it's rebuilt
+#[cfg(feature = "esp32c3")]
+pub static PACKETS: Channel<CriticalSectionRawMutex, Packet, 8> = Channel::new();
+
+enum Value<'a> {
+    Int(i64),
+    Float(Option<f32>),
+    Bool(bool),
+    Name(&'a dyn Debug), // Enums, written as their variant name
+    Text(&'a dyn Display),
+    Missing,
+}
+
+impl Value<'_> {
+    fn int<T: Into<i64>>(value: Option<T>) -> Self {
+        value.map_or(Value::Missing, |v| Value::Int(v.into()))
+    }
+
+    fn write(&self, out: &mut impl Write, quote: bool) -> fmt::Result {
+        match self {
+            Value::Int(v) => write!(out, "{}", v),
+            // NaN and infinity aren't numbers anything downstream will understand
+            Value::Float(Some(v)) if v.is_finite() => write!(out, "{}", v),
+            Value::Name(v) if quote => write!(out, "\"{:?}\"", v),
+            Value::Name(v) => write!(out, "{:?}", v),
+            Value::Text(v) if quote => write!(out, "\"{}\"", v),
+            Value::Text(v) => write!(out, "{}", v),
+            Value::Bool(v) => write!(out, "{}", v),
+            Value::Float(_) | Value::Missing if quote => out.write_str("null"),
+            Value::Float(_) | Value::Missing => Ok(()),
+        }
+    }
+}
+
+fn values(packet: &Packet) -> [Value<'_>; COLUMNS.len()] {
+    let (link, state) = (&packet.link, &packet.state);
+    [
+        Value::Int(packet.header.vehicle.into()),
+        Value::Int(packet.header.seq.into()),
+        Value::Int(packet.rssi.into()),
+        Value::Int(packet.snr.into()),
+        Value::Int(link.received.into()),
+        Value::Int(link.missed.into()),
+        Value::Int(link.corrupt.into()),
+        Value::Int(link.rejected.into()),
+        Value::Int(link.rssi_avg.into()),
+        Value::Int(link.rssi_min.into()),
+        Value::Int(link.snr_avg.into()),
+        Value::Int(link.snr_min.into()),
+        Value::int(link.gap_ms),
+        Value::Bool(packet.recovered),
+        state.t.as_ref().map_or(Value::Missing, |t| Value::Text(t)),
+        Value::Int(state.bt as i64),
+        Value::Float(state.lt),
+        Value::Float(state.ln),
+        Value::Float(state.ga),
+        Value::Name(&state.fx),
+        Value::int(state.ns),
+        Value::Float(state.hd),
+        Value::Float(state.gs),
+        Value::Float(state.gc),
+        Value::Int(state.ge.into()),
+        Value::Float(state.aaa),
+        Value::Float(state.aar),
+        Value::Name(&state.fp),
+        Value::Float(state.va),
+        Value::Float(state.fa),
+        Value::Float(state.vv),
+        Value::Bool(state.ap),
+        Value::Name(&state.pd),
+        Value::Name(&state.pm),
+        Value::int(state.ak),
+    ]
+}
+
+/// Column names for the top of a CSV file, without a line ending.
+pub fn write_csv_header(out: &mut impl Write) -> fmt::Result {
+    for (i, column) in COLUMNS.iter().enumerate() {
+        if i > 0 {
+            out.write_char(',')?;
+        }
+        out.write_str(column)?;
+    }
+    Ok(())
+}
+
+/// Writes `packet` as a single line in `format`, without a line ending.
+pub fn write_line(packet: &Packet, format: Format, out: &mut impl Write) -> fmt::Result {
+    match format {
+        Format::Csv => write_fields(packet, out),
+        Format::Json => {
+            out.write_char('{')?;
+            for (i, (column, value)) in COLUMNS.iter().zip(values(packet)).enumerate() {
+                if i > 0 {
+                    out.write_char(',')?;
+                }
+                write!(out, "\"{}\":", column)?;
+                value.write(out, true)?;
+            }
+            out.write_char('}')
+        }
+        Format::Nmea => {
+            out.write_char('$')?;
+            let mut checksum = Checksum {
+                out: &mut *out,
+                checksum: 0,
+            };
+            write!(checksum, "{},", NMEA_TALKER)?;
+            write_fields(packet, &mut checksum)?;
+
+            let checksum = checksum.checksum;
+            write!(out, "*{:02X}", checksum)
+        }
+    }
+}
+
+fn write_fields(packet: &Packet, out: &mut impl Write) -> fmt::Result {
+    for (i, value) in values(packet).iter().enumerate() {
+        if i > 0 {
+            out.write_char(',')?;
+        }
+        value.write(out, false)?;
+    }
+    Ok(())
+}
+
+/// Reads back a line written by `write_line`, in any of the formats.
+pub fn parse_line(line: &str) -> Result<Packet, ParseError> {
+    let line = line.trim_end();
+    let mut fields = [""; COLUMNS.len()];
+
+    if let Some(sentence) = line.strip_prefix('$') {
+        let (body, checksum) = sentence.split_once('*').ok_or(ParseError::BadChecksum)?;
+        if u8::from_str_radix(checksum, 16) != Ok(nmea_checksum(body)) {
+            return Err(ParseError::BadChecksum);
+        }
+
+        let body = body
+            .strip_prefix(NMEA_TALKER)
+            .and_then(|body| body.strip_prefix(','))
+            .ok_or(ParseError::UnknownFormat)?;
+        split_fields(body, &mut fields)?;
+    } else if let Some(body) = line.strip_prefix('{').and_then(|l| l.strip_suffix('}')) {
+        // None of our values have commas, colons or escapes in them, so there's no need for a real JSON parser
+        for pair in body.split(',') {
+            let (key, value) = pair.split_once(':').ok_or(ParseError::UnknownFormat)?;
+            let column = COLUMNS
+                .iter()
+                .position(|column| key.trim_matches('"') == *column)
+                .ok_or(ParseError::UnknownFormat)?;
+            fields[column] = match value {
+                "null" => "",
+                value => value.trim_matches('"'),
+            };
+        }
+    } else {
+        split_fields(line, &mut fields)?;
+    }
+
+    let [vehicle, seq, rssi, snr, rx, lost, crc, rej, ravg, rmin, savg, smin, gap, fec, t, bt, lt, ln, ga, fx, ns, hd, gs, gc, ge, aaa, aar, fp, va, fa, vv, ap, pd, pm, ak] =
+        fields;
+
+    Ok(Packet {
+        header: Header {
+            vehicle: required(vehicle, "vehicle")?,
+            seq: required(seq, "seq")?,
+        },
+        rssi: required(rssi, "rssi")?,
+        snr: required(snr, "snr")?,
+        link: Summary {
+            received: required(rx, "rx")?,
+            missed: required(lost, "lost")?,
+            corrupt: required(crc, "crc")?,
+            rejected: required(rej, "rej")?,
+            rssi_avg: required(ravg, "ravg")?,
+            rssi_min: required(rmin, "rmin")?,
+            snr_avg: required(savg, "savg")?,
+            snr_min: required(smin, "smin")?,
+            gap_ms: optional(gap, "gap")?,
+        },
+        recovered: required(fec, "fec")?,
+        state: State {
+            t: optional(t, "t")?,
+            bt: required(bt, "bt")?,
+            lt: optional(lt, "lt")?,
+            ln: optional(ln, "ln")?,
+            ga: optional(ga, "ga")?,
+            fx: required(fx, "fx")?,
+            ns: optional(ns, "ns")?,
+            hd: optional(hd, "hd")?,
+            gs: optional(gs, "gs")?,
+            gc: optional(gc, "gc")?,
+            ge: required(ge, "ge")?,
+            aaa: optional(aaa, "aaa")?,
+            aar: optional(aar, "aar")?,
+            fp: required(fp, "fp")?,
+            va: optional(va, "va")?,
+            fa: optional(fa, "fa")?,
+            vv: optional(vv, "vv")?,
+            ap: required(ap, "ap")?,
+            pd: required(pd, "pd")?,
+            pm: required(pm, "pm")?,
+            ak: optional(ak, "ak")?,
+            ..State::default()
+        },
+    })
+}
+
+fn split_fields<'a>(
+    line: &'a str,
+    fields: &mut [&'a str; COLUMNS.len()],
+) -> Result<(), ParseError> {
+    let mut values = line.split(',');
+    for field in fields.iter_mut() {
+        *field = values.next().ok_or(ParseError::FieldCount)?;
+    }
+
+    match values.next() {
+        Some(_) => Err(ParseError::FieldCount),
+        None => Ok(()),
+    }
+}
+
+fn optional<T: FromStr>(value: &str, column: &'static str) -> Result<Option<T>, ParseError> {
+    match value {
+        "" => Ok(None),
+        value => value
+            .parse()
+            .map(Some)
+            .map_err(|_| ParseError::BadField(column)),
+    }
+}
+
+fn required<T: FromStr>(value: &str, column: &'static str) -> Result<T, ParseError> {
+    optional(value, column)?.ok_or(ParseError::BadField(column))
+}
+
+/// Separates the telemetry lines from the defmt frames mixed in with them, a byte at a time.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Demux {
+    state: DemuxState,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum DemuxState {
+    #[default]
+    Text,
+    Start, // Had the 0xFF a frame starts with
+    Frame,
+}
+
+impl Demux {
+    pub const fn new() -> Self {
+        Demux {
+            state: DemuxState::Text,
+        }
+    }
+
+    /// Hands `byte` back if it's part of a telemetry line, or None if it belongs to a defmt frame.
+    pub fn push(&mut self, byte: u8) -> Option<u8> {
+        match (self.state, byte) {
+            (DemuxState::Frame, 0x00) => self.state = DemuxState::Text,
+            (DemuxState::Frame, _) => {}
+            (DemuxState::Start, 0x00) => self.state = DemuxState::Frame,
+            // A 0xFF on its own isn't text either, so there's nothing to give back for it
+            (_, 0xFF) => self.state = DemuxState::Start,
+            (_, byte) => {
+                self.state = DemuxState::Text;
+                return Some(byte);
+            }
+        }
+        None
+    }
+}
+
+/// XOR of every byte between the `$` and the `*` of an NMEA sentence.
+pub fn nmea_checksum(body: &str) -> u8 {
+    body.bytes().fold(0, |checksum, byte| checksum ^ byte)
+}
+
+// Passes everything through to `out`, keeping a running NMEA checksum as it goes
+struct Checksum<'a, W> {
+    out: &'a mut W,
+    checksum: u8,
+}
+
+impl<W: Write> Write for Checksum<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.checksum ^= nmea_checksum(s);
+        self.out.write_str(s)
+    }
+}
+
+#[cfg(feature = "esp32c3")]
+#[task]
+pub async fn forward(mut usb: UsbSerialJtagTx<'static, Async>, format: Format) -> ! {
+    let mut line: String<MAX_LINE_LEN> = String::new();
+
+    if format == Format::Csv {
+        // Best effort, a spreadsheet opened mid-session just won't get the header
+        let _ = write_csv_header(&mut line);
+        let _ = line.push_str("\r\n");
+        let _ = usb.write_all(line.as_bytes()).await;
+    }
+
+    loop {
+        let packet = PACKETS.receive().await;
+
+        line.clear();
+        if write_line(&packet, format, &mut line).is_err() || line.push_str("\r\n").is_err() {
+            error!("Telemetry line too long, dropping it");
+            continue;
+        }
+
+        if usb.write_all(line.as_bytes()).await.is_err() {
+            error!("USB serial write failed");
+        }
+    }
+}
@@ -0,0 +1,318 @@
+// Disciplines the boot-relative clock against the GNSS timepulse.
+//
+// The receiver raises PPS at the top of every UTC second. Each rising edge is captured against `Instant`, then paired
+// with the UTC second it marks, which turns up a little later in a navigation solution. Successive edges give the
+// drift of our crystal, the latest edge gives the offset, and together they map any instant to UTC far better than
+// the time of the last navigation solution, which turns up with an unknown UART latency.
+//
+// `Utc` is the wall clock time carried in `State`, to the millisecond, with the date.
+use core::{fmt, str::FromStr};
+
+#[cfg(feature = "esp32c3")]
+use defmt::{info, warn};
+#[cfg(feature = "esp32c3")]
+use embassy_executor::task;
+#[cfg(feature = "esp32c3")]
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+#[cfg(feature = "esp32c3")]
+use embassy_time::{Duration, Instant, Timer};
+#[cfg(feature = "esp32c3")]
+use esp_hal::gpio::Input;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "esp32c3")]
+use crate::{gps::Fix, state::STATE};
+
+pub const MS_PER_SECOND: i64 = 1_000;
+pub const MS_PER_DAY: i64 = 86_400 * MS_PER_SECOND;
+pub const US_PER_SECOND: i64 = 1_000_000;
+
+// Days from 0000-03-01 to 1970-01-01, in the proleptic Gregorian calendar
+const UNIX_EPOCH_DAYS: i64 = 719_468;
+
+// Far more than any crystal we'd fit, so anything past it is a glitch or a mispaired edge
+pub const MAX_DRIFT_PPM: f32 = 500.0;
+
+// After this many edges in a row disagree with the reference, it's the reference that's wrong
+const MAX_REJECTED: u8 = 3;
+
+// How much of each new drift measurement to take, the rest is the running estimate
+const DRIFT_GAIN: f32 = 0.25;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    Duplicate, // Same UTC second as the last edge
+    Glitch,    // Interval doesn't match the UTC seconds between edges
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Edge {
+    instant_us: u64, // Boot-relative
+    utc_us: i64,     // Since the Unix epoch
+}
+
+/// Offset and drift of the local clock against UTC, from PPS edges.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Clock {
+    reference: Option<Edge>,
+    drift_ppm: Option<f32>, // Positive when our clock runs fast
+    rejected: u8,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock {
+    pub const fn new() -> Self {
+        Clock {
+            reference: None,
+            drift_ppm: None,
+            rejected: 0,
+        }
+    }
+
+    /// Takes a PPS edge captured at `instant_us`, marking the start of the UTC second `utc_us` (since the epoch).
+    /// Rejected edges leave the estimate alone, unless enough of them in a row say it's the estimate that's wrong.
+    pub fn edge(&mut self, instant_us: u64, utc_us: i64) -> Result<(), Error> {
+        let edge = Edge { instant_us, utc_us };
+
+        let Some(reference) = self.reference else {
+            self.reference = Some(edge);
+            return Ok(());
+        };
+
+        let utc_interval = utc_us - reference.utc_us;
+        let local_interval = instant_us.wrapping_sub(reference.instant_us) as i64;
+
+        let result = if utc_interval == 0 {
+            Err(Error::Duplicate)
+        } else if utc_interval < 0 {
+            Err(Error::Glitch)
+        } else {
+            let ppm = (local_interval - utc_interval) as f32 / utc_interval as f32 * 1e6;
+
+            if ppm.abs() > MAX_DRIFT_PPM {
+                Err(Error::Glitch)
+            } else {
+                self.drift_ppm = Some(match self.drift_ppm {
+                    Some(drift) => drift + (ppm - drift) * DRIFT_GAIN,
+                    None => ppm,
+                });
+                self.reference = Some(edge);
+                self.rejected = 0;
+                Ok(())
+            }
+        };
+
+        if result.is_err() {
+            self.rejected += 1;
+            if self.rejected >= MAX_REJECTED {
+                *self = Clock::new();
+                self.reference = Some(edge);
+            }
+        }
+
+        result
+    }
+
+    /// Synced once two edges have agreed on the drift.
+    pub fn is_synced(&self) -> bool {
+        self.drift_ppm.is_some()
+    }
+
+    pub fn drift_ppm(&self) -> Option<f32> {
+        self.drift_ppm
+    }
+
+    /// UTC for a boot-relative instant, in microseconds since the epoch. Works either side of the latest edge.
+    pub fn to_utc(&self, instant_us: u64) -> Option<i64> {
+        let reference = self.reference?;
+        let drift = self.drift_ppm?;
+
+        let local = instant_us as i64 - reference.instant_us as i64;
+        let correction = (local as f32 * drift * 1e-6) as i64;
+
+        Some(reference.utc_us + local - correction)
+    }
+}
+
+/// A UTC date and time, in milliseconds since the Unix epoch. Written and parsed as ISO 8601,
+/// `2026-04-18T09:27:50.200Z`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Utc(pub i64);
+
+impl Utc {
+    /// None if the date doesn't exist, like the 31st of April.
+    pub fn from_date_time(year: u16, month: u8, day: u8, ms_of_day: u32) -> Option<Self> {
+        if ms_of_day as i64 >= MS_PER_DAY {
+            return None;
+        }
+
+        let days = days_from_civil(year as i64, month as i64, day as i64);
+        let utc = Utc(days * MS_PER_DAY + ms_of_day as i64);
+
+        // Out of range days and months come back as some other date
+        (utc.date() == (year, month, day)).then_some(utc)
+    }
+
+    /// Year, month and day.
+    pub fn date(self) -> (u16, u8, u8) {
+        let (year, month, day) = civil_from_days(self.0.div_euclid(MS_PER_DAY));
+        (year as u16, month as u8, day as u8)
+    }
+
+    pub fn ms_of_day(self) -> u32 {
+        self.0.rem_euclid(MS_PER_DAY) as u32
+    }
+
+    pub fn as_micros(self) -> i64 {
+        self.0 * 1_000
+    }
+
+    /// The start of the second this falls in.
+    pub fn whole_second(self) -> Self {
+        Utc(self.0 - self.0.rem_euclid(MS_PER_SECOND))
+    }
+
+    /// The same date at another time of day, for sentences that only carry the time. If that's more than half a day
+    /// away it's taken to be the day before or after, so a GGA just past midnight lands on the new date.
+    pub fn with_time_of_day(self, ms_of_day: u32) -> Self {
+        let midnight = self.0 - self.ms_of_day() as i64;
+        let mut utc = midnight + ms_of_day as i64;
+
+        if utc - self.0 > MS_PER_DAY / 2 {
+            utc -= MS_PER_DAY;
+        } else if self.0 - utc > MS_PER_DAY / 2 {
+            utc += MS_PER_DAY;
+        }
+
+        Utc(utc)
+    }
+}
+
+impl fmt::Display for Utc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day) = self.date();
+        let ms = self.ms_of_day();
+
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            year,
+            month,
+            day,
+            ms / 3_600_000,
+            ms / 60_000 % 60,
+            ms / 1_000 % 60,
+            ms % 1_000
+        )
+    }
+}
+
+/// Parses exactly what `Display` writes.
+impl FromStr for Utc {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let b = s.as_bytes();
+        if b.len() != 24 || !s.is_ascii() {
+            return Err(());
+        }
+
+        let separators = [
+            (4, b'-'),
+            (7, b'-'),
+            (10, b'T'),
+            (13, b':'),
+            (16, b':'),
+            (19, b'.'),
+            (23, b'Z'),
+        ];
+        if separators.iter().any(|(i, c)| b[*i] != *c) {
+            return Err(());
+        }
+
+        let number = |range: core::ops::Range<usize>| s[range].parse::<u32>().map_err(|_| ());
+        let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+        if hour > 23 || minute > 59 || second > 59 {
+            return Err(());
+        }
+
+        Utc::from_date_time(
+            number(0..4)? as u16,
+            number(5..7)? as u8,
+            number(8..10)? as u8,
+            ((hour * 60 + minute) * 60 + second) * 1_000 + number(20..23)?,
+        )
+        .ok_or(())
+    }
+}
+
+// Howard Hinnant's days_from_civil and civil_from_days, see http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - UNIX_EPOCH_DAYS
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + UNIX_EPOCH_DAYS;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(feature = "esp32c3")]
+pub static CLOCK: Mutex<CriticalSectionRawMutex, Clock> = Mutex::new(Clock::new());
+
+/// UTC for `instant`, in microseconds since the epoch, once the clock has synced.
+#[cfg(feature = "esp32c3")]
+pub async fn utc(instant: Instant) -> Option<i64> {
+    CLOCK.lock().await.to_utc(instant.as_micros())
+}
+
+#[cfg(feature = "esp32c3")]
+#[task]
+pub async fn discipline(mut pps: Input<'static>) -> ! {
+    loop {
+        pps.wait_for_rising_edge().await;
+        let instant = Instant::now();
+
+        // The solution for the second this edge starts lands within a few hundred ms, so by halfway through the
+        // second the time in the state is the one the edge belongs to
+        Timer::at(instant + Duration::from_millis(500)).await;
+
+        let time = {
+            let state = STATE.lock().await;
+            match (state.fx, state.t) {
+                (Fix::None, _) | (_, None) => continue,
+                (_, Some(t)) => t.whole_second(),
+            }
+        };
+
+        let mut clock = CLOCK.lock().await;
+        let synced = clock.is_synced();
+        match clock.edge(instant.as_micros(), time.as_micros()) {
+            Ok(()) if !synced && clock.is_synced() => {
+                info!("Clock synced to PPS, drift {} ppm", clock.drift_ppm())
+            }
+            Ok(()) => {}
+            Err(e) => warn!("PPS edge rejected: {}", e),
+        }
+    }
+}
@@ -0,0 +1,389 @@
+// Commands from the ground station up to a vehicle, over the same radio link as the telemetry.
+//
+// The link is half duplex. After every packet it sends, the vehicle listens for `window_ms` on the same profile. A
+// ground station with a command waiting for that vehicle sends it `TURNAROUND_MS` after hearing the packet, which
+// gives the vehicle time to switch over. The vehicle acknowledges by carrying the command's counter in `State::ak`
+// from its next packet on, and the ground station keeps resending until it sees that or gives up. Both ends save the
+// counter, so an ack from before either of them rebooted can't be taken for one of a new command.
+//
+// Every command frame on air looks like:
+//
+//   | magic | version | vehicle | counter (LE) | len | payload (postcard Command) | tag |
+//   |   1   |    1    |    1    |      4       |  1  |            len             |  8  |
+//
+// The tag is a truncated HMAC over everything from the magic byte to the end of the payload, and the counter goes up
+// with every new command, see `auth`. The tag stands in for a CRC, a frame damaged on the way fails it too.
+use core::str::FromStr;
+
+#[cfg(feature = "esp32c3")]
+use defmt::{error, info, warn};
+#[cfg(feature = "esp32c3")]
+use embassy_executor::task;
+#[cfg(feature = "esp32c3")]
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+#[cfg(feature = "esp32c3")]
+use embedded_io_async::Read;
+#[cfg(feature = "esp32c3")]
+use esp_hal::{usb_serial_jtag::UsbSerialJtagRx, Async};
+#[cfg(feature = "esp32c3")]
+use heapless::Vec;
+use postcard::{from_bytes, to_slice};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::{Key, TAG_LEN},
+    lora::{Modulation, Profile},
+    protocol::{Error, VERSION},
+};
+#[cfg(feature = "esp32c3")]
+use crate::{logger, pyro};
+
+pub const MAGIC: u8 = 0xA8;
+
+pub const HEADER_LEN: usize = 8;
+pub const MAX_FRAME_LEN: usize = 24;
+
+// How long the ground station waits after hearing a packet before it answers, the vehicle needs a moment to go
+// from sending to listening
+pub const TURNAROUND_MS: u32 = 50;
+
+// Slack on the end of the vehicle's window, for the ground station being slow to start sending
+const GUARD_MS: u32 = 100;
+
+// How many times the ground station sends a command without hearing it acknowledged
+pub const MAX_ATTEMPTS: u8 = 5;
+
+// Longest line the ground station takes a command from
+#[cfg(feature = "esp32c3")]
+const MAX_LINE_LEN: usize = 32;
+
+// The key both ends share, from `UPLINK_KEY` (64 hex digits) when the firmware is built. Without one the ground
+// station won't send commands and the vehicle won't take them.
+#[cfg(feature = "esp32c3")]
+pub const KEY: Option<Key> = match option_env!("UPLINK_KEY") {
+    Some(hex) => match Key::from_hex(hex) {
+        Some(key) => Some(key),
+        None => panic!("UPLINK_KEY has to be 64 hex digits"),
+    },
+    None => None,
+};
+
+/// Something the ground station can ask of a vehicle.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command {
+    Ping,                     // Does nothing, but still gets acknowledged
+    Arm,                      // Let the pyro channels arm at launch
+    Disarm,                   // Hold the pyro channels safe
+    StartLogging,             // Log to flash from now on, not just after launch
+    Profile(Option<Profile>), // Hold a radio profile, or None to follow the flight phase again
+}
+
+/// Parses the variant name, as written by `Debug`, then the profile name for `Profile`. `Profile` on its own goes
+/// back to following the flight phase.
+impl FromStr for Command {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s.split_once(' ') {
+            Some(("Profile", profile)) => Ok(Command::Profile(Some(profile.trim().parse()?))),
+            Some(_) => Err(()),
+            None => match s {
+                "Ping" => Ok(Command::Ping),
+                "Arm" => Ok(Command::Arm),
+                "Disarm" => Ok(Command::Disarm),
+                "StartLogging" => Ok(Command::StartLogging),
+                "Profile" => Ok(Command::Profile(None)),
+                _ => Err(()),
+            },
+        }
+    }
+}
+
+/// A command addressed to one vehicle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Uplink {
+    pub vehicle: u8,
+    pub counter: u32, // Never goes back, the same every time it's resent
+    pub command: Command,
+}
+
+/// A line typed at the ground station: the vehicle, then the command, like `1 Profile Beacon`.
+pub fn parse_request(line: &str) -> Option<(u8, Command)> {
+    let (vehicle, command) = line.trim().split_once(' ')?;
+    Some((vehicle.parse().ok()?, command.trim().parse().ok()?))
+}
+
+/// Frames `uplink` into `buf` and signs it with `key`, returning the slice that should go on air.
+pub fn encode<'a>(uplink: &Uplink, key: &Key, buf: &'a mut [u8]) -> Result<&'a [u8], Error> {
+    if buf.len() < HEADER_LEN + TAG_LEN {
+        return Err(Error::BufferTooSmall);
+    }
+
+    let payload_space =
+        (buf.len() - HEADER_LEN - TAG_LEN).min(MAX_FRAME_LEN - HEADER_LEN - TAG_LEN);
+    let payload_len = to_slice(
+        &uplink.command,
+        &mut buf[HEADER_LEN..HEADER_LEN + payload_space],
+    )
+    .map_err(|_| Error::Serialize)?
+    .len();
+
+    buf[0] = MAGIC;
+    buf[1] = VERSION;
+    buf[2] = uplink.vehicle;
+    buf[3..7].copy_from_slice(&uplink.counter.to_le_bytes());
+    buf[7] = payload_len as u8;
+
+    let tag_start = HEADER_LEN + payload_len;
+    let tag = key.tag(&buf[..tag_start]);
+    buf[tag_start..tag_start + TAG_LEN].copy_from_slice(&tag);
+
+    Ok(&buf[..tag_start + TAG_LEN])
+}
+
+/// Validates and unpacks a received command frame, checking it was signed with `key`. `buf` must be exactly the
+/// bytes the radio received. Whether the counter is new is up to the `Inbox`.
+pub fn decode(buf: &[u8], key: &Key) -> Result<Uplink, Error> {
+    if buf.len() < HEADER_LEN + TAG_LEN {
+        return Err(Error::Truncated);
+    }
+
+    if buf[0] != MAGIC {
+        return Err(Error::BadMagic(buf[0]));
+    }
+
+    if buf[1] != VERSION {
+        return Err(Error::UnsupportedVersion(buf[1]));
+    }
+
+    let tag_start = HEADER_LEN + buf[7] as usize;
+    if buf.len() != tag_start + TAG_LEN {
+        return Err(Error::LengthMismatch);
+    }
+
+    if !key.verify(&buf[..tag_start], &buf[tag_start..]) {
+        return Err(Error::BadTag);
+    }
+
+    let command = from_bytes(&buf[HEADER_LEN..tag_start]).map_err(|_| Error::Deserialize)?;
+
+    Ok(Uplink {
+        vehicle: buf[2],
+        counter: u32::from_le_bytes([buf[3], buf[4], buf[5], buf[6]]),
+        command,
+    })
+}
+
+/// How long the vehicle listens after each packet: long enough for the ground station to turn around and get the
+/// biggest command frame across.
+pub fn window_ms(modulation: &Modulation) -> u32 {
+    TURNAROUND_MS + modulation.airtime_us(MAX_FRAME_LEN) / 1_000 + GUARD_MS
+}
+
+/// Why the vehicle didn't act on a command that decoded fine.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Reject {
+    NotOurs,  // For another vehicle
+    Resent,   // The last one again, done already but the ground station hasn't heard
+    Replayed, // Older than the last one, someone's playing back a recording
+}
+
+/// The vehicle's side: which commands to act on, and what to acknowledge.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Inbox {
+    vehicle: u8,
+    counter: u32,
+}
+
+impl Inbox {
+    /// `counter` is the last one acted on, as saved before the reboot, or 0.
+    pub const fn new(vehicle: u8, counter: u32) -> Self {
+        Inbox { vehicle, counter }
+    }
+
+    /// The command to act on, if `uplink` is for us and newer than anything we've acted on.
+    pub fn accept(&mut self, uplink: &Uplink) -> Result<Command, Reject> {
+        if uplink.vehicle != self.vehicle {
+            return Err(Reject::NotOurs);
+        }
+
+        if uplink.counter == self.counter {
+            return Err(Reject::Resent);
+        }
+
+        if uplink.counter < self.counter {
+            return Err(Reject::Replayed);
+        }
+
+        self.counter = uplink.counter;
+        Ok(uplink.command)
+    }
+
+    /// The counter to put in `State::ak`. Carries on after a reboot, so a resend that was on its way still gets
+    /// acknowledged.
+    pub fn ack(&self) -> Option<u32> {
+        // The ground station starts from 1
+        (self.counter != 0).then_some(self.counter)
+    }
+
+    /// The counter to save, so the same commands aren't taken again after a reboot.
+    pub fn counter(&self) -> u32 {
+        self.counter
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Pending {
+    uplink: Uplink,
+    attempts: u8,
+}
+
+/// The ground station's side: one command at a time, sent whenever its vehicle is heard until it's acknowledged.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Outbox {
+    pending: Option<Pending>,
+    counter: u32,
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Outbox {
+    pub const fn new() -> Self {
+        Outbox {
+            pending: None,
+            counter: 0,
+        }
+    }
+
+    /// Carries on from `counter`, the last one saved before the reboot. The vehicle ignores anything at or below
+    /// the last counter it saw, and an ack it's still sending for that one isn't for anything queued from now on.
+    pub fn resume(&mut self, counter: u32) {
+        self.counter = self.counter.max(counter);
+    }
+
+    /// Queues `command` for `vehicle`, or None if there's already one waiting.
+    pub fn queue(&mut self, vehicle: u8, command: Command) -> Option<Uplink> {
+        if self.pending.is_some() {
+            return None;
+        }
+
+        self.counter = self.counter.saturating_add(1);
+        let uplink = Uplink {
+            vehicle,
+            counter: self.counter,
+            command,
+        };
+        self.pending = Some(Pending {
+            uplink,
+            attempts: 0,
+        });
+
+        Some(uplink)
+    }
+
+    pub fn pending(&self) -> Option<Uplink> {
+        self.pending.map(|pending| pending.uplink)
+    }
+
+    /// After hearing a packet from `vehicle` that carried `ack`. Returns the command it acknowledged, if that's the
+    /// one we were waiting on.
+    pub fn acked(&mut self, vehicle: u8, ack: Option<u32>) -> Option<Uplink> {
+        let uplink = self.pending()?;
+        if uplink.vehicle != vehicle || ack != Some(uplink.counter) {
+            return None;
+        }
+
+        self.pending = None;
+        Some(uplink)
+    }
+
+    /// What to send now that `vehicle` is listening, if anything. Gives up on a command after `MAX_ATTEMPTS`.
+    pub fn due(&mut self, vehicle: u8) -> Option<Uplink> {
+        let pending = self.pending.as_mut()?;
+        if pending.uplink.vehicle != vehicle {
+            return None;
+        }
+
+        if pending.attempts >= MAX_ATTEMPTS {
+            self.pending = None;
+            return None;
+        }
+
+        pending.attempts += 1;
+        Some(pending.uplink)
+    }
+}
+
+#[cfg(feature = "esp32c3")]
+pub static OUTBOX: Mutex<CriticalSectionRawMutex, Outbox> = Mutex::new(Outbox::new());
+
+/// Hands a command the vehicle has taken to whichever task looks after it. Profile changes aren't handled here,
+/// the radio has to hold off on those until the acknowledgement has gone out.
+#[cfg(feature = "esp32c3")]
+pub fn dispatch(command: Command) {
+    let sent = match command {
+        Command::Ping | Command::Profile(_) => Ok(()),
+        Command::Arm => pyro::COMMANDS.try_send(pyro::Command::Arm).map_err(drop),
+        Command::Disarm => pyro::COMMANDS.try_send(pyro::Command::Disarm).map_err(drop),
+        Command::StartLogging => logger::COMMANDS
+            .try_send(logger::Command::Start)
+            .map_err(drop),
+    };
+
+    if sent.is_err() {
+        error!("Dropping {}, too many commands waiting", command);
+    }
+}
+
+/// Takes commands typed into the ground station's USB serial port, one per line, see `parse_request`.
+#[cfg(feature = "esp32c3")]
+#[task]
+pub async fn listen(mut usb: UsbSerialJtagRx<'static, Async>) -> ! {
+    let mut read_buffer = [0u8; 16];
+    let mut line: Vec<u8, MAX_LINE_LEN> = Vec::new();
+
+    loop {
+        let Ok(len) = usb.read(&mut read_buffer).await else {
+            error!("USB serial read failed");
+            continue;
+        };
+
+        for byte in &read_buffer[..len] {
+            if *byte != b'\n' && *byte != b'\r' {
+                if line.push(*byte).is_err() {
+                    warn!("Command line too long, dropping it");
+                    line.clear();
+                }
+                continue;
+            }
+
+            // The other half of a CRLF
+            if line.is_empty() {
+                continue;
+            }
+
+            let request = core::str::from_utf8(&line).ok().and_then(parse_request);
+            line.clear();
+
+            if KEY.is_none() {
+                warn!("Built without UPLINK_KEY, can't send commands");
+                continue;
+            }
+
+            match request {
+                Some((vehicle, command)) => match OUTBOX.lock().await.queue(vehicle, command) {
+                    Some(uplink) => info!("Queued {}", uplink),
+                    None => warn!("Still waiting on the last command, try again later"),
+                },
+                None => warn!("Couldn't make sense of that command"),
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use stack_ripper::altimeter::{crc4, pressure_altitude, Calibration, Reading};
+
+// Example PROM from the MS5611 datasheet, with the CRC in the last word worked out per AN520
+const DATASHEET_PROM: [u16; 8] = [0, 40127, 36924, 23317, 23282, 33464, 28312, 0];
+
+fn with_crc(mut prom: [u16; 8]) -> [u16; 8] {
+    prom[7] |= crc4(&prom) as u16;
+    prom
+}
+
+#[test]
+fn crc4_matches_an520_example() {
+    let prom = [
+        0x3132, 0x3334, 0x3536, 0x3738, 0x3940, 0x4142, 0x4344, 0x450B,
+    ];
+    assert_eq!(crc4(&prom), 0x0B);
+    assert!(Calibration::from_prom(&prom).is_some());
+}
+
+#[test]
+fn rejects_corrupted_prom() {
+    let mut prom = with_crc(DATASHEET_PROM);
+    prom[3] ^= 0x0100;
+    assert_eq!(Calibration::from_prom(&prom), None);
+}
+
+#[test]
+fn compensates_datasheet_example() {
+    let calibration = Calibration::from_prom(&with_crc(DATASHEET_PROM)).unwrap();
+
+    assert_eq!(
+        calibration.compensate(9_085_466, 8_569_150),
+        Reading {
+            pressure_pa: 100_009,
+            temperature_centi_c: 2007,
+        }
+    );
+}
+
+#[test]
+fn applies_second_order_compensation_when_cold() {
+    let calibration = Calibration::from_prom(&with_crc(DATASHEET_PROM)).unwrap();
+
+    // Same pressure ADC reading, but a D2 that works out just below freezing
+    let cold = calibration.compensate(9_085_466, 8_000_000);
+    assert!((-100..0).contains(&cold.temperature_centi_c));
+    assert!((90_000..100_009).contains(&cold.pressure_pa));
+}
+
+#[test]
+fn altitude_follows_the_standard_atmosphere() {
+    assert!(pressure_altitude(101_325.0).abs() < 0.1);
+    assert!((pressure_altitude(89_874.6) - 1_000.0).abs() < 1.0);
+    assert!((pressure_altitude(54_019.9) - 5_000.0).abs() < 1.0);
+}
@@ -0,0 +1,222 @@
+mod common;
+
+use common::FakeFlash;
+use embedded_storage::nor_flash::NorFlash;
+use stack_ripper::{
+    auth::{
+        decode_counter, encode_counter, hmac_sha256, load_counter, save_counter, CounterError, Key,
+        COUNTER_ADDRESS, COUNTER_MAGIC, COUNTER_RECORD_LEN, TAG_LEN,
+    },
+    protocol::Error,
+};
+
+fn hex(digits: &str) -> Vec<u8> {
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+fn test_key() -> Key {
+    Key::new(core::array::from_fn(|i| i as u8))
+}
+
+#[test]
+fn hmac_matches_rfc_4231() {
+    // Test case 1
+    assert_eq!(
+        hmac_sha256(&[0x0b; 20], b"Hi There").to_vec(),
+        hex("b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7")
+    );
+
+    // Test case 2, a key shorter than the hash
+    assert_eq!(
+        hmac_sha256(b"Jefe", b"what do ya want for nothing?").to_vec(),
+        hex("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843")
+    );
+
+    // Test case 5, truncated to 128 bits
+    assert_eq!(
+        hmac_sha256(&[0x0c; 20], b"Test With Truncation")[..16].to_vec(),
+        hex("a3b6167473100ee06e0c796c2955552b")
+    );
+
+    // Test case 6, a key longer than the block size
+    assert_eq!(
+        hmac_sha256(
+            &[0xaa; 131],
+            b"Test Using Larger Than Block-Size Key - Hash Key First"
+        )
+        .to_vec(),
+        hex("60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54")
+    );
+}
+
+#[test]
+fn tag_is_the_front_of_the_hmac() {
+    let key = test_key();
+    let bytes: Vec<u8> = (0..32).collect();
+
+    assert_eq!(key.tag(b"stack-ripper").to_vec(), hex("ead47ca3d5ec4df4"));
+    assert_eq!(
+        key.tag(b"stack-ripper"),
+        hmac_sha256(&bytes, b"stack-ripper")[..TAG_LEN]
+    );
+}
+
+#[test]
+fn verify_rejects_forgeries() {
+    let key = test_key();
+    let tag = key.tag(b"1 Arm");
+    assert!(key.verify(b"1 Arm", &tag));
+
+    // Someone else's message, someone else's key, or a tag that's been tampered with
+    assert!(!key.verify(b"2 Arm", &tag));
+    assert!(!Key::new([0x55; 32]).verify(b"1 Arm", &tag));
+    for i in 0..TAG_LEN {
+        let mut forged = tag;
+        forged[i] ^= 0x80;
+        assert!(!key.verify(b"1 Arm", &forged));
+    }
+
+    // Only the whole tag will do
+    assert!(!key.verify(b"1 Arm", &tag[..TAG_LEN - 1]));
+    assert!(!key.verify(b"1 Arm", &[]));
+}
+
+#[test]
+fn parses_hex_keys() {
+    let digits = "000102030405060708090a0b0c0d0e0f101112131415161718191A1B1C1D1E1F";
+    let key = Key::from_hex(digits).unwrap();
+    assert_eq!(key.tag(b"stack-ripper"), test_key().tag(b"stack-ripper"));
+
+    assert!(Key::from_hex(&digits[..62]).is_none());
+    assert!(Key::from_hex(&digits.replace('0', "g")).is_none());
+    assert!(Key::from_hex("").is_none());
+}
+
+#[test]
+fn counter_record_round_trip() {
+    let record = encode_counter(0x1234_5678);
+    assert_eq!(record[0], COUNTER_MAGIC);
+    assert_eq!(decode_counter(&record), Ok(0x1234_5678));
+
+    let mut corrupt = record;
+    corrupt[2] ^= 0x01;
+    assert_eq!(decode_counter(&corrupt), Err(Error::BadCrc));
+
+    assert_eq!(decode_counter(&[0xFF; 8]), Err(Error::BadMagic(0xFF)));
+}
+
+#[test]
+fn counter_survives_in_flash() {
+    let mut flash = FakeFlash::new();
+    assert_eq!(load_counter(&mut flash, COUNTER_ADDRESS), Ok(None));
+
+    save_counter(&mut flash, COUNTER_ADDRESS, 7).unwrap();
+    assert_eq!(load_counter(&mut flash, COUNTER_ADDRESS), Ok(Some(7)));
+
+    save_counter(&mut flash, COUNTER_ADDRESS, 8).unwrap();
+    assert_eq!(load_counter(&mut flash, COUNTER_ADDRESS), Ok(Some(8)));
+}
+
+#[test]
+fn counter_only_erases_once_the_sector_is_full() {
+    let mut flash = FakeFlash::new();
+    let slots = FakeFlash::ERASE_SIZE / COUNTER_RECORD_LEN;
+
+    for counter in 1..=slots as u32 {
+        save_counter(&mut flash, COUNTER_ADDRESS, counter).unwrap();
+        assert_eq!(load_counter(&mut flash, COUNTER_ADDRESS), Ok(Some(counter)));
+    }
+    assert_eq!(flash.erases, 0);
+
+    // Carries on in the other sector, and only then erases the full one
+    save_counter(&mut flash, COUNTER_ADDRESS, slots as u32 + 1).unwrap();
+    assert_eq!(flash.erases, 1);
+    assert_eq!(
+        load_counter(&mut flash, COUNTER_ADDRESS),
+        Ok(Some(slots as u32 + 1))
+    );
+}
+
+#[test]
+fn counter_cut_short_leaves_the_one_before() {
+    let mut flash = FakeFlash::new();
+    save_counter(&mut flash, COUNTER_ADDRESS, 7).unwrap();
+
+    // Lost power halfway through writing the next one
+    let record = encode_counter(8);
+    flash
+        .write(COUNTER_ADDRESS + COUNTER_RECORD_LEN as u32, &record[..4])
+        .unwrap();
+    assert_eq!(load_counter(&mut flash, COUNTER_ADDRESS), Ok(Some(7)));
+
+    // And the one after goes past it
+    save_counter(&mut flash, COUNTER_ADDRESS, 9).unwrap();
+    assert_eq!(load_counter(&mut flash, COUNTER_ADDRESS), Ok(Some(9)));
+}
+
+#[test]
+fn counter_fills_both_sectors_in_turn() {
+    let mut flash = FakeFlash::new();
+    let slots = (FakeFlash::ERASE_SIZE / COUNTER_RECORD_LEN) as u32;
+
+    for counter in 1..=3 * slots {
+        save_counter(&mut flash, COUNTER_ADDRESS, counter).unwrap();
+        assert_eq!(load_counter(&mut flash, COUNTER_ADDRESS), Ok(Some(counter)));
+    }
+    assert_eq!(flash.erases, 2);
+}
+
+#[test]
+fn counter_survives_losing_power_while_moving_sectors() {
+    let slots = (FakeFlash::ERASE_SIZE / COUNTER_RECORD_LEN) as u32;
+
+    // Once with the other sector blank, and once with a stale one a reboot stopped being erased
+    for (filled, stale) in [(slots, false), (2 * slots, true)] {
+        // Cut after each of the erases and writes the move takes, and without a cut at all
+        for cut in 0..4 {
+            let mut flash = FakeFlash::new();
+            for counter in 1..=filled {
+                save_counter(&mut flash, COUNTER_ADDRESS, counter).unwrap();
+            }
+            if stale {
+                flash
+                    .write(COUNTER_ADDRESS, &encode_counter(filled - slots))
+                    .unwrap();
+            }
+
+            flash.cut = Some(cut);
+            let saved = save_counter(&mut flash, COUNTER_ADDRESS, filled + 1);
+            flash.cut = None;
+
+            // Never back to nothing, and never behind where it was
+            let loaded = load_counter(&mut flash, COUNTER_ADDRESS).unwrap().unwrap();
+            if saved.is_ok() {
+                assert_eq!(loaded, filled + 1);
+            } else {
+                assert!(loaded >= filled, "cut {cut}: loaded {loaded}");
+            }
+
+            save_counter(&mut flash, COUNTER_ADDRESS, filled + 2).unwrap();
+            assert_eq!(
+                load_counter(&mut flash, COUNTER_ADDRESS),
+                Ok(Some(filled + 2))
+            );
+        }
+    }
+}
+
+#[test]
+fn counter_that_was_written_but_reads_bad_is_corrupt() {
+    let mut flash = FakeFlash::new();
+    let mut record = encode_counter(7);
+    record[2] ^= 0x01;
+    flash.write(COUNTER_ADDRESS, &record).unwrap();
+
+    assert_eq!(
+        load_counter(&mut flash, COUNTER_ADDRESS),
+        Err(CounterError::Corrupt)
+    );
+}
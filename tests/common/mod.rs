@@ -0,0 +1,181 @@
+// Shared synthetic flight data for the host tests
+#![allow(dead_code)]
+
+use embedded_storage::nor_flash::{ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash};
+
+pub const G: f32 = 9.81;
+pub const SAMPLE_MS: u64 = 50;
+
+pub struct Point {
+    pub time_ms: u64,
+    pub altitude: f32,
+    pub acceleration: f32,
+}
+
+// A simple synthetic flight: 5s on the pad, a 2s burn at ~6g, a drag-free coast, 25 m/s under drogue down to
+// 150m, 6 m/s under main and then 10s sitting on the ground. Apogee is at ~854m, 19.2s after boot.
+pub fn synthetic_flight() -> Vec<Point> {
+    let dt = SAMPLE_MS as f32 / 1000.0;
+    let mut points = Vec::new();
+    let (mut altitude, mut velocity) = (0.0f32, 0.0f32);
+    let mut landed_at: Option<f32> = None;
+
+    for i in 0.. {
+        let t = i as f32 * dt;
+        let terminal = if altitude > 150.0 { -25.0 } else { -6.0 };
+        let acceleration = match t {
+            t if t < 5.0 => 0.0,
+            t if t < 7.0 => 60.0,
+            _ if landed_at.is_none() && velocity > terminal => -G,
+            _ => 0.0,
+        };
+
+        if landed_at.is_none() {
+            velocity = (velocity + acceleration * dt).max(terminal);
+            altitude += velocity * dt;
+        }
+
+        if t > 7.0 && altitude <= 0.0 {
+            altitude = 0.0;
+            landed_at.get_or_insert(t);
+        }
+
+        points.push(Point {
+            time_ms: i * SAMPLE_MS,
+            altitude,
+            acceleration,
+        });
+
+        if landed_at.is_some_and(|landed| t > landed + 10.0) {
+            break;
+        }
+    }
+
+    points
+}
+
+// Small deterministic LCG so the noisy runs are repeatable
+pub fn noise(seed: &mut u32, amplitude: f32) -> f32 {
+    *seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+    ((*seed >> 8) as f32 / (1u32 << 24) as f32 - 0.5) * 2.0 * amplitude
+}
+
+// Roughly normal noise with the given standard deviation, from the sum of four uniform samples
+pub fn gaussian(seed: &mut u32, sigma: f32) -> f32 {
+    let sum: f32 = (0..4).map(|_| noise(seed, 1.0)).sum();
+    sum * sigma / 1.1547
+}
+
+pub fn apogee(points: &[Point]) -> &Point {
+    points
+        .iter()
+        .max_by(|a, b| a.altitude.total_cmp(&b.altitude))
+        .unwrap()
+}
+
+// The start of the ESP32's flash, NVS partition included, with the same alignment rules as the real driver
+pub struct FakeFlash {
+    memory: Vec<u8>,
+    pub erases: usize,      // Sectors erased so far, they only take so many
+    pub cut: Option<usize>, // Erases and writes left before the power goes, and none get through after
+}
+
+impl FakeFlash {
+    pub fn new() -> Self {
+        FakeFlash {
+            memory: vec![0xFF; 16 * Self::ERASE_SIZE],
+            erases: 0,
+            cut: None,
+        }
+    }
+
+    fn powered(&mut self) -> Result<(), NorFlashErrorKind> {
+        match &mut self.cut {
+            Some(0) => Err(NorFlashErrorKind::Other),
+            Some(left) => {
+                *left -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+fn aligned(a: usize, b: usize, size: usize) -> bool {
+    a.is_multiple_of(size) && b.is_multiple_of(size)
+}
+
+impl ErrorType for FakeFlash {
+    type Error = NorFlashErrorKind;
+}
+
+impl ReadNorFlash for FakeFlash {
+    const READ_SIZE: usize = 4;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        if !aligned(offset as usize, bytes.len(), Self::READ_SIZE) {
+            return Err(NorFlashErrorKind::NotAligned);
+        }
+        let start = offset as usize;
+        bytes.copy_from_slice(&self.memory[start..start + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.memory.len()
+    }
+}
+
+impl NorFlash for FakeFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = 4096;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if !aligned(from as usize, to as usize, Self::ERASE_SIZE) {
+            return Err(NorFlashErrorKind::NotAligned);
+        }
+        self.powered()?;
+        self.memory[from as usize..to as usize].fill(0xFF);
+        self.erases += (to - from) as usize / Self::ERASE_SIZE;
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        if !aligned(offset as usize, bytes.len(), Self::WRITE_SIZE) {
+            return Err(NorFlashErrorKind::NotAligned);
+        }
+        self.powered()?;
+        // Programming only ever clears bits
+        for (cell, byte) in self.memory[offset as usize..].iter_mut().zip(bytes) {
+            *cell &= byte;
+        }
+        Ok(())
+    }
+}
+
+// xorshift64*, seeded so a fuzz failure can be replayed
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed.max(1))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    // Anything in 0..n
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    pub fn fill(&mut self, buf: &mut [u8]) {
+        for byte in buf {
+            *byte = self.next_u64() as u8;
+        }
+    }
+}
@@ -0,0 +1,369 @@
+mod common;
+
+use common::Rng;
+use stack_ripper::{
+    compact::{Decoder, Encoder, KEYFRAME_INTERVAL, MAX_PAYLOAD_LEN},
+    flight::Phase,
+    gps::Fix,
+    lora::fec::{self, Scheme},
+    protocol::{self, Error, Header, MAX_FRAME_LEN},
+    pyro::Status,
+    state::State,
+    timesync::Utc,
+};
+
+const VEHICLE: u8 = 1;
+
+fn header(seq: u16) -> Header {
+    Header {
+        vehicle: VEHICLE,
+        seq,
+    }
+}
+
+fn pad_state() -> State {
+    State {
+        ln: Some(174.7762),
+        lt: Some(-41.2865),
+        ga: Some(35.2),
+        aaa: Some(135.7),
+        aar: Some(0.1),
+        t: Utc::from_date_time(2026, 4, 18, 43_200_000),
+        bt: 3_600_000,
+        fx: Fix::Dgps,
+        ns: Some(11),
+        hd: Some(0.9),
+        gs: Some(0.1),
+        gc: Some(271.3),
+        ge: 3,
+        fp: Phase::Pad,
+        la: Some([0.01, -0.02, 0.03]),
+        gv: Some([0.0, 0.0, 9.81]),
+        q: Some([1.0, 0.0, 0.0, 0.0]),
+        va: Some(0.02),
+        fa: Some(0.0),
+        vv: Some(0.0),
+        pd: Status::Safe,
+        pm: Status::Safe,
+        ..State::default()
+    }
+}
+
+// Half a second apart, partway up at a couple of hundred metres a second
+fn ascent_state(i: u32) -> State {
+    let t = i as f32 * 0.5;
+    let altitude = 500.0 + 180.0 * t - 4.9 * t * t;
+    State {
+        ln: Some(174.7762 + 2e-5 * t),
+        lt: Some(-41.2865 - 1e-5 * t),
+        ga: Some(35.2 + altitude),
+        aaa: Some(135.7 + altitude),
+        aar: Some(altitude),
+        t: Utc::from_date_time(2026, 4, 18, 43_260_000 + 500 * i + i % 3),
+        bt: 3_660_000 + 500 * i as u64,
+        gs: Some(12.5 + t),
+        gc: Some(95.0),
+        fp: Phase::Coast,
+        va: Some(-9.81 - 0.1 * t),
+        fa: Some(altitude + 0.3),
+        vv: Some(180.0 - 9.8 * t),
+        pd: Status::Armed,
+        pm: Status::Armed,
+        ak: Some(4),
+        ..pad_state()
+    }
+}
+
+fn encode(encoder: &mut Encoder, seq: u16, state: &State) -> Vec<u8> {
+    let mut buf = [0u8; MAX_PAYLOAD_LEN];
+    let len = encoder.encode(&header(seq), state, &mut buf).unwrap();
+    buf[..len].to_vec()
+}
+
+// What's expected back: everything that goes on air, to the precision it goes at
+fn assert_close(got: &State, sent: &State) {
+    fn close(got: Option<f32>, sent: Option<f32>, step: f32) -> bool {
+        match (got, sent) {
+            (Some(got), Some(sent)) => (got - sent).abs() <= step / 2.0 + sent.abs() * f32::EPSILON,
+            (got, sent) => got == sent,
+        }
+    }
+
+    let fields = [
+        ("lt", got.lt, sent.lt, 1e-7),
+        ("ln", got.ln, sent.ln, 1e-7),
+        ("ga", got.ga, sent.ga, 0.1),
+        ("aaa", got.aaa, sent.aaa, 0.1),
+        ("aar", got.aar, sent.aar, 0.1),
+        ("fa", got.fa, sent.fa, 0.1),
+        ("vv", got.vv, sent.vv, 1.0),
+        ("va", got.va, sent.va, 1.0),
+        ("hd", got.hd, sent.hd, 0.1),
+        ("gs", got.gs, sent.gs, 0.1),
+        ("gc", got.gc, sent.gc, 0.1),
+    ];
+    for (name, got, sent, step) in fields {
+        assert!(close(got, sent, step), "{}: {:?} for {:?}", name, got, sent);
+    }
+
+    let exact = State {
+        lt: None,
+        ln: None,
+        ga: None,
+        aaa: None,
+        aar: None,
+        fa: None,
+        vv: None,
+        va: None,
+        hd: None,
+        gs: None,
+        gc: None,
+        la: None,
+        gv: None,
+        q: None,
+        ..*sent
+    };
+    assert_eq!(
+        State {
+            lt: None,
+            ln: None,
+            ga: None,
+            aaa: None,
+            aar: None,
+            fa: None,
+            vv: None,
+            va: None,
+            hd: None,
+            gs: None,
+            gc: None,
+            ..*got
+        },
+        exact
+    );
+}
+
+#[test]
+fn keyframe_carries_everything_sent_down() {
+    let mut buf = [0u8; MAX_PAYLOAD_LEN];
+    let len = Encoder::new()
+        .encode(&header(0), &pad_state(), &mut buf)
+        .unwrap();
+    let got = Decoder::new().decode(&header(0), &buf[..len]).unwrap();
+
+    assert_close(&got, &pad_state());
+    // 1e-7 degrees is finer than an f32 this far from the equator, so these come back exactly
+    assert_eq!((got.lt, got.ln), (pad_state().lt, pad_state().ln));
+    // The IMU vectors are only in the flash log
+    assert_eq!((got.la, got.gv, got.q), (None, None, None));
+}
+
+// A frame as it goes on air, with the header and CRC around the payload
+fn send(encoder: &mut Encoder, seq: u16, state: &State) -> Vec<u8> {
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    protocol::encode(&header(seq), state, encoder, &mut buf)
+        .unwrap()
+        .to_vec()
+}
+
+#[test]
+fn frames_between_keyframes_are_small() {
+    let mut encoder = Encoder::new();
+    let mut decoder = Decoder::new();
+    let mut parity = fec::Encoder::new(Scheme::new(8, 2).unwrap());
+    let mut buf = [0u8; MAX_FRAME_LEN];
+
+    for i in 0..4 * KEYFRAME_INTERVAL {
+        let mut state = pad_state();
+        state.bt += 5_000 * i as u64;
+        state.t = state.t.map(|t| Utc(t.0 + 5_000 * i as i64));
+        state.ga = state.ga.map(|ga| ga + 0.1 * (i % 3) as f32);
+
+        let frame = send(&mut encoder, i, &state);
+        match i % KEYFRAME_INTERVAL {
+            0 => assert!(frame.len() <= 48, "keyframe is {} bytes", frame.len()),
+            _ => assert!(frame.len() <= 16, "frame {} is {} bytes", i, frame.len()),
+        }
+        assert_close(&protocol::decode(&frame, &mut decoder).unwrap().1, &state);
+
+        // Parity's as long as the longest frame in the group, the keyframe, plus its own framing
+        if parity.push(i, &frame) {
+            let len = parity.parity(VEHICLE, 0, &mut buf).unwrap().len();
+            assert!(len <= 60, "parity is {} bytes", len);
+        }
+    }
+
+    // Going up fast enough that position, altitude, speed and time all move between every frame
+    let mut between = Vec::new();
+    for i in 0..4 * KEYFRAME_INTERVAL {
+        let state = ascent_state(i as u32);
+        let frame = send(&mut encoder, 64 + i, &state);
+        match i % KEYFRAME_INTERVAL {
+            0 => assert!(frame.len() <= 52, "keyframe is {} bytes", frame.len()),
+            _ => {
+                assert!(frame.len() <= 23, "frame {} is {} bytes", i, frame.len());
+                between.push(frame.len());
+            }
+        }
+        assert_close(&protocol::decode(&frame, &mut decoder).unwrap().1, &state);
+
+        if parity.push(64 + i, &frame) {
+            let len = parity.parity(VEHICLE, 0, &mut buf).unwrap().len();
+            assert!(len <= 64, "parity is {} bytes", len);
+        }
+    }
+    let mean = between.iter().sum::<usize>() as f32 / between.len() as f32;
+    assert!(mean <= 22.0, "frames between are {} bytes on average", mean);
+}
+
+fn random_value(rng: &mut Rng, range: f32) -> Option<f32> {
+    match rng.below(8) {
+        0 => None,
+        _ => Some((rng.next_u64() >> 40) as f32 / (1u64 << 24) as f32 * 2.0 * range - range),
+    }
+}
+
+fn random_state(rng: &mut Rng) -> State {
+    State {
+        ln: random_value(rng, 180.0),
+        lt: random_value(rng, 90.0),
+        ga: random_value(rng, 10_000.0),
+        aaa: random_value(rng, 10_000.0),
+        aar: random_value(rng, 10_000.0),
+        t: (rng.below(8) != 0).then(|| Utc(rng.below(1 << 42) as i64)),
+        bt: rng.next_u64() >> rng.below(64),
+        fx: [Fix::None, Fix::Gps, Fix::Rtk, Fix::Estimated][rng.below(4)],
+        ns: (rng.below(8) != 0).then(|| rng.below(256) as u8),
+        hd: random_value(rng, 50.0).map(f32::abs),
+        gs: random_value(rng, 500.0).map(f32::abs),
+        gc: random_value(rng, 360.0).map(f32::abs),
+        ge: rng.below(1 << 16) as u16,
+        fp: [Phase::Pad, Phase::Boost, Phase::Apogee, Phase::Landed][rng.below(4)],
+        va: random_value(rng, 300.0),
+        fa: random_value(rng, 10_000.0),
+        vv: random_value(rng, 400.0),
+        ap: rng.below(2) == 1,
+        pd: [Status::Open, Status::Armed, Status::Fired][rng.below(3)],
+        pm: [Status::Safe, Status::Firing][rng.below(2)],
+        ak: (rng.below(8) != 0).then(|| rng.below(1_000) as u32),
+        ..State::default()
+    }
+}
+
+#[test]
+fn round_trip_is_lossless_once_quantized() {
+    let mut rng = Rng::new(0xC0DE);
+    let (mut encoder, mut decoder) = (Encoder::new(), Decoder::new());
+    let (mut again, mut decoder_again) = (Encoder::new(), Decoder::new());
+
+    for seq in 0..2_000 {
+        // Anything, even from one frame to the next
+        let state = random_state(&mut rng);
+        let payload = encode(&mut encoder, seq, &state);
+        let once = decoder.decode(&header(seq), &payload).unwrap();
+        assert_close(&once, &state);
+
+        // What came out goes back in the same
+        let payload = encode(&mut again, seq, &once);
+        assert_eq!(decoder_again.decode(&header(seq), &payload), Ok(once));
+    }
+}
+
+#[test]
+fn sends_non_finite_values_as_missing() {
+    let state = State {
+        va: Some(f32::NAN),
+        vv: Some(f32::INFINITY),
+        fa: Some(f32::NEG_INFINITY),
+        ..pad_state()
+    };
+    let payload = encode(&mut Encoder::new(), 0, &state);
+    let got = Decoder::new().decode(&header(0), &payload).unwrap();
+
+    assert_eq!((got.va, got.vv, got.fa), (None, None, None));
+    assert_eq!(got.aar, Some(0.1));
+}
+
+#[test]
+fn frames_between_keyframes_need_theirs() {
+    let mut encoder = Encoder::new();
+    let keyframe = encode(&mut encoder, 8, &pad_state());
+    let frames: Vec<_> = (9..16)
+        .map(|seq| encode(&mut encoder, seq, &pad_state()))
+        .collect();
+
+    // Keyframe lost, and the rest of its run with it
+    let mut decoder = Decoder::new();
+    for (seq, frame) in (9..).zip(&frames) {
+        assert_eq!(decoder.decode(&header(seq), frame), Err(Error::NoKeyframe));
+    }
+
+    // Another vehicle's doesn't stand in for it
+    let mut other = Encoder::new();
+    let mut buf = [0u8; MAX_PAYLOAD_LEN];
+    let len = other
+        .encode(&Header { vehicle: 5, seq: 8 }, &pad_state(), &mut buf)
+        .unwrap();
+    decoder
+        .decode(&Header { vehicle: 5, seq: 8 }, &buf[..len])
+        .unwrap();
+    assert_eq!(
+        decoder.decode(&header(9), &frames[0]),
+        Err(Error::NoKeyframe)
+    );
+
+    // Nor does the keyframe from before a reboot
+    let mut before = Encoder::new();
+    let stale = encode(&mut before, 8, &ascent_state(3));
+    decoder.decode(&header(8), &stale).unwrap();
+    assert_eq!(
+        decoder.decode(&header(9), &frames[0]),
+        Err(Error::NoKeyframe)
+    );
+
+    // Once it's in, the rest follow
+    decoder.decode(&header(8), &keyframe).unwrap();
+    for (seq, frame) in (9..).zip(&frames) {
+        assert_close(&decoder.decode(&header(seq), frame).unwrap(), &pad_state());
+    }
+}
+
+#[test]
+fn starts_with_a_keyframe_wherever_it_starts() {
+    let mut encoder = Encoder::new();
+    let mut decoder = Decoder::new();
+
+    // Partway into a run, then on over the sequence number wrapping
+    for seq in [u16::MAX - 2, u16::MAX, 0, 1] {
+        let payload = encode(&mut encoder, seq, &pad_state());
+        if seq == u16::MAX - 2 {
+            // Nothing needed before it
+            assert!(Decoder::new().decode(&header(seq), &payload).is_ok());
+        }
+        assert_close(
+            &decoder.decode(&header(seq), &payload).unwrap(),
+            &pad_state(),
+        );
+    }
+}
+
+#[test]
+fn rejects_damaged_payloads() {
+    let payload = encode(&mut Encoder::new(), 0, &pad_state());
+    let mut decoder = Decoder::new();
+
+    for len in 0..payload.len() {
+        assert_eq!(
+            decoder.decode(&header(0), &payload[..len]),
+            Err(Error::Deserialize)
+        );
+    }
+
+    let mut padded = payload.clone();
+    padded.push(0);
+    assert_eq!(decoder.decode(&header(0), &padded), Err(Error::Deserialize));
+
+    // A flight phase past the last one
+    let mut phase = payload.clone();
+    phase[0] |= 0b111 << 2;
+    assert_eq!(decoder.decode(&header(0), &phase), Err(Error::Deserialize));
+}
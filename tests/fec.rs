@@ -0,0 +1,348 @@
+mod common;
+
+use common::Rng;
+use stack_ripper::{
+    compact,
+    lora::fec::{Decoder, Encoder, Scheme, MAGIC, MAX_PROTECTED_LEN},
+    protocol::{self, Error, Header, MAX_FRAME_LEN},
+    state::State,
+};
+
+// What went out for one group: the telemetry frames, then the parity frames
+struct Group {
+    frames: Vec<Vec<u8>>,
+    parity: Vec<Vec<u8>>,
+}
+
+fn send_group(encoder: &mut Encoder, rng: &mut Rng, first: u16) -> Group {
+    let scheme = encoder.scheme();
+    let mut frames = Vec::new();
+    for i in 0..scheme.data() as u16 {
+        let mut frame = vec![0u8; 20 + rng.below(100)];
+        rng.fill(&mut frame);
+        let last = encoder.push(first.wrapping_add(i), &frame);
+        assert_eq!(last, i == scheme.data() as u16 - 1);
+        frames.push(frame);
+    }
+
+    let parity = (0..scheme.parity())
+        .map(|index| {
+            let mut buf = [0u8; MAX_FRAME_LEN];
+            encoder.parity(1, index, &mut buf).unwrap().to_vec()
+        })
+        .collect();
+
+    Group { frames, parity }
+}
+
+// Hands the decoder only what `heard` lets through, returns how many frames it ends up with
+fn receive_group(
+    decoder: &mut Decoder,
+    group: &Group,
+    first: u16,
+    mut heard: impl FnMut() -> bool,
+) -> usize {
+    for (i, frame) in group.frames.iter().enumerate() {
+        if heard() {
+            decoder.data(1, first.wrapping_add(i as u16), frame);
+        }
+    }
+    for parity in &group.parity {
+        if heard() {
+            decoder.parity(parity).unwrap();
+        }
+    }
+
+    let mut have = 0;
+    for (i, frame) in group.frames.iter().enumerate() {
+        if let Some(got) = decoder.frame(1, first.wrapping_add(i as u16)) {
+            // Never anything other than what was sent
+            assert_eq!(got, &frame[..]);
+            have += 1;
+        }
+    }
+    have
+}
+
+#[test]
+fn schemes_line_up_with_sequence_numbers() {
+    assert!(Scheme::new(4, 2).is_some());
+    assert!(Scheme::new(3, 1).is_none());
+    assert!(Scheme::new(16, 1).is_none());
+    assert!(Scheme::new(4, 0).is_none());
+    assert!(Scheme::new(4, 5).is_none());
+
+    let scheme = Scheme::new(4, 1).unwrap();
+    assert_eq!(scheme.first(7), 4);
+    assert_eq!(scheme.first(8), 8);
+    assert_eq!(scheme.first(u16::MAX), u16::MAX - 3);
+}
+
+#[test]
+fn rebuilds_any_frames_up_to_the_parity_count() {
+    let scheme = Scheme::new(4, 2).unwrap();
+    let mut rng = Rng::new(4);
+
+    // Every pattern of lost frames and parity, all 64 of them
+    for pattern in 0u32..1 << 6 {
+        let mut encoder = Encoder::new(scheme);
+        let mut decoder = Decoder::new();
+        let group = send_group(&mut encoder, &mut rng, 40);
+
+        let mut bit = 0;
+        let have = receive_group(&mut decoder, &group, 40, || {
+            bit += 1;
+            pattern & 1 << (bit - 1) == 0
+        });
+
+        let lost = pattern.count_ones();
+        if lost <= 2 {
+            assert_eq!(have, 4, "pattern {:06b}", pattern);
+        } else {
+            assert!(have < 4, "pattern {:06b}", pattern);
+        }
+    }
+}
+
+#[test]
+fn rebuilt_frames_decode_as_telemetry() {
+    let scheme = Scheme::new(8, 4).unwrap();
+    let mut encoder = Encoder::new(scheme);
+    let mut decoder = Decoder::new();
+
+    let mut compact = compact::Encoder::new();
+    let mut frames = Vec::new();
+    for seq in 16..24 {
+        let state = State {
+            bt: seq as u64 * 1_000,
+            aar: (seq % 3 == 0).then_some(seq as f32),
+            ..State::default()
+        };
+        let mut buf = [0u8; MAX_FRAME_LEN];
+        let frame =
+            protocol::encode(&Header { vehicle: 1, seq }, &state, &mut compact, &mut buf).unwrap();
+        encoder.push(seq, frame);
+        frames.push((seq, state, frame.to_vec()));
+    }
+
+    // Half of them heard
+    for (seq, _, frame) in frames.iter().step_by(2) {
+        decoder.data(1, *seq, frame);
+    }
+
+    let mut rebuilt = Vec::new();
+    for index in 0..4 {
+        let mut buf = [0u8; MAX_FRAME_LEN];
+        let parity = encoder.parity(1, index, &mut buf).unwrap();
+        assert_eq!(parity[0], MAGIC);
+        rebuilt.extend(
+            decoder
+                .parity(parity)
+                .unwrap()
+                .iter()
+                .map(|header| header.seq),
+        );
+    }
+    assert_eq!(rebuilt, [17, 19, 21, 23]);
+
+    let mut compact = compact::Decoder::new();
+    for (seq, state, _) in frames {
+        let frame = decoder.frame(1, seq).unwrap();
+        assert_eq!(
+            protocol::decode(frame, &mut compact),
+            Ok((Header { vehicle: 1, seq }, state))
+        );
+    }
+}
+
+#[test]
+fn frames_held_for_a_lost_keyframe_decode_once_it_is_rebuilt() {
+    let mut encoder = Encoder::new(Scheme::new(8, 1).unwrap());
+    let mut decoder = Decoder::new();
+    let mut compact_encoder = compact::Encoder::new();
+    let mut compact = compact::Decoder::new();
+
+    let mut frames = Vec::new();
+    for seq in 8..16 {
+        let state = State {
+            bt: seq as u64 * 250,
+            aar: Some(100.0 + seq as f32),
+            ..State::default()
+        };
+        let mut buf = [0u8; MAX_FRAME_LEN];
+        let frame = protocol::encode(
+            &Header { vehicle: 1, seq },
+            &state,
+            &mut compact_encoder,
+            &mut buf,
+        )
+        .unwrap();
+        encoder.push(seq, frame);
+        frames.push((seq, state, frame.to_vec()));
+    }
+
+    // Everything but the keyframe heard, and none of it readable yet
+    for (seq, _, frame) in &frames[1..] {
+        decoder.data(1, *seq, frame);
+        assert_eq!(
+            protocol::decode(frame, &mut compact),
+            Err(Error::NoKeyframe)
+        );
+        decoder.hold(1, *seq);
+    }
+    assert_eq!(decoder.release(1, 8), None);
+
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let rebuilt = decoder
+        .parity(encoder.parity(1, 0, &mut buf).unwrap())
+        .unwrap();
+    assert_eq!(rebuilt, [Header { vehicle: 1, seq: 8 }]);
+    let mut frames = frames.into_iter();
+    let (_, keyframe_state, _) = frames.next().unwrap();
+    let keyframe = decoder.frame(1, 8).unwrap();
+    assert_eq!(
+        protocol::decode(keyframe, &mut compact),
+        Ok((Header { vehicle: 1, seq: 8 }, keyframe_state))
+    );
+
+    for (seq, state, _) in frames {
+        let frame = decoder.release(1, seq).unwrap();
+        assert_eq!(
+            protocol::decode(frame, &mut compact),
+            Ok((Header { vehicle: 1, seq }, state))
+        );
+        // Only the once
+        assert_eq!(decoder.release(1, seq), None);
+    }
+}
+
+#[test]
+fn frames_that_never_went_out_are_not_rebuilt() {
+    let scheme = Scheme::new(4, 3).unwrap();
+    let mut encoder = Encoder::new(scheme);
+    let mut decoder = Decoder::new();
+
+    // 1 failed to send, 2 was too long to protect, and 3 was lost
+    encoder.push(0, &[0xAA; 30]);
+    decoder.data(1, 0, &[0xAA; 30]);
+    encoder.push(2, &[0xBB; MAX_PROTECTED_LEN + 1]);
+    decoder.data(1, 2, &[0xBB; MAX_PROTECTED_LEN + 1]);
+    assert!(encoder.push(3, &[0xCC; 40]));
+
+    let mut rebuilt = Vec::new();
+    for index in 0..3 {
+        let mut buf = [0u8; MAX_FRAME_LEN];
+        let parity = encoder.parity(1, index, &mut buf).unwrap();
+        rebuilt.extend(
+            decoder
+                .parity(parity)
+                .unwrap()
+                .iter()
+                .map(|header| header.seq),
+        );
+    }
+    assert_eq!(rebuilt, [3]);
+
+    assert_eq!(decoder.frame(1, 1), None);
+    assert_eq!(decoder.frame(1, 2), None);
+    assert_eq!(decoder.frame(1, 3), Some(&[0xCC; 40][..]));
+}
+
+#[test]
+fn ignores_other_vehicles_frames() {
+    let scheme = Scheme::new(2, 1).unwrap();
+    let mut encoder = Encoder::new(scheme);
+    let mut decoder = Decoder::new();
+
+    encoder.push(10, &[1; 20]);
+    encoder.push(11, &[2; 20]);
+    decoder.data(1, 10, &[1; 20]);
+    // Vehicle 2 happens to be on the same sequence number
+    decoder.data(2, 11, &[9; 20]);
+
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let parity = encoder.parity(1, 0, &mut buf).unwrap();
+    assert_eq!(
+        &decoder.parity(parity).unwrap()[..],
+        [Header {
+            vehicle: 1,
+            seq: 11
+        }]
+    );
+    assert_eq!(decoder.frame(1, 11), Some(&[2; 20][..]));
+    assert_eq!(decoder.frame(2, 11), None);
+}
+
+#[test]
+fn rejects_damaged_parity() {
+    let scheme = Scheme::new(4, 1).unwrap();
+    let mut encoder = Encoder::new(scheme);
+    for seq in 0..4 {
+        encoder.push(seq, &[seq as u8; 25]);
+    }
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let len = encoder.parity(1, 0, &mut buf).unwrap().len();
+    let mut decoder = Decoder::new();
+
+    let mut flipped = buf;
+    flipped[12] ^= 0x01;
+    assert_eq!(decoder.parity(&flipped[..len]), Err(Error::BadCrc));
+    assert_eq!(decoder.parity(&buf[..len - 1]), Err(Error::LengthMismatch));
+    assert_eq!(decoder.parity(&buf[..4]), Err(Error::Truncated));
+
+    let mut telemetry = [0u8; MAX_FRAME_LEN];
+    let telemetry = protocol::encode(
+        &Header { vehicle: 1, seq: 0 },
+        &State::default(),
+        &mut compact::Encoder::new(),
+        &mut telemetry,
+    )
+    .unwrap();
+    assert_eq!(
+        decoder.parity(telemetry),
+        Err(Error::BadMagic(protocol::MAGIC))
+    );
+
+    // Nothing to protect, nothing to send
+    assert_eq!(
+        Encoder::new(scheme).parity(1, 0, &mut [0u8; MAX_FRAME_LEN]),
+        Err(Error::Serialize)
+    );
+}
+
+// Every packet, telemetry and parity alike, lost with probability `loss`. Returns the fraction of telemetry frames
+// the ground station still doesn't have.
+fn simulate(scheme: Scheme, loss: f64, seed: u64) -> f64 {
+    const GROUPS: usize = 2_000;
+    let mut rng = Rng::new(seed);
+    let mut encoder = Encoder::new(scheme);
+    let mut decoder = Decoder::new();
+    let k = scheme.data() as usize;
+
+    let mut have = 0;
+    for g in 0..GROUPS {
+        let first = (g * k) as u16;
+        let group = send_group(&mut encoder, &mut rng, first);
+        let mut coin = Rng::new(rng.next_u64());
+        have += receive_group(&mut decoder, &group, first, || {
+            (coin.next_u64() >> 11) as f64 / (1u64 << 53) as f64 >= loss
+        });
+    }
+
+    1.0 - have as f64 / (GROUPS * k) as f64
+}
+
+#[test]
+fn recovers_most_losses_at_long_range() {
+    // 4 + 2: 50% more packets, for most of a lossy link back
+    let scheme = Scheme::new(4, 2).unwrap();
+    assert!(simulate(scheme, 0.1, 1) < 0.02);
+    assert!(simulate(scheme, 0.3, 2) < 0.2);
+
+    // 8 + 1: cheap, and still helps with the odd dropped packet
+    let scheme = Scheme::new(8, 1).unwrap();
+    assert!(simulate(scheme, 0.05, 3) < 0.025);
+
+    // Nothing lost, nothing to do
+    assert_eq!(simulate(scheme, 0.0, 4), 0.0);
+}
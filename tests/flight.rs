@@ -0,0 +1,116 @@
+mod common;
+
+use common::{noise, synthetic_flight, Point, SAMPLE_MS};
+use stack_ripper::flight::{Config, Flight, Phase, Sample};
+
+fn run(points: &[Point], with_acceleration: bool, altitude_noise: f32) -> Vec<(u64, Phase)> {
+    let mut flight = Flight::new(Config::default());
+    let mut seed = 42;
+    let mut transitions = Vec::new();
+    let mut last = flight.phase();
+
+    for point in points {
+        let sample = Sample {
+            time_ms: point.time_ms,
+            altitude: point.altitude + noise(&mut seed, altitude_noise),
+            velocity: None,
+            acceleration: with_acceleration.then_some(point.acceleration),
+        };
+
+        let phase = flight.update(&sample);
+        assert!(
+            phase >= last,
+            "phase went backwards: {:?} -> {:?}",
+            last,
+            phase
+        );
+        if phase != last {
+            transitions.push((point.time_ms, phase));
+            last = phase;
+        }
+    }
+
+    transitions
+}
+
+fn time_of(transitions: &[(u64, Phase)], phase: Phase) -> u64 {
+    transitions
+        .iter()
+        .find(|(_, p)| *p == phase)
+        .unwrap_or_else(|| panic!("never reached {:?}", phase))
+        .0
+}
+
+#[test]
+fn walks_through_every_phase_with_an_accelerometer() {
+    let transitions = run(&synthetic_flight(), true, 0.0);
+    let phases: Vec<Phase> = transitions.iter().map(|(_, p)| *p).collect();
+
+    assert_eq!(
+        phases,
+        [
+            Phase::Boost,
+            Phase::Coast,
+            Phase::Apogee,
+            Phase::Descent,
+            Phase::Main,
+            Phase::Landed
+        ]
+    );
+
+    let launch = time_of(&transitions, Phase::Boost);
+    assert!((5_000..5_300).contains(&launch), "launch at {}", launch);
+
+    let burnout = time_of(&transitions, Phase::Coast);
+    assert!((7_000..7_300).contains(&burnout), "burnout at {}", burnout);
+
+    // 5m below a ballistic apogee is ~1s later
+    let apogee = time_of(&transitions, Phase::Apogee);
+    assert!((19_200..20_600).contains(&apogee), "apogee at {}", apogee);
+}
+
+#[test]
+fn detects_flight_from_altitude_alone() {
+    let transitions = run(&synthetic_flight(), false, 0.0);
+
+    let launch = time_of(&transitions, Phase::Boost);
+    assert!((5_000..6_200).contains(&launch), "launch at {}", launch);
+
+    let apogee = time_of(&transitions, Phase::Apogee);
+    assert!((19_200..20_600).contains(&apogee), "apogee at {}", apogee);
+
+    time_of(&transitions, Phase::Main);
+    time_of(&transitions, Phase::Landed);
+}
+
+#[test]
+fn deploys_main_at_the_configured_altitude() {
+    let points = synthetic_flight();
+    let transitions = run(&points, true, 0.0);
+
+    let main = time_of(&transitions, Phase::Main);
+    let altitude = points.iter().find(|p| p.time_ms == main).unwrap().altitude;
+    assert!((140.0..150.0).contains(&altitude), "main at {}m", altitude);
+}
+
+#[test]
+fn noisy_altimeter_on_the_pad_is_not_a_launch() {
+    let pad: Vec<Point> = (0..2_000)
+        .map(|i| Point {
+            time_ms: i * SAMPLE_MS,
+            altitude: 0.0,
+            acceleration: 0.0,
+        })
+        .collect();
+
+    assert!(run(&pad, true, 3.0).is_empty());
+}
+
+#[test]
+fn survives_a_noisy_flight() {
+    let transitions = run(&synthetic_flight(), true, 0.75);
+
+    let apogee = time_of(&transitions, Phase::Apogee);
+    assert!((19_200..21_000).contains(&apogee), "apogee at {}", apogee);
+    time_of(&transitions, Phase::Landed);
+}
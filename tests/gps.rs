@@ -0,0 +1,103 @@
+use nmea0183::{ParseResult, Parser};
+use stack_ripper::{
+    gps::{time_of_day, update, Fix},
+    state::State,
+    timesync::Utc,
+};
+
+fn parse(sentence: &str) -> ParseResult {
+    Parser::new()
+        .parse_from_bytes(sentence.as_bytes())
+        .find_map(|result| result.ok())
+        .expect("sentence should parse")
+}
+
+#[test]
+fn time_of_day_is_in_milliseconds() {
+    let ParseResult::GGA(Some(gga)) =
+        parse("$GPGGA,092750.000,5321.6802,N,00630.3372,W,1,8,1.03,61.7,M,55.2,M,,*76\r\n")
+    else {
+        panic!("expected a GGA fix");
+    };
+
+    assert_eq!(time_of_day(gga.time), (9 * 3_600 + 27 * 60 + 50) * 1_000);
+}
+
+#[test]
+fn keeps_fractional_seconds() {
+    let ParseResult::GGA(Some(gga)) =
+        parse("$GPGGA,235958.50,4116.7140,S,17446.5720,E,1,08,0.9,120.3,M,19.0,M,,*7D\r\n")
+    else {
+        panic!("expected a GGA fix");
+    };
+
+    assert_eq!(time_of_day(gga.time), 86_398_500);
+}
+
+// A u-blox M8 going from a good fix, to a better one, to losing it
+const STREAM: &str = concat!(
+    "$GNRMC,092750.00,A,4116.71400,S,17446.57200,E,24.300,271.50,180426,,,A*59\r\n",
+    "$GNVTG,271.50,T,,M,24.300,N,45.004,K,A*22\r\n",
+    "$GNGGA,092750.00,4116.71400,S,17446.57200,E,1,11,0.87,120.3,M,19.0,M,,*51\r\n",
+    "$GNGLL,4116.71400,S,17446.57200,E,092750.00,A,A*63\r\n",
+    "$GNGGA,092751.00,4116.71600,S,17446.57000,E,2,14,0.62,121.0,M,19.0,M,1.0,0000*70\r\n",
+    "$GNRMC,092752.00,V,,,,,,,180426,,,N*61\r\n",
+    "$GNVTG,,T,,M,,N,,K,N*32\r\n",
+    "$GNGGA,092752.00,,,,,0,03,25.5,,,,,,*42\r\n",
+);
+
+// Feeds the stream through a parser a byte at a time, like the UART task does, stopping after `sentences`
+fn state_after(sentences: usize) -> State {
+    let mut parser = Parser::new();
+    let mut state = State::default();
+
+    STREAM
+        .bytes()
+        .filter_map(|byte| parser.parse_from_byte(byte))
+        .take(sentences)
+        .for_each(|result| update(&mut state, result.expect("sentence should parse")));
+
+    state
+}
+
+#[test]
+fn takes_speed_and_course_from_rmc_and_vtg() {
+    let state = state_after(2);
+
+    assert!((state.gs.unwrap() - 12.5).abs() < 0.01);
+    assert_eq!(state.gc, Some(271.5));
+    assert_eq!(state.t, "2026-04-18T09:27:50.000Z".parse().ok());
+    assert_eq!(state.fx, Fix::None);
+}
+
+#[test]
+fn takes_fix_quality_from_gga() {
+    let state = state_after(3);
+
+    assert_eq!(state.fx, Fix::Gps);
+    assert_eq!(state.ns, Some(11));
+    assert_eq!(state.hd, Some(0.87));
+    assert_eq!(state.ga, Some(120.3));
+    assert!((state.lt.unwrap() + 41.278_57).abs() < 1e-4);
+    assert!((state.ln.unwrap() - 174.776_2).abs() < 1e-4);
+
+    let state = state_after(5);
+    assert_eq!(state.fx, Fix::Dgps);
+    assert_eq!(state.ns, Some(14));
+    assert_eq!(state.hd, Some(0.62));
+}
+
+#[test]
+fn losing_the_fix_keeps_the_last_position() {
+    let state = state_after(8);
+
+    assert_eq!(state.fx, Fix::None);
+    assert_eq!((state.ns, state.hd), (None, None));
+    assert_eq!(state.gs, None);
+    assert_eq!(state.gc, None);
+    assert_eq!(state.ga, Some(121.0));
+    assert!(state.lt.is_some() && state.ln.is_some());
+
+    // The date only comes with RMC, GGA just moves the time along
+    assert_eq!(state.t, Utc::from_date_time(2026, 4, 18, 34_072_000));
+}
@@ -0,0 +1,52 @@
+use stack_ripper::imu::vertical_acceleration;
+
+const G: f32 = 9.81;
+
+fn assert_close(actual: Option<f32>, expected: f32) {
+    let actual = actual.expect("expected a vertical acceleration");
+    assert!(
+        (actual - expected).abs() < 1e-3,
+        "{} is not {}",
+        actual,
+        expected
+    );
+}
+
+#[test]
+fn level_board_reads_z_as_vertical() {
+    assert_close(vertical_acceleration([1.0, 2.0, 30.0], [0.0, 0.0, G]), 30.0);
+}
+
+#[test]
+fn upside_down_board_flips_the_sign() {
+    assert_close(
+        vertical_acceleration([0.0, 0.0, 30.0], [0.0, 0.0, -G]),
+        -30.0,
+    );
+}
+
+#[test]
+fn board_mounted_along_the_airframe() {
+    // Typical install, with the x axis pointing up the rocket
+    assert_close(
+        vertical_acceleration([55.0, 0.5, -0.5], [G, 0.0, 0.0]),
+        55.0,
+    );
+}
+
+#[test]
+fn tilted_board_projects_onto_gravity() {
+    let s = core::f32::consts::FRAC_1_SQRT_2;
+    assert_close(
+        vertical_acceleration([20.0, 0.0, 0.0], [G * s, 0.0, G * s]),
+        20.0 * s,
+    );
+}
+
+#[test]
+fn no_gravity_estimate_yet() {
+    assert_eq!(
+        vertical_acceleration([1.0, 2.0, 3.0], [0.0, 0.0, 0.0]),
+        None
+    );
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use common::{apogee, gaussian, synthetic_flight, Point, SAMPLE_MS};
+use stack_ripper::kalman::{AltitudeFilter, Config};
+
+const DT: f32 = SAMPLE_MS as f32 / 1000.0;
+
+struct Run {
+    apogee_ms: Option<u64>,
+    velocity_at_burnout: f32,
+    worst_pad_error: f32,
+}
+
+fn run(points: &[Point], altitude_sigma: f32, acceleration_sigma: Option<f32>) -> Run {
+    let mut filter = AltitudeFilter::new(Config::default());
+    let mut seed = 7;
+    let mut run = Run {
+        apogee_ms: None,
+        velocity_at_burnout: 0.0,
+        worst_pad_error: 0.0,
+    };
+
+    for point in points {
+        let altitude = point.altitude + gaussian(&mut seed, altitude_sigma);
+        let acceleration =
+            acceleration_sigma.map(|sigma| point.acceleration + gaussian(&mut seed, sigma));
+
+        filter.step(DT, Some(altitude), acceleration);
+
+        // Give the filter a second to settle before judging it
+        if (1_000..5_000).contains(&point.time_ms) {
+            run.worst_pad_error = run.worst_pad_error.max(filter.altitude().abs());
+        }
+
+        if point.time_ms == 7_000 {
+            run.velocity_at_burnout = filter.velocity();
+        }
+
+        if filter.apogee_detected() && run.apogee_ms.is_none() {
+            run.apogee_ms = Some(point.time_ms);
+        }
+    }
+
+    run
+}
+
+#[test]
+fn smooths_the_barometer_on_the_pad() {
+    let run = run(&synthetic_flight(), 1.0, Some(0.5));
+    assert!(
+        run.worst_pad_error < 1.0,
+        "{}m off on the pad",
+        run.worst_pad_error
+    );
+}
+
+#[test]
+fn tracks_velocity_through_the_burn() {
+    let run = run(&synthetic_flight(), 1.0, Some(0.5));
+    assert!(
+        (run.velocity_at_burnout - 120.0).abs() < 5.0,
+        "{} m/s at burnout",
+        run.velocity_at_burnout
+    );
+}
+
+#[test]
+fn calls_apogee_promptly_with_both_sensors() {
+    let points = synthetic_flight();
+    let truth = apogee(&points).time_ms;
+
+    let detected = run(&points, 1.0, Some(0.5)).apogee_ms.expect("no apogee");
+    assert!(
+        (truth..truth + 500).contains(&detected),
+        "apogee at {}ms, detected at {}ms",
+        truth,
+        detected
+    );
+}
+
+#[test]
+fn calls_apogee_from_the_barometer_alone() {
+    let points = synthetic_flight();
+    let truth = apogee(&points).time_ms;
+
+    let detected = run(&points, 1.0, None).apogee_ms.expect("no apogee");
+    assert!(
+        (truth..truth + 1_500).contains(&detected),
+        "apogee at {}ms, detected at {}ms",
+        truth,
+        detected
+    );
+}
+
+#[test]
+fn never_calls_apogee_on_the_pad() {
+    let pad: Vec<Point> = (0..6_000)
+        .map(|i| Point {
+            time_ms: i * SAMPLE_MS,
+            altitude: 0.0,
+            acceleration: 0.0,
+        })
+        .collect();
+
+    assert_eq!(run(&pad, 3.0, Some(2.0)).apogee_ms, None);
+}
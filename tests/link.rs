@@ -0,0 +1,102 @@
+use stack_ripper::{
+    link::{LinkStats, Summary, HISTORY_LEN},
+    protocol::{Error, Header},
+};
+
+fn header(vehicle: u8, seq: u16) -> Header {
+    Header { vehicle, seq }
+}
+
+#[test]
+fn starts_empty() {
+    let stats = LinkStats::new();
+    assert_eq!(stats.summary(), Summary::default());
+    assert_eq!(stats.summary().loss(), 0.0);
+    assert_eq!(stats.since_last_ms(1_000), None);
+}
+
+#[test]
+fn counts_gaps_in_the_sequence() {
+    let mut stats = LinkStats::new();
+    stats.record(&header(1, 10), -80, 5, 0);
+    stats.record(&header(1, 11), -80, 5, 1_000);
+
+    // 12 to 14 never made it
+    let summary = stats.record(&header(1, 15), -80, 5, 5_000);
+    assert_eq!(summary.received, 3);
+    assert_eq!(summary.missed, 3);
+    assert_eq!(summary.loss(), 0.5);
+
+    // Across the wrap, missing 65535 and 0
+    let mut stats = LinkStats::new();
+    stats.record(&header(1, u16::MAX - 1), -80, 5, 0);
+    assert_eq!(stats.record(&header(1, 1), -80, 5, 3_000).missed, 2);
+}
+
+#[test]
+fn repeats_reboots_and_other_vehicles_are_not_losses() {
+    let mut stats = LinkStats::new();
+    stats.record(&header(1, 10), -80, 5, 0);
+
+    // The same packet twice still counts as heard
+    assert_eq!(stats.record(&header(1, 10), -80, 5, 100).missed, 0);
+
+    // The vehicle rebooted
+    assert_eq!(stats.record(&header(1, 0), -80, 5, 200).missed, 0);
+
+    // Another vehicle cuts in, and nothing's lost from either
+    assert_eq!(stats.record(&header(2, 500), -80, 5, 300).missed, 0);
+    assert_eq!(stats.record(&header(1, 1), -80, 5, 400).missed, 0);
+    assert_eq!(stats.summary().received, 5);
+}
+
+#[test]
+fn counts_bad_packets_apart() {
+    let mut stats = LinkStats::new();
+    stats.corrupt();
+    stats.reject(&Error::BadCrc);
+    stats.reject(&Error::BadMagic(0x00));
+    stats.reject(&Error::Deserialize);
+    // Only waiting on its keyframe, there's nothing wrong with it
+    stats.reject(&Error::NoKeyframe);
+    let summary = stats.record(&header(1, 0), -80, 5, 0);
+
+    assert_eq!(summary.corrupt, 2);
+    assert_eq!(summary.rejected, 2);
+    assert_eq!(summary.received, 1);
+    assert_eq!(summary.loss(), 0.0);
+}
+
+#[test]
+fn signal_is_averaged_over_recent_packets() {
+    let mut stats = LinkStats::new();
+    let summary = stats.record(&header(1, 0), -60, 9, 0);
+    assert_eq!((summary.rssi_avg, summary.rssi_min), (-60, -60));
+    assert_eq!((summary.snr_avg, summary.snr_min), (9, 9));
+
+    let summary = stats.record(&header(1, 1), -65, -2, 1_000);
+    assert_eq!((summary.rssi_avg, summary.rssi_min), (-62, -65));
+    assert_eq!((summary.snr_avg, summary.snr_min), (4, -2));
+
+    // A run of good packets pushes the bad one out of the history
+    for seq in 2..2 + HISTORY_LEN as u16 {
+        stats.record(&header(1, seq), -50, 10, seq as u64 * 1_000);
+    }
+    let summary = stats.summary();
+    assert_eq!((summary.rssi_avg, summary.rssi_min), (-50, -50));
+    assert_eq!((summary.snr_avg, summary.snr_min), (10, 10));
+}
+
+#[test]
+fn tracks_time_between_packets() {
+    let mut stats = LinkStats::new();
+    assert_eq!(stats.record(&header(1, 0), -80, 5, 10_000).gap_ms, None);
+    assert_eq!(
+        stats.record(&header(1, 1), -80, 5, 11_500).gap_ms,
+        Some(1_500)
+    );
+
+    assert_eq!(stats.since_last_ms(11_500), Some(0));
+    assert_eq!(stats.since_last_ms(14_000), Some(2_500));
+    assert_eq!(stats.summary().gap_ms, None);
+}
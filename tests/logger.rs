@@ -0,0 +1,229 @@
+use std::{
+    convert::Infallible,
+    future::Future,
+    pin::pin,
+    task::{Context, Poll, Waker},
+};
+
+use embedded_hal_async::{
+    delay::DelayNs,
+    spi::{ErrorType, Operation, SpiDevice},
+};
+use stack_ripper::{
+    flight::Phase,
+    logger::{
+        decode_record, encode_record, page_remaining, record_len, scan, FlashError, PreLaunch,
+        W25q, MAX_RECORD_LEN, PAGE_SIZE, PRE_LAUNCH_RECORDS,
+    },
+    protocol::Error,
+    state::State,
+};
+
+// Everything the fake flash does completes straight away, so there's never a reason to wait
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut context = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+    }
+}
+
+struct NoDelay;
+
+impl DelayNs for NoDelay {
+    async fn delay_ns(&mut self, _ns: u32) {}
+}
+
+// Just enough of a 64KiB W25Q to log to, programming only ever clears bits like the real thing
+struct FakeFlash {
+    memory: Vec<u8>,
+    write_enabled: bool,
+}
+
+impl FakeFlash {
+    fn new() -> Self {
+        FakeFlash {
+            memory: vec![0xFF; 1 << 16],
+            write_enabled: false,
+        }
+    }
+}
+
+impl ErrorType for FakeFlash {
+    type Error = Infallible;
+}
+
+impl SpiDevice for FakeFlash {
+    async fn transaction(
+        &mut self,
+        operations: &mut [Operation<'_, u8>],
+    ) -> Result<(), Infallible> {
+        let [Operation::Write(command), rest @ ..] = operations else {
+            panic!("transaction doesn't start with a command");
+        };
+        let command = command.to_vec();
+        let address = || u32::from_be_bytes([0, command[1], command[2], command[3]]) as usize;
+
+        match (command[0], rest) {
+            (0x9F, [Operation::Read(id)]) => id.copy_from_slice(&[0xEF, 0x40, 0x10]),
+            (0x05, [Operation::Read(status)]) => status[0] = 0,
+            (0x06, []) => self.write_enabled = true,
+            (0x03, [Operation::Read(buf)]) => {
+                let start = address();
+                buf.copy_from_slice(&self.memory[start..start + buf.len()]);
+            }
+            (0x02, [Operation::Write(data)]) => {
+                assert!(self.write_enabled, "page program without write enable");
+                let start = address();
+                assert!(
+                    start % 256 + data.len() <= 256,
+                    "page program wraps its page"
+                );
+                for (i, byte) in data.iter().enumerate() {
+                    self.memory[start + i] &= byte;
+                }
+                self.write_enabled = false;
+            }
+            (0xC7, []) => {
+                assert!(self.write_enabled, "chip erase without write enable");
+                self.memory.fill(0xFF);
+                self.write_enabled = false;
+            }
+            (command, _) => panic!("unexpected command {:#04x}", command),
+        }
+
+        Ok(())
+    }
+}
+
+fn sample_state(altitude: f32) -> State {
+    State {
+        aar: Some(altitude),
+        fa: Some(altitude),
+        vv: Some(-12.5),
+        fp: Phase::Descent,
+        ..State::default()
+    }
+}
+
+#[test]
+fn record_round_trip() {
+    let mut buf = [0u8; MAX_RECORD_LEN];
+    let record = encode_record(123_456, &sample_state(420.0), &mut buf).unwrap();
+
+    assert_eq!(record_len(record[0]), Some(record.len()));
+    assert_eq!(decode_record(record), Ok((123_456, sample_state(420.0))));
+}
+
+#[test]
+fn rejects_corrupted_records_and_stops_at_erased_flash() {
+    let mut buf = [0u8; MAX_RECORD_LEN];
+    let len = encode_record(1, &sample_state(10.0), &mut buf)
+        .unwrap()
+        .len();
+    buf[7] ^= 0x01;
+
+    assert_eq!(decode_record(&buf[..len]), Err(Error::BadCrc));
+    assert_eq!(record_len(0xFF), None);
+}
+
+#[test]
+fn pre_launch_buffer_keeps_only_the_latest_records() {
+    let mut pre_launch = PreLaunch::new();
+
+    for i in 0..(PRE_LAUNCH_RECORDS as u32 * 3) {
+        let mut buf = [0u8; MAX_RECORD_LEN];
+        pre_launch.push(encode_record(i, &State::default(), &mut buf).unwrap());
+    }
+
+    assert_eq!(pre_launch.len(), PRE_LAUNCH_RECORDS);
+
+    let times: Vec<u32> = pre_launch
+        .records()
+        .map(|record| decode_record(record).unwrap().0)
+        .collect();
+    let expected: Vec<u32> =
+        (PRE_LAUNCH_RECORDS as u32 * 2..PRE_LAUNCH_RECORDS as u32 * 3).collect();
+    assert_eq!(times, expected);
+}
+
+#[test]
+fn page_remaining_stops_at_the_boundary() {
+    assert_eq!(page_remaining(0), PAGE_SIZE);
+    assert_eq!(page_remaining(250), 6);
+    assert_eq!(page_remaining(PAGE_SIZE), PAGE_SIZE);
+}
+
+#[test]
+fn writes_across_pages_and_scans_back() {
+    let mut flash = W25q::new(FakeFlash::new());
+    block_on(flash.init()).unwrap();
+    assert_eq!(flash.capacity(), 1 << 16);
+
+    // Plenty of records, so some of them have to straddle a page boundary
+    let mut address = 0;
+    for i in 0..20 {
+        let mut buf = [0u8; MAX_RECORD_LEN];
+        let record = encode_record(i * 100, &sample_state(i as f32), &mut buf).unwrap();
+        block_on(flash.write(address, record, &mut NoDelay)).unwrap();
+        address += record.len() as u32;
+    }
+    assert!(address > PAGE_SIZE);
+
+    let mut records = Vec::new();
+    let end = block_on(scan(&mut flash, |_, record| records.push(record.unwrap()))).unwrap();
+
+    assert_eq!(end, address);
+    assert_eq!(records.len(), 20);
+    for (i, (time_ms, state)) in records.into_iter().enumerate() {
+        assert_eq!(time_ms, i as u32 * 100);
+        assert_eq!(state, sample_state(i as f32));
+    }
+}
+
+#[test]
+fn skips_a_length_no_record_could_have() {
+    for bad in [(MAX_RECORD_LEN - 6) as u8, 0xFE] {
+        let mut flash = W25q::new(FakeFlash::new());
+        block_on(flash.init()).unwrap();
+
+        let mut address = 0;
+        for i in 0..2 {
+            let mut buf = [0u8; MAX_RECORD_LEN];
+            let record = encode_record(i, &sample_state(i as f32), &mut buf).unwrap();
+            block_on(flash.write(address, record, &mut NoDelay)).unwrap();
+            address += record.len() as u32;
+        }
+        let torn = address;
+        block_on(flash.write(torn, &[bad, 0x12, 0x34], &mut NoDelay)).unwrap();
+
+        // The log carries on from the next page
+        let end = block_on(scan(&mut flash, |_, _| {})).unwrap();
+        assert_eq!(end, PAGE_SIZE);
+        let mut buf = [0u8; MAX_RECORD_LEN];
+        let record = encode_record(2, &sample_state(2.0), &mut buf).unwrap();
+        block_on(flash.write(end, record, &mut NoDelay)).unwrap();
+
+        let mut records = Vec::new();
+        block_on(scan(&mut flash, |address, record| {
+            records.push((address, record.map(|(time_ms, _)| time_ms)))
+        }))
+        .unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[2], (torn, Err(Error::LengthMismatch)));
+        assert_eq!(records[3], (PAGE_SIZE, Ok(2)));
+    }
+}
+
+#[test]
+fn refuses_to_write_past_the_end() {
+    let mut flash = W25q::new(FakeFlash::new());
+    block_on(flash.init()).unwrap();
+
+    assert_eq!(
+        block_on(flash.write(flash.capacity() - 2, &[0; 4], &mut NoDelay)),
+        Err(FlashError::Full)
+    );
+}
@@ -0,0 +1,274 @@
+mod common;
+
+use common::FakeFlash;
+use stack_ripper::{
+    flight::Phase,
+    lora::{
+        ConfigError, Modulation, Profile, RadioConfig, Region, Schedule, BANDWIDTHS_HZ,
+        CONFIG_ADDRESS, CONFIG_MAGIC,
+    },
+    protocol::Error,
+};
+
+const SF7: Modulation = Modulation {
+    spreading_factor: 7,
+    bandwidth_hz: 125_000,
+    coding_rate: 5,
+};
+
+const SF12: Modulation = Modulation {
+    spreading_factor: 12,
+    bandwidth_hz: 125_000,
+    coding_rate: 8,
+};
+
+#[test]
+fn symbol_time_and_low_data_rate() {
+    assert_eq!(SF7.symbol_us(), 1_024);
+    assert_eq!(SF12.symbol_us(), 32_768);
+
+    assert!(!SF7.low_data_rate_optimize());
+    assert!(SF12.low_data_rate_optimize());
+    assert!(Profile::Beacon.modulation().low_data_rate_optimize());
+}
+
+#[test]
+fn bitrates_match_the_datasheet() {
+    assert_eq!(SF7.bitrate(), 5_468);
+    assert_eq!(SF12.bitrate(), 183);
+    assert_eq!(Profile::LongRange.modulation().bitrate(), 610);
+}
+
+#[test]
+fn airtime_matches_the_semtech_calculator() {
+    // 16 symbol preamble, explicit header, CRC on, to the microsecond
+    assert_eq!(SF7.airtime_us(10), 49_408);
+    assert_eq!(SF7.airtime_us(90), 167_168);
+    assert_eq!(Profile::LongRange.modulation().airtime_us(90), 1_476_608);
+    assert_eq!(Profile::Beacon.modulation().airtime_us(90), 3_084_288);
+    assert_eq!(SF12.airtime_us(90), 5_644_288);
+
+    // Longer packets never get quicker
+    for profile in Profile::ALL {
+        let modulation = profile.modulation();
+        for len in 1..255 {
+            assert!(modulation.airtime_us(len) <= modulation.airtime_us(len + 1));
+        }
+    }
+}
+
+#[test]
+fn profiles_fit_their_intervals() {
+    for bandwidth_hz in BANDWIDTHS_HZ {
+        let config = RadioConfig::new(Region::Amateur433, 433_000_000, 10, bandwidth_hz).unwrap();
+        for profile in Profile::ALL {
+            let airtime_ms = config.modulation(profile).airtime_us(90) / 1_000;
+            let interval_ms = config.interval_ms(profile);
+            assert!(
+                airtime_ms * 2 < interval_ms,
+                "{:?} at {}",
+                profile,
+                bandwidth_hz
+            );
+            assert!(config.listen_ms(profile) > 3 * interval_ms);
+        }
+    }
+}
+
+#[test]
+fn narrow_channels_slow_down() {
+    let wide = RadioConfig::new(Region::Amateur433, 433_000_000, 10, 250_000).unwrap();
+    let narrow = RadioConfig::new(Region::Amateur433, 433_000_000, 10, 62_500).unwrap();
+
+    for profile in Profile::ALL {
+        // At least twice as long on air, more if it needs low data rate optimisation, and as long again between
+        assert!(narrow.interval_ms(profile) >= 2 * profile.interval_ms());
+        assert!(narrow.listen_ms(profile) > RadioConfig::default().listen_ms(profile));
+
+        // But no quicker than at 125 kHz
+        assert_eq!(wide.interval_ms(profile), profile.interval_ms());
+    }
+}
+
+#[test]
+fn follows_the_flight_phase() {
+    let mut schedule = Schedule::new();
+    assert_eq!(schedule.profile(), Profile::LongRange);
+
+    assert_eq!(schedule.follow(Phase::Boost), Profile::Ascent);
+    assert_eq!(schedule.follow(Phase::Descent), Profile::Ascent);
+    assert_eq!(schedule.follow(Phase::Main), Profile::Beacon);
+    assert_eq!(schedule.follow(Phase::Landed), Profile::Beacon);
+    assert_eq!(schedule.follow(Phase::Pad), Profile::LongRange);
+}
+
+#[test]
+fn hunts_through_every_profile() {
+    let mut schedule = Schedule::new();
+    schedule.follow(Phase::Landed);
+
+    let mut seen = vec![schedule.profile()];
+    for _ in 1..Profile::ALL.len() {
+        seen.push(schedule.hunt());
+    }
+    for profile in Profile::ALL {
+        assert!(seen.contains(&profile), "{:?}", profile);
+    }
+
+    // And around again
+    assert_eq!(schedule.hunt(), Profile::Beacon);
+}
+
+#[test]
+fn loses_the_link_mid_ascent() {
+    let config = RadioConfig::default();
+    let mut schedule = Schedule::new();
+    schedule.follow(Phase::Coast);
+
+    // A couple of missed packets, and the rest of the way up still on Ascent
+    let mut steps = Vec::new();
+    let mut blackout_ms = schedule.listen_ms(&config);
+    while steps.len() < 4 {
+        let profile = schedule.hunt();
+        if profile == Profile::Ascent {
+            assert!(blackout_ms < 12_000, "{} ms", blackout_ms);
+            blackout_ms = 0;
+        } else {
+            steps.push(profile);
+        }
+        blackout_ms += schedule.listen_ms(&config);
+    }
+
+    // Still gets round everything else in case it's rebooted or out under the main, just not for as long
+    assert_eq!(
+        steps,
+        [
+            Profile::Beacon,
+            Profile::LongRange,
+            Profile::Beacon,
+            Profile::LongRange
+        ]
+    );
+    schedule.hunt();
+    assert!(schedule.listen_ms(&config) < config.listen_ms(Profile::Beacon) / 3);
+
+    // Heard again on the way down, a long wait is fine once it's landed
+    schedule.follow(Phase::Landed);
+    assert_eq!(
+        schedule.listen_ms(&config),
+        config.listen_ms(Profile::Beacon)
+    );
+}
+
+#[test]
+fn forced_profile_holds_until_released() {
+    let mut schedule = Schedule::new();
+    schedule.force(Some(Profile::Beacon));
+
+    assert_eq!(schedule.follow(Phase::Boost), Profile::Beacon);
+    assert_eq!(schedule.hunt(), Profile::Beacon);
+
+    // Still tracking the phase underneath
+    schedule.force(None);
+    assert_eq!(schedule.profile(), Profile::Ascent);
+}
+
+#[test]
+fn region_defaults_are_valid() {
+    for region in Region::ALL {
+        let config = region.default_config();
+        assert_eq!(config.validate(), Ok(()), "{:?}", region);
+        assert_eq!(config.power_dbm, region.max_power_dbm());
+    }
+
+    // Where we were before there was a choice
+    let config = RadioConfig::default();
+    assert_eq!(config.frequency_hz, 433_000_000);
+    assert_eq!(config.power_dbm, 20);
+}
+
+#[test]
+fn rejects_what_the_region_does_not_allow() {
+    assert_eq!(
+        RadioConfig::new(Region::Ism433, 433_000_000, 10, 125_000),
+        Err(ConfigError::Frequency)
+    );
+    assert_eq!(
+        RadioConfig::new(Region::Eu868, 915_000_000, 14, 125_000),
+        Err(ConfigError::Frequency)
+    );
+    // Centre's in the band, but the edge of the channel isn't
+    assert_eq!(
+        RadioConfig::new(Region::Us915, 927_800_000, 20, 500_000),
+        Err(ConfigError::Frequency)
+    );
+
+    assert_eq!(
+        RadioConfig::new(Region::Ism433, 433_920_000, 20, 125_000),
+        Err(ConfigError::Power)
+    );
+    assert_eq!(
+        RadioConfig::new(Region::Amateur433, 433_000_000, 22, 125_000),
+        Err(ConfigError::Power)
+    );
+    assert_eq!(
+        RadioConfig::new(Region::Amateur433, 433_000_000, 0, 125_000),
+        Err(ConfigError::Power)
+    );
+
+    assert_eq!(
+        RadioConfig::new(Region::Eu868, 868_100_000, 14, 500_000),
+        Err(ConfigError::Bandwidth)
+    );
+    assert_eq!(
+        RadioConfig::new(Region::Amateur433, 433_000_000, 20, 100_000),
+        Err(ConfigError::Bandwidth)
+    );
+
+    assert!(RadioConfig::new(Region::Us915, 927_700_000, 20, 500_000).is_ok());
+}
+
+#[test]
+fn config_bandwidth_applies_to_every_profile() {
+    let config = RadioConfig::new(Region::Amateur433, 434_000_000, 17, 250_000).unwrap();
+
+    for profile in Profile::ALL {
+        let modulation = config.modulation(profile);
+        assert_eq!(modulation.bandwidth_hz, 250_000);
+        assert_eq!(
+            modulation.spreading_factor,
+            profile.modulation().spreading_factor
+        );
+        assert!(modulation.airtime_us(90) < profile.modulation().airtime_us(90));
+    }
+}
+
+#[test]
+fn config_record_round_trip() {
+    let config = RadioConfig::new(Region::Eu868, 869_525_000, 14, 250_000).unwrap();
+    let record = config.encode().unwrap();
+
+    assert_eq!(record[0], CONFIG_MAGIC);
+    assert_eq!(RadioConfig::decode(&record), Ok(config));
+
+    let mut corrupt = record;
+    corrupt[3] ^= 0x01;
+    assert_eq!(RadioConfig::decode(&corrupt), Err(Error::BadCrc));
+
+    assert_eq!(RadioConfig::decode(&[0xFF; 32]), Err(Error::BadMagic(0xFF)));
+}
+
+#[test]
+fn saves_to_and_loads_from_flash() {
+    let mut flash = FakeFlash::new();
+    assert_eq!(RadioConfig::load(&mut flash, CONFIG_ADDRESS), None);
+
+    let first = Region::Us915.default_config();
+    first.save(&mut flash, CONFIG_ADDRESS).unwrap();
+    assert_eq!(RadioConfig::load(&mut flash, CONFIG_ADDRESS), Some(first));
+
+    // Saving again has to erase first, or the bits from last time would show through
+    let second = RadioConfig::new(Region::Ism433, 434_000_000, 7, 62_500).unwrap();
+    second.save(&mut flash, CONFIG_ADDRESS).unwrap();
+    assert_eq!(RadioConfig::load(&mut flash, CONFIG_ADDRESS), Some(second));
+}
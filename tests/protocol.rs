@@ -0,0 +1,173 @@
+mod common;
+
+use common::Rng;
+use stack_ripper::{
+    compact,
+    protocol::{
+        self, crc16, Error, Header, CRC_LEN, HEADER_LEN, MAGIC, MAX_FRAME_LEN, MAX_PAYLOAD_LEN,
+        VERSION,
+    },
+    state::State,
+    timesync::Utc,
+};
+
+// Frames on their own, so every one is a keyframe, see tests/compact.rs for the ones in between
+fn encode<'a>(header: &Header, state: &State, buf: &'a mut [u8]) -> Result<&'a [u8], Error> {
+    protocol::encode(header, state, &mut compact::Encoder::new(), buf)
+}
+
+fn decode(buf: &[u8]) -> Result<(Header, State), Error> {
+    protocol::decode(buf, &mut compact::Decoder::new())
+}
+
+fn sample_state() -> State {
+    State {
+        ln: Some(174.7762),
+        lt: Some(-41.2865),
+        ga: Some(123.4),
+        aaa: Some(130.0),
+        aar: Some(12.5),
+        t: Utc::from_date_time(2026, 4, 18, 86_399_200),
+        bt: 61_200,
+        ..State::default()
+    }
+}
+
+#[test]
+fn crc16_matches_ccitt_false_check_value() {
+    assert_eq!(crc16(b"123456789"), 0x29B1);
+}
+
+#[test]
+fn round_trip() {
+    let header = Header {
+        vehicle: 3,
+        seq: 0xBEEF,
+    };
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let frame = encode(&header, &sample_state(), &mut buf).unwrap();
+
+    assert_eq!(frame[0], MAGIC);
+    assert_eq!(frame[1], VERSION);
+    assert_eq!(decode(frame), Ok((header, sample_state())));
+}
+
+#[test]
+fn rejects_foreign_magic() {
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let len = encode(&Header { vehicle: 1, seq: 0 }, &sample_state(), &mut buf)
+        .unwrap()
+        .len();
+    buf[0] = 0x00;
+
+    assert_eq!(decode(&buf[..len]), Err(Error::BadMagic(0x00)));
+}
+
+#[test]
+fn rejects_other_versions() {
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let len = encode(&Header { vehicle: 1, seq: 0 }, &sample_state(), &mut buf)
+        .unwrap()
+        .len();
+    buf[1] = VERSION + 1;
+
+    assert_eq!(
+        decode(&buf[..len]),
+        Err(Error::UnsupportedVersion(VERSION + 1))
+    );
+}
+
+#[test]
+fn rejects_corrupted_payload() {
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let len = encode(&Header { vehicle: 1, seq: 7 }, &sample_state(), &mut buf)
+        .unwrap()
+        .len();
+    buf[8] ^= 0x01;
+
+    assert_eq!(decode(&buf[..len]), Err(Error::BadCrc));
+}
+
+#[test]
+fn rejects_truncated_and_padded_frames() {
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let len = encode(&Header { vehicle: 1, seq: 7 }, &sample_state(), &mut buf)
+        .unwrap()
+        .len();
+
+    assert_eq!(decode(&buf[..3]), Err(Error::Truncated));
+    assert_eq!(decode(&buf[..len - 1]), Err(Error::LengthMismatch));
+    assert_eq!(decode(&buf), Err(Error::LengthMismatch));
+}
+
+#[test]
+fn encode_fails_on_tiny_buffer() {
+    let mut buf = [0u8; 10];
+    assert_eq!(
+        encode(&Header { vehicle: 1, seq: 0 }, &sample_state(), &mut buf),
+        Err(Error::Serialize)
+    );
+}
+
+const FUZZ_RUNS: usize = 10_000;
+
+#[test]
+fn fuzz_random_buffers() {
+    let mut rng = Rng::new(0x5EED);
+    let mut buf = [0u8; MAX_FRAME_LEN];
+
+    for _ in 0..FUZZ_RUNS {
+        let len = rng.below(MAX_FRAME_LEN + 1);
+        rng.fill(&mut buf[..len]);
+
+        // Whatever else is on 433MHz, it shouldn't make it past the header and CRC
+        assert!(decode(&buf[..len]).is_err(), "{:02x?}", &buf[..len]);
+    }
+}
+
+#[test]
+fn fuzz_well_framed_garbage() {
+    let mut rng = Rng::new(0xF00D);
+    let mut buf = [0u8; MAX_FRAME_LEN];
+
+    for _ in 0..FUZZ_RUNS {
+        // A good header and CRC around a payload that isn't a State, so only unpacking it stands in the way
+        let payload_len = rng.below(MAX_PAYLOAD_LEN + 1);
+        let crc_start = HEADER_LEN + payload_len;
+        rng.fill(&mut buf[2..crc_start]);
+        buf[0] = MAGIC;
+        buf[1] = VERSION;
+        buf[5] = payload_len as u8;
+        let crc = crc16(&buf[..crc_start]).to_le_bytes();
+        buf[crc_start..crc_start + CRC_LEN].copy_from_slice(&crc);
+
+        let frame = &buf[..crc_start + CRC_LEN];
+        match decode(frame) {
+            Ok(_) | Err(Error::Deserialize | Error::NoKeyframe) => {}
+            Err(err) => panic!("{:?} from {:02x?}", err, frame),
+        }
+    }
+}
+
+#[test]
+fn fuzz_damaged_frames() {
+    let mut rng = Rng::new(0xBAD);
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let len = encode(&Header { vehicle: 1, seq: 7 }, &sample_state(), &mut buf)
+        .unwrap()
+        .len();
+
+    for _ in 0..FUZZ_RUNS {
+        // The CRC catches any three bit errors in a frame this size, wherever they land
+        let mut damaged = buf;
+        for _ in 0..1 + rng.below(3) {
+            damaged[rng.below(len)] ^= 1 << rng.below(8);
+        }
+        if damaged[..len] != buf[..len] {
+            assert!(decode(&damaged[..len]).is_err(), "{:02x?}", &damaged[..len]);
+        }
+
+        // And cut short anywhere
+        assert!(decode(&buf[..rng.below(len)]).is_err());
+    }
+}
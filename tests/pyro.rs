@@ -0,0 +1,230 @@
+use std::{cell::Cell, convert::Infallible, rc::Rc};
+
+use embedded_hal::digital::{ErrorType, InputPin, OutputPin};
+use stack_ripper::{
+    flight::Phase,
+    pyro::{Channel, Config, Pyro, Status},
+};
+
+// A pin shared between the channel and the test, so the test can see (or set) what the channel sees
+#[derive(Clone, Default)]
+struct Pin(Rc<Cell<bool>>);
+
+impl Pin {
+    fn high() -> Self {
+        Pin(Rc::new(Cell::new(true)))
+    }
+
+    fn get(&self) -> bool {
+        self.0.get()
+    }
+
+    fn set(&self, high: bool) {
+        self.0.set(high)
+    }
+}
+
+impl ErrorType for Pin {
+    type Error = Infallible;
+}
+
+impl OutputPin for Pin {
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.0.set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.0.set(true);
+        Ok(())
+    }
+}
+
+impl InputPin for Pin {
+    fn is_high(&mut self) -> Result<bool, Infallible> {
+        Ok(self.0.get())
+    }
+
+    fn is_low(&mut self) -> Result<bool, Infallible> {
+        Ok(!self.0.get())
+    }
+}
+
+struct Rig {
+    pyro: Pyro<Pin, Pin>,
+    drogue_fire: Pin,
+    drogue_sense: Pin,
+    main_fire: Pin,
+    main_sense: Pin,
+}
+
+fn rig() -> Rig {
+    let (drogue_fire, main_fire) = (Pin::default(), Pin::default());
+    let (drogue_sense, main_sense) = (Pin::high(), Pin::high());
+
+    let pyro = Pyro::new(
+        Config::default(),
+        Channel::new(drogue_fire.clone(), drogue_sense.clone()),
+        Channel::new(main_fire.clone(), main_sense.clone()),
+    );
+
+    Rig {
+        pyro,
+        drogue_fire,
+        drogue_sense,
+        main_fire,
+        main_sense,
+    }
+}
+
+#[test]
+fn never_fires_without_a_launch() {
+    let mut rig = rig();
+
+    rig.pyro.update(0, Phase::Pad, 0.0).unwrap();
+    assert_eq!(rig.pyro.drogue(), Status::Safe);
+    assert_eq!(rig.pyro.main(), Status::Safe);
+    assert!(!rig.pyro.armed());
+
+    assert!(!rig.drogue_fire.get());
+    assert!(!rig.main_fire.get());
+}
+
+#[test]
+fn fires_drogue_at_apogee_then_main_below_the_main_altitude() {
+    let mut rig = rig();
+
+    rig.pyro.update(0, Phase::Boost, 10.0).unwrap();
+    rig.pyro.update(5_000, Phase::Coast, 800.0).unwrap();
+    assert_eq!(rig.pyro.drogue(), Status::Armed);
+    assert!(!rig.drogue_fire.get());
+
+    rig.pyro.update(10_000, Phase::Apogee, 850.0).unwrap();
+    assert_eq!(rig.pyro.drogue(), Status::Firing);
+    assert_eq!(rig.pyro.main(), Status::Armed);
+    assert!(rig.drogue_fire.get());
+    assert!(!rig.main_fire.get());
+
+    rig.pyro.update(40_000, Phase::Descent, 200.0).unwrap();
+    assert!(!rig.main_fire.get());
+
+    rig.pyro.update(45_000, Phase::Descent, 149.0).unwrap();
+    assert_eq!(rig.pyro.main(), Status::Firing);
+    assert!(rig.main_fire.get());
+}
+
+#[test]
+fn holds_the_output_for_the_fire_duration() {
+    let mut rig = rig();
+    let fire_ms = Config::default().fire_ms;
+
+    rig.pyro.update(0, Phase::Boost, 10.0).unwrap();
+    rig.pyro.update(10_000, Phase::Apogee, 850.0).unwrap();
+
+    rig.pyro
+        .update(10_000 + fire_ms - 1, Phase::Descent, 800.0)
+        .unwrap();
+    assert!(rig.drogue_fire.get());
+    assert_eq!(rig.pyro.drogue(), Status::Firing);
+
+    rig.pyro
+        .update(10_000 + fire_ms, Phase::Descent, 800.0)
+        .unwrap();
+    assert!(!rig.drogue_fire.get());
+    assert_eq!(rig.pyro.drogue(), Status::Fired);
+
+    // A burnt out e-match reads open, but the channel stays fired and doesn't go again
+    rig.drogue_sense.set(false);
+    rig.pyro.update(20_000, Phase::Descent, 700.0).unwrap();
+    assert!(!rig.drogue_fire.get());
+    assert_eq!(rig.pyro.drogue(), Status::Fired);
+}
+
+#[test]
+fn skips_a_channel_without_continuity() {
+    let mut rig = rig();
+    rig.main_sense.set(false);
+
+    rig.pyro.update(0, Phase::Boost, 10.0).unwrap();
+    assert_eq!(rig.pyro.main(), Status::Open);
+
+    rig.pyro.update(40_000, Phase::Main, 100.0).unwrap();
+    assert_eq!(rig.pyro.main(), Status::Open);
+    assert!(!rig.main_fire.get());
+
+    // If continuity comes back while we're still under the main altitude, it goes
+    rig.main_sense.set(true);
+    rig.pyro.update(40_010, Phase::Main, 99.0).unwrap();
+    assert_eq!(rig.pyro.main(), Status::Firing);
+    assert!(rig.main_fire.get());
+}
+
+#[test]
+fn low_apogee_fires_both_channels_together() {
+    let mut rig = rig();
+
+    rig.pyro.update(0, Phase::Boost, 10.0).unwrap();
+    rig.pyro.update(5_000, Phase::Apogee, 120.0).unwrap();
+
+    assert_eq!(rig.pyro.drogue(), Status::Firing);
+    assert_eq!(rig.pyro.main(), Status::Firing);
+}
+
+#[test]
+fn disarmed_from_the_ground_holds_safe_until_armed_again() {
+    let mut rig = rig();
+    rig.pyro.disarm();
+
+    rig.pyro.update(0, Phase::Boost, 10.0).unwrap();
+    rig.pyro.update(10_000, Phase::Apogee, 850.0).unwrap();
+    assert!(!rig.pyro.armed());
+    assert_eq!(rig.pyro.drogue(), Status::Safe);
+    assert!(!rig.drogue_fire.get());
+
+    rig.pyro.arm();
+    rig.pyro.update(10_010, Phase::Descent, 840.0).unwrap();
+    assert!(rig.pyro.armed());
+    assert_eq!(rig.pyro.drogue(), Status::Firing);
+    assert!(rig.drogue_fire.get());
+}
+
+#[test]
+fn arming_again_after_landing_fires_nothing() {
+    let mut rig = rig();
+
+    rig.pyro.update(0, Phase::Boost, 10.0).unwrap();
+    rig.pyro.disarm();
+    rig.pyro.update(10_000, Phase::Apogee, 850.0).unwrap();
+    rig.pyro.update(60_000, Phase::Main, 100.0).unwrap();
+    rig.pyro.update(120_000, Phase::Landed, 0.0).unwrap();
+
+    rig.pyro.arm();
+    rig.pyro.update(120_010, Phase::Landed, 0.0).unwrap();
+    rig.pyro.update(121_010, Phase::Landed, 0.0).unwrap();
+    assert!(!rig.drogue_fire.get());
+    assert!(!rig.main_fire.get());
+    assert_eq!(rig.pyro.drogue(), Status::Safe);
+    assert_eq!(rig.pyro.main(), Status::Safe);
+}
+
+#[test]
+fn continuity_turning_up_after_landing_fires_nothing() {
+    let mut rig = rig();
+    rig.drogue_sense.set(false);
+    rig.main_sense.set(false);
+
+    rig.pyro.update(0, Phase::Boost, 10.0).unwrap();
+    rig.pyro.update(10_000, Phase::Apogee, 850.0).unwrap();
+    rig.pyro.update(60_000, Phase::Main, 100.0).unwrap();
+    assert_eq!(rig.pyro.drogue(), Status::Open);
+    assert_eq!(rig.pyro.main(), Status::Open);
+
+    rig.pyro.update(120_000, Phase::Landed, 0.0).unwrap();
+    rig.drogue_sense.set(true);
+    rig.main_sense.set(true);
+    rig.pyro.update(120_010, Phase::Landed, 0.0).unwrap();
+    assert!(!rig.drogue_fire.get());
+    assert!(!rig.main_fire.get());
+    assert_eq!(rig.pyro.drogue(), Status::Safe);
+    assert_eq!(rig.pyro.main(), Status::Safe);
+}
@@ -0,0 +1,273 @@
+use stack_ripper::{
+    compact::KEYFRAME_INTERVAL,
+    flight::Phase,
+    lora::{
+        rate::{Cadence, Policy, Rates},
+        Profile, RadioConfig, Region, Schedule,
+    },
+    uplink::window_ms,
+};
+
+// Same as the transmitter
+const POLL_MS: u64 = 50;
+
+const CONFIG: RadioConfig = RadioConfig {
+    region: Region::Amateur433,
+    frequency_hz: 433_000_000,
+    power_dbm: 20,
+    bandwidth_hz: 125_000,
+};
+
+// On air in flight, about what the compact tests see
+const KEYFRAME_LEN: usize = 52;
+const FRAME_LEN: usize = 23;
+
+// What went out: when, with which phase in it, and on which profile
+type Sent = Vec<(u64, Phase, Profile)>;
+
+// How long the `n`th frame keeps the radio, going out and then listening for the ground station if it's `listening`
+fn busy_ms(n: usize, profile: Profile, listening: bool) -> u64 {
+    let modulation = CONFIG.modulation(profile);
+    let len = match n % KEYFRAME_INTERVAL as usize {
+        0 => KEYFRAME_LEN,
+        _ => FRAME_LEN,
+    };
+    let window_ms = if listening { window_ms(&modulation) } else { 0 };
+    (modulation.airtime_us(len) / 1_000 + window_ms) as u64
+}
+
+// A flight against a made up clock. `phases` has when each phase starts, and every frame takes as long as `busy_ms`.
+fn fly(policy: &dyn Policy, phases: &[(u64, Phase)], end_ms: u64, listening: bool) -> Sent {
+    let phase_at = |now_ms| {
+        phases
+            .iter()
+            .rev()
+            .find(|(start_ms, _)| *start_ms <= now_ms)
+            .map_or(Phase::Pad, |(_, phase)| *phase)
+    };
+
+    let mut cadence = Cadence::new(policy, listening);
+    let mut schedule = Schedule::new();
+    let mut sent = Vec::new();
+    let mut now_ms = 0;
+    while now_ms < end_ms {
+        let phase = phase_at(now_ms);
+        match cadence.wait_ms(now_ms, phase, &CONFIG, schedule.profile()) {
+            0 => {
+                let profile = schedule.profile();
+                cadence.take(now_ms, Some(phase), &CONFIG, profile);
+                sent.push((now_ms, phase, profile));
+                now_ms += busy_ms(sent.len() - 1, profile, listening);
+                schedule.follow(phase);
+            }
+            wait => now_ms += wait.min(POLL_MS),
+        }
+    }
+    sent
+}
+
+const FLIGHT: [(u64, Phase); 6] = [
+    (60_000, Phase::Boost),
+    (63_000, Phase::Coast),
+    (80_000, Phase::Apogee),
+    (80_040, Phase::Descent),
+    (150_000, Phase::Main),
+    (200_000, Phase::Landed),
+];
+
+#[test]
+fn keeps_to_the_rate_for_each_phase() {
+    let rates = Rates::new();
+    let sent = fly(&rates, &FLIGHT, 300_000, false);
+
+    for pair in sent.windows(2) {
+        let ((before_ms, before, was_on), (at_ms, phase, profile)) = (pair[0], pair[1]);
+        // The launch goes out on the slow profile, and the next one can't go before it's done
+        if (before, was_on) == (phase, profile) {
+            assert_eq!(
+                at_ms - before_ms,
+                rates.interval_ms(phase, profile) as u64,
+                "{:?} at {}",
+                phase,
+                at_ms
+            );
+        }
+    }
+
+    let count = |phase| sent.iter().filter(|(_, p, _)| *p == phase).count();
+    assert_eq!(count(Phase::Pad), 12);
+    assert!((79..=81).contains(&(count(Phase::Boost) + count(Phase::Coast))));
+    assert_eq!(count(Phase::Landed), 10);
+}
+
+#[test]
+fn phase_changes_go_out_straight_away() {
+    let sent = fly(&Rates::new(), &FLIGHT, 300_000, false);
+
+    for (start_ms, phase) in FLIGHT {
+        // Apogee's over before anyone looks
+        if phase == Phase::Apogee {
+            continue;
+        }
+        let n = sent.iter().position(|(_, p, _)| *p == phase).unwrap();
+        let (at_ms, _, profile) = sent[n];
+        // As soon as the frame before is done with the radio
+        let (_, before, before_profile) = sent[n - 1];
+        let busy = busy_ms(n - 1, before_profile, false);
+        assert!(
+            at_ms - start_ms <= busy + POLL_MS,
+            "{:?} at {}",
+            phase,
+            at_ms
+        );
+        // Still on the profile the ground station's listening on
+        assert_eq!(profile, Profile::for_phase(before));
+    }
+
+    // And the launch only once, whatever the profile it moves onto
+    let boost: Vec<_> = sent.iter().filter(|(_, p, _)| *p == Phase::Boost).collect();
+    assert_eq!(boost[0].2, Profile::LongRange);
+    assert!(boost[1..]
+        .iter()
+        .all(|(_, _, profile)| *profile == Profile::Ascent));
+}
+
+#[test]
+fn sending_does_not_stretch_the_gap() {
+    let sent = fly(&Rates::new(), &[(0, Phase::Descent)], 60_000, true);
+
+    // Every 500 ms on the dot once it's onto Ascent, though each one takes 300 ms or so of it with listening after
+    let (start_ms, ..) = sent[1];
+    assert!(sent[1..].len() >= 116);
+    assert!(sent[1..]
+        .iter()
+        .zip(0..)
+        .all(|((at_ms, ..), i)| *at_ms == start_ms + i * 500));
+}
+
+#[test]
+fn leaves_time_to_listen_for_commands() {
+    let rates = Rates::new();
+    let listening = fly(&rates, &FLIGHT, 300_000, true);
+
+    // Every frame's done sending, and listening after, before the next is due
+    for (n, pair) in listening.windows(2).enumerate() {
+        let ((before_ms, _, profile), (at_ms, ..)) = (pair[0], pair[1]);
+        assert!(at_ms - before_ms >= busy_ms(n, profile, true), "{}", at_ms);
+    }
+
+    // Which costs some of the rate on the way up, but it's still kept to
+    let interval_ms =
+        Cadence::new(&rates, true).interval_ms(Phase::Coast, &CONFIG, Profile::Ascent);
+    assert!((250..400).contains(&interval_ms), "{}", interval_ms);
+    let coast: Vec<_> = listening
+        .iter()
+        .filter(|(_, phase, _)| *phase == Phase::Coast)
+        .collect();
+    assert!(coast
+        .windows(2)
+        .all(|pair| pair[1].0 - pair[0].0 == interval_ms));
+    assert!(coast.len() as u64 >= 17_000 / interval_ms - 1);
+
+    // The rest of the flight doesn't notice
+    let count = |sent: &Sent, phase| sent.iter().filter(|(_, p, _)| *p == phase).count();
+    let quiet = fly(&rates, &FLIGHT, 300_000, false);
+    for phase in [Phase::Descent, Phase::Main, Phase::Landed] {
+        assert_eq!(
+            count(&listening, phase),
+            count(&quiet, phase),
+            "{:?}",
+            phase
+        );
+    }
+}
+
+#[test]
+fn starts_over_rather_than_catching_up() {
+    let rates = Rates::new();
+    let mut cadence = Cadence::new(&rates, false);
+    cadence.take(0, Some(Phase::Coast), &CONFIG, Profile::Ascent);
+    assert_eq!(
+        cadence.wait_ms(100, Phase::Coast, &CONFIG, Profile::Ascent),
+        150
+    );
+
+    // A bit late, the next one's still due on time
+    cadence.take(300, Some(Phase::Coast), &CONFIG, Profile::Ascent);
+    assert_eq!(
+        cadence.wait_ms(300, Phase::Coast, &CONFIG, Profile::Ascent),
+        200
+    );
+
+    // Stuck for a couple of seconds, a whole gap after now rather than one straight after another
+    cadence.take(2_600, Some(Phase::Coast), &CONFIG, Profile::Ascent);
+    assert_eq!(
+        cadence.wait_ms(2_600, Phase::Coast, &CONFIG, Profile::Ascent),
+        250
+    );
+}
+
+#[test]
+fn parity_keeps_the_pace_of_the_telemetry_before() {
+    let rates = Rates::new();
+    let mut cadence = Cadence::new(&rates, false);
+    cadence.take(0, Some(Phase::Pad), &CONFIG, Profile::LongRange);
+    cadence.take(5_000, None, &CONFIG, Profile::LongRange);
+
+    assert!(!cadence.changed(Phase::Pad));
+    assert_eq!(
+        cadence.wait_ms(5_000, Phase::Pad, &CONFIG, Profile::LongRange),
+        5_000
+    );
+    // Parity doesn't say anything about the phase, the launch still has to go out
+    assert!(cadence.changed(Phase::Boost));
+    assert_eq!(
+        cadence.wait_ms(5_000, Phase::Boost, &CONFIG, Profile::LongRange),
+        0
+    );
+}
+
+#[test]
+fn keeps_within_what_the_profile_allows() {
+    let flat_out = |_: Phase, _: Profile| 1;
+    let asleep = |_: Phase, _: Profile| u32::MAX;
+
+    for profile in Profile::ALL {
+        let longest = profile.interval_ms() as u64;
+        let fastest = Cadence::new(&flat_out, false).interval_ms(Phase::Boost, &CONFIG, profile);
+        assert_eq!(fastest, longest / 2);
+        assert_eq!(
+            Cadence::new(&asleep, false).interval_ms(Phase::Landed, &CONFIG, profile),
+            longest
+        );
+
+        // Flat out, a keyframe still leaves the channel quiet over half the time
+        let airtime_ms = profile.modulation().airtime_us(48) as u64 / 1_000;
+        assert!(airtime_ms * 2 < fastest, "{:?}", profile);
+
+        // Which takes longer on a narrower channel
+        let narrow = RadioConfig {
+            bandwidth_hz: 62_500,
+            ..CONFIG
+        };
+        let fastest = Cadence::new(&flat_out, false).interval_ms(Phase::Boost, &narrow, profile);
+        assert!(fastest >= longest, "{:?}", profile);
+        let airtime_ms = narrow.modulation(profile).airtime_us(48) as u64 / 1_000;
+        assert!(airtime_ms * 2 < fastest, "{:?}", profile);
+    }
+}
+
+#[test]
+fn takes_any_policy() {
+    // Say, twice a second the whole way, as fast as each profile goes
+    let eager = |_: Phase, profile: Profile| profile.interval_ms() / 2;
+    let sent = fly(&eager, &FLIGHT, 300_000, false);
+
+    for pair in sent.windows(2) {
+        let ((before_ms, before, was_on), (at_ms, phase, profile)) = (pair[0], pair[1]);
+        if (before, was_on) == (phase, profile) {
+            assert_eq!(at_ms - before_ms, profile.interval_ms() as u64 / 2);
+        }
+    }
+    assert!(sent.len() > fly(&Rates::new(), &FLIGHT, 300_000, false).len());
+}
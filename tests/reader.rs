@@ -0,0 +1,140 @@
+use stack_ripper::gps::{
+    reader::{Event, Fault, Reader, Stats},
+    ubx::{cfg_rate, encode, CLASS_CFG, ID_CFG_RATE},
+};
+
+const GGA: &str = "$GNGGA,092750.00,4116.71400,S,17446.57200,E,1,11,0.87,120.3,M,19.0,M,,*51\r\n";
+const VTG: &str = "$GNVTG,271.50,T,,M,24.300,N,45.004,K,A*22\r\n";
+
+#[derive(Debug, PartialEq)]
+enum Owned {
+    Sentence(String),
+    Frame(u8, u8, Vec<u8>),
+}
+
+fn read(reader: &mut Reader, bytes: &[u8]) -> Vec<Owned> {
+    bytes
+        .iter()
+        .filter_map(|byte| match reader.feed(*byte)? {
+            Event::Sentence(sentence) => Some(Owned::Sentence(sentence.to_string())),
+            Event::Frame(frame) => {
+                Some(Owned::Frame(frame.class, frame.id, frame.payload.to_vec()))
+            }
+        })
+        .collect()
+}
+
+fn ubx_frame() -> Vec<u8> {
+    let mut buf = [0u8; 32];
+    encode(CLASS_CFG, ID_CFG_RATE, &cfg_rate(200), &mut buf)
+        .unwrap()
+        .to_vec()
+}
+
+#[test]
+fn passes_good_sentences_and_frames_through() {
+    let mut reader = Reader::new();
+    let stream = [GGA.as_bytes(), &ubx_frame(), VTG.as_bytes()].concat();
+
+    assert_eq!(
+        read(&mut reader, &stream),
+        [
+            Owned::Sentence(GGA.to_string()),
+            Owned::Frame(CLASS_CFG, ID_CFG_RATE, cfg_rate(200).to_vec()),
+            Owned::Sentence(VTG.to_string()),
+        ]
+    );
+    assert_eq!(
+        reader.stats(),
+        Stats {
+            sentences: 2,
+            frames: 1,
+            ..Stats::default()
+        }
+    );
+}
+
+#[test]
+fn counts_and_drops_bad_checksums() {
+    let mut reader = Reader::new();
+    let mut frame = ubx_frame();
+    frame[7] ^= 0x01;
+    let stream = [
+        GGA.replace("120.3", "120.4").as_bytes(),
+        &frame,
+        "$GNVTG,271.50,T,,M,24.300,N,45.004,K,A\r\n".as_bytes(),
+        VTG.as_bytes(),
+    ]
+    .concat();
+
+    assert_eq!(
+        read(&mut reader, &stream),
+        [Owned::Sentence(VTG.to_string())]
+    );
+    assert_eq!(reader.stats().checksum_errors, 3);
+}
+
+#[test]
+fn ubx_payloads_that_look_like_nmea_stay_ubx() {
+    let mut reader = Reader::new();
+    let mut buf = [0u8; 64];
+    let payload = b"$GNVTG,*00\r\n";
+    let frame = encode(CLASS_CFG, ID_CFG_RATE, payload, &mut buf).unwrap();
+
+    assert_eq!(
+        read(&mut reader, frame),
+        [Owned::Frame(CLASS_CFG, ID_CFG_RATE, payload.to_vec())]
+    );
+    assert_eq!(reader.stats().errors(), 0);
+}
+
+#[test]
+fn recovers_from_cut_short_and_runaway_sentences() {
+    let mut reader = Reader::new();
+    let runaway = format!("$GNTXT,{}\r\n", "X".repeat(200));
+    let stream = [
+        &GGA.as_bytes()[..30],
+        VTG.as_bytes(),
+        runaway.as_bytes(),
+        &GGA.as_bytes()[..20],
+        &ubx_frame(),
+        GGA.as_bytes(),
+    ]
+    .concat();
+
+    assert_eq!(
+        read(&mut reader, &stream),
+        [
+            Owned::Sentence(VTG.to_string()),
+            Owned::Frame(CLASS_CFG, ID_CFG_RATE, cfg_rate(200).to_vec()),
+            Owned::Sentence(GGA.to_string()),
+        ]
+    );
+    // Cut short by the next `$`, too long, then cut short by a UBX frame
+    assert_eq!(reader.stats().framing_errors, 3);
+}
+
+#[test]
+fn uart_faults_reset_the_reader() {
+    let mut reader = Reader::new();
+
+    assert_eq!(read(&mut reader, &GGA.as_bytes()[..40]), []);
+    reader.fault(Fault::Overrun);
+    assert_eq!(read(&mut reader, &GGA.as_bytes()[40..]), []);
+
+    let frame = ubx_frame();
+    assert_eq!(read(&mut reader, &frame[..8]), []);
+    reader.fault(Fault::Framing);
+    assert_eq!(read(&mut reader, &frame[8..]), []);
+
+    assert_eq!(read(&mut reader, GGA.as_bytes()).len(), 1);
+    assert_eq!(
+        reader.stats(),
+        Stats {
+            sentences: 1,
+            framing_errors: 1,
+            overruns: 1,
+            ..Stats::default()
+        }
+    );
+}
@@ -0,0 +1,188 @@
+use stack_ripper::{
+    flight::Phase,
+    gps::Fix,
+    link::Summary,
+    protocol::Header,
+    pyro::Status,
+    state::State,
+    telemetry::{
+        nmea_checksum, parse_line, write_csv_header, write_line, Demux, Format, Packet, ParseError,
+        COLUMNS,
+    },
+    timesync::Utc,
+};
+
+fn sample_packet() -> Packet {
+    Packet {
+        header: Header {
+            vehicle: 1,
+            seq: 42,
+        },
+        rssi: -87,
+        snr: 6,
+        link: Summary {
+            received: 40,
+            missed: 2,
+            corrupt: 1,
+            rejected: 0,
+            rssi_avg: -85,
+            rssi_min: -91,
+            snr_avg: 7,
+            snr_min: 5,
+            gap_ms: Some(1003),
+        },
+        recovered: false,
+        state: State {
+            ln: Some(174.7762),
+            lt: Some(-41.2865),
+            ga: Some(123.4),
+            fx: Fix::Dgps,
+            ns: Some(11),
+            hd: Some(0.9),
+            ge: 3,
+            aar: Some(12.5),
+            t: Utc::from_date_time(2026, 4, 18, 86_399_200),
+            bt: 61_200,
+            fp: Phase::Coast,
+            vv: Some(80.25),
+            pd: Status::Armed,
+            ak: Some(7),
+            ..State::default()
+        },
+    }
+}
+
+fn line(packet: &Packet, format: Format) -> String {
+    let mut line = String::new();
+    write_line(packet, format, &mut line).unwrap();
+    line
+}
+
+#[test]
+fn csv_header_matches_the_columns() {
+    let mut header = String::new();
+    write_csv_header(&mut header).unwrap();
+
+    assert_eq!(
+        header,
+        concat!(
+            "vehicle,seq,rssi,snr,rx,lost,crc,rej,ravg,rmin,savg,smin,gap,fec,",
+            "t,bt,lt,ln,ga,fx,ns,hd,gs,gc,ge,aaa,aar,fp,va,fa,vv,ap,pd,pm,ak"
+        )
+    );
+    assert_eq!(header.split(',').count(), COLUMNS.len());
+}
+
+#[test]
+fn csv_leaves_missing_values_empty() {
+    assert_eq!(
+        line(&sample_packet(), Format::Csv),
+        concat!(
+            "1,42,-87,6,40,2,1,0,-85,-91,7,5,1003,false,",
+            "2026-04-18T23:59:59.200Z,61200,-41.2865,174.7762,123.4,Dgps,11,0.9,,,3,,12.5,Coast,,,80.25,false,Armed,Open,7"
+        )
+    );
+}
+
+#[test]
+fn json_uses_null_for_missing_values() {
+    assert_eq!(
+        line(&sample_packet(), Format::Json),
+        concat!(
+            r#"{"vehicle":1,"seq":42,"rssi":-87,"snr":6,"#,
+            r#""rx":40,"lost":2,"crc":1,"rej":0,"ravg":-85,"rmin":-91,"savg":7,"smin":5,"gap":1003,"#,
+            r#""fec":false,"t":"2026-04-18T23:59:59.200Z","bt":61200,"#,
+            r#""lt":-41.2865,"ln":174.7762,"#,
+            r#""ga":123.4,"fx":"Dgps","ns":11,"hd":0.9,"gs":null,"gc":null,"ge":3,"aaa":null,"aar":12.5,"#,
+            r#""fp":"Coast","va":null,"fa":null,"vv":80.25,"#,
+            r#""ap":false,"pd":"Armed","pm":"Open","ak":7}"#
+        )
+    );
+}
+
+#[test]
+fn nmea_sentence_has_a_valid_checksum() {
+    let sentence = line(&sample_packet(), Format::Nmea);
+
+    let (body, checksum) = sentence
+        .strip_prefix('$')
+        .and_then(|s| s.split_once('*'))
+        .unwrap();
+
+    assert!(body
+        .starts_with("PSRTL,1,42,-87,6,40,2,1,0,-85,-91,7,5,1003,false,2026-04-18T23:59:59.200Z,"));
+    assert_eq!(
+        u8::from_str_radix(checksum, 16).unwrap(),
+        nmea_checksum(body)
+    );
+    assert_eq!(checksum.len(), 2);
+}
+
+#[test]
+fn non_finite_values_are_treated_as_missing() {
+    let mut packet = sample_packet();
+    packet.state.fa = Some(f32::NAN);
+    packet.state.va = Some(f32::INFINITY);
+
+    assert!(line(&packet, Format::Json).contains(r#""va":null,"fa":null"#));
+}
+
+#[test]
+fn parses_back_every_format() {
+    for format in [Format::Csv, Format::Json, Format::Nmea] {
+        assert_eq!(
+            parse_line(&line(&sample_packet(), format)),
+            Ok(sample_packet()),
+            "{:?}",
+            format
+        );
+    }
+
+    // Rebuilt from parity rather than heard
+    let recovered = || Packet {
+        recovered: true,
+        ..sample_packet()
+    };
+    for format in [Format::Csv, Format::Json, Format::Nmea] {
+        assert_eq!(parse_line(&line(&recovered(), format)), Ok(recovered()));
+    }
+}
+
+#[test]
+fn rejects_headers_and_damaged_lines() {
+    let mut header = String::new();
+    write_csv_header(&mut header).unwrap();
+    assert_eq!(parse_line(&header), Err(ParseError::BadField("vehicle")));
+
+    let csv = line(&sample_packet(), Format::Csv);
+    assert_eq!(
+        parse_line(&csv[..csv.len() - 5]),
+        Err(ParseError::FieldCount)
+    );
+
+    let nmea = line(&sample_packet(), Format::Nmea).replace("Coast", "Boost");
+    assert_eq!(parse_line(&nmea), Err(ParseError::BadChecksum));
+}
+
+#[test]
+fn takes_defmt_frames_out_from_between_lines() {
+    let sent = line(&sample_packet(), Format::Csv) + "\r\n";
+    // rzcobs never has a zero in it, but newlines and 0xFF are fair game
+    let frame = [0xFF, 0x00, 0x12, 0x0A, 0xFF, 0x80, 0x00];
+
+    let mut stream = Vec::new();
+    stream.extend_from_slice(&frame);
+    stream.extend_from_slice(&sent.as_bytes()[..40]);
+    stream.extend_from_slice(&frame);
+    stream.extend_from_slice(&frame);
+    stream.extend_from_slice(&sent.as_bytes()[40..]);
+    stream.extend_from_slice(&frame);
+
+    let mut demux = Demux::new();
+    let text: Vec<u8> = stream
+        .into_iter()
+        .filter_map(|byte| demux.push(byte))
+        .collect();
+    assert_eq!(String::from_utf8(text).unwrap(), sent);
+    assert_eq!(parse_line(sent.trim_end()), Ok(sample_packet()));
+}
@@ -0,0 +1,169 @@
+use stack_ripper::timesync::{Clock, Error, Utc, MS_PER_DAY, US_PER_SECOND};
+
+// A crystal running 40ppm fast, booted 12.345678s before the first edge at 09:27:50 UTC
+const DRIFT_PPM: f64 = 40.0;
+const BOOT_US: u64 = 12_345_678;
+
+// Where our clock reads when UTC is `seconds` past the first edge, give or take `jitter_us`
+fn local_at(seconds: f64, jitter_us: i64) -> u64 {
+    ((BOOT_US as f64 + seconds * 1e6 * (1.0 + DRIFT_PPM * 1e-6)) as i64 + jitter_us) as u64
+}
+
+fn utc_at(seconds: i64) -> i64 {
+    let first = Utc::from_date_time(2026, 4, 18, 34_070_000).unwrap();
+    first.as_micros() + seconds * US_PER_SECOND
+}
+
+fn synced(edges: i64) -> Clock {
+    // Capture latency wanders by a few microseconds
+    let jitter = [0, 3, -2, 5, -4, 1, 0, -3];
+
+    let mut clock = Clock::new();
+    for second in 0..edges {
+        let edge = clock.edge(
+            local_at(second as f64, jitter[second as usize % jitter.len()]),
+            utc_at(second),
+        );
+        assert_eq!(edge, Ok(()));
+    }
+    clock
+}
+
+#[test]
+fn dates_round_trip() {
+    let utc = Utc::from_date_time(2026, 4, 18, 34_070_200).unwrap();
+
+    assert_eq!(utc.date(), (2026, 4, 18));
+    assert_eq!(utc.ms_of_day(), 34_070_200);
+    assert_eq!(utc.whole_second().ms_of_day(), 34_070_000);
+    assert_eq!(Utc::from_date_time(1970, 1, 1, 0), Some(Utc(0)));
+    assert_eq!(
+        Utc::from_date_time(2024, 2, 29, 0).unwrap().date(),
+        (2024, 2, 29)
+    );
+
+    assert_eq!(Utc::from_date_time(2026, 2, 29, 0), None);
+    assert_eq!(Utc::from_date_time(2026, 4, 31, 0), None);
+    assert_eq!(Utc::from_date_time(2026, 13, 1, 0), None);
+    assert_eq!(Utc::from_date_time(2026, 4, 18, MS_PER_DAY as u32), None);
+}
+
+#[test]
+fn writes_and_parses_iso_8601() {
+    let utc = Utc::from_date_time(2026, 12, 31, 86_399_999).unwrap();
+
+    assert_eq!(utc.to_string(), "2026-12-31T23:59:59.999Z");
+    assert_eq!("2026-12-31T23:59:59.999Z".parse(), Ok(utc));
+
+    for bad in [
+        "2026-12-31T23:59:59Z",
+        "2026-12-31 23:59:59.999Z",
+        "2026-12-32T00:00:00.000Z",
+        "235959",
+    ] {
+        assert_eq!(bad.parse::<Utc>(), Err(()), "{}", bad);
+    }
+}
+
+#[test]
+fn time_of_day_rolls_over_midnight() {
+    let before = Utc::from_date_time(2026, 4, 17, 86_399_000).unwrap();
+    let after = before.with_time_of_day(500);
+    assert_eq!(after.to_string(), "2026-04-18T00:00:00.500Z");
+
+    // And back again, if a late sentence from before midnight turns up
+    assert_eq!(after.with_time_of_day(86_399_000), before);
+    assert_eq!(before.with_time_of_day(43_200_000).date(), (2026, 4, 17));
+}
+
+#[test]
+fn needs_two_edges_to_sync() {
+    let mut clock = Clock::new();
+    assert_eq!(clock.to_utc(BOOT_US), None);
+
+    clock.edge(local_at(0.0, 0), utc_at(0)).unwrap();
+    assert!(!clock.is_synced());
+    assert_eq!(clock.to_utc(BOOT_US), None);
+
+    clock.edge(local_at(1.0, 0), utc_at(1)).unwrap();
+    assert!(clock.is_synced());
+}
+
+#[test]
+fn estimates_drift_and_maps_instants_to_utc() {
+    let clock = synced(30);
+
+    let drift = clock.drift_ppm().unwrap();
+    assert!((drift - DRIFT_PPM as f32).abs() < 5.0, "{}", drift);
+
+    // Between edges, and well past the last one, still well inside a millisecond
+    for seconds in [10.25, 29.5, 45.125] {
+        let expected = utc_at(0) + (seconds * 1e6) as i64;
+        let utc = clock.to_utc(local_at(seconds, 0)).unwrap();
+        assert!(
+            (utc - expected).abs() < 100,
+            "{}s off by {}us",
+            seconds,
+            utc - expected
+        );
+    }
+}
+
+#[test]
+fn rides_through_missed_edges() {
+    let mut clock = synced(5);
+
+    assert_eq!(clock.edge(local_at(9.0, 0), utc_at(9)), Ok(()));
+    let utc = clock.to_utc(local_at(9.5, 0)).unwrap();
+    assert!((utc - (utc_at(9) + 500_000)).abs() < 100);
+}
+
+#[test]
+fn rejects_glitches_and_duplicates() {
+    let mut clock = synced(5);
+    let before = clock;
+
+    // Noise on the line half way through a second, paired with either second
+    assert_eq!(
+        clock.edge(local_at(4.5, 0), utc_at(4)),
+        Err(Error::Duplicate)
+    );
+    assert_eq!(clock.edge(local_at(4.5, 0), utc_at(5)), Err(Error::Glitch));
+
+    assert_eq!(
+        clock.to_utc(local_at(4.75, 0)),
+        before.to_utc(local_at(4.75, 0))
+    );
+    assert_eq!(clock.edge(local_at(5.0, 0), utc_at(5)), Ok(()));
+}
+
+#[test]
+fn starts_over_when_the_reference_is_wrong() {
+    // The first edge got paired with the wrong second, so everything after looks a second out
+    let mut clock = Clock::new();
+    clock.edge(local_at(0.0, 0), utc_at(1)).unwrap();
+
+    for second in 1..4 {
+        assert!(clock
+            .edge(local_at(second as f64, 0), utc_at(second))
+            .is_err());
+    }
+    assert!(!clock.is_synced());
+
+    assert_eq!(clock.edge(local_at(4.0, 0), utc_at(4)), Ok(()));
+    let utc = clock.to_utc(local_at(4.5, 0)).unwrap();
+    assert!((utc - (utc_at(4) + 500_000)).abs() < 100);
+}
+
+#[test]
+fn crosses_midnight() {
+    let midnight = Utc::from_date_time(2026, 4, 18, 0).unwrap().as_micros();
+
+    let mut clock = Clock::new();
+    clock.edge(1_000_000, midnight - US_PER_SECOND).unwrap();
+    clock.edge(2_000_000, midnight).unwrap();
+
+    assert_eq!(clock.drift_ppm(), Some(0.0));
+    assert_eq!(clock.to_utc(1_500_000), Some(midnight - 500_000));
+    assert_eq!(clock.to_utc(2_250_000), Some(midnight + 250_000));
+}
@@ -0,0 +1,170 @@
+use stack_ripper::{
+    gps::{
+        ubx::{
+            cfg_nav5_airborne, cfg_prt_uart, cfg_rate, encode, update, Error, Message, Parser,
+            CLASS_CFG, CLASS_NAV, ID_CFG_RATE, ID_NAV_PVT,
+        },
+        Fix,
+    },
+    state::State,
+    timesync::Utc,
+};
+
+// Wellington, climbing at 10m/s, 3D fix from 11 satellites
+fn nav_pvt(fix_type: u8, flags: u8) -> [u8; 92] {
+    let mut payload = [0u8; 92];
+    payload[4..6].copy_from_slice(&2026u16.to_le_bytes());
+    payload[6] = 4; // month
+    payload[7] = 18; // day
+    payload[8] = 9; // hour
+    payload[9] = 27; // min
+    payload[10] = 50; // sec
+    payload[11] = 0x03; // validDate | validTime
+    payload[16..20].copy_from_slice(&(-200_000_000i32).to_le_bytes()); // nano
+    payload[20] = fix_type;
+    payload[21] = flags;
+    payload[23] = 11;
+    payload[24..28].copy_from_slice(&1_747_762_000i32.to_le_bytes());
+    payload[28..32].copy_from_slice(&(-412_865_000i32).to_le_bytes());
+    payload[36..40].copy_from_slice(&120_300i32.to_le_bytes());
+    payload[56..60].copy_from_slice(&(-10_000i32).to_le_bytes());
+    payload[60..64].copy_from_slice(&12_500i32.to_le_bytes());
+    payload[64..68].copy_from_slice(&27_150_000i32.to_le_bytes());
+    payload[76..78].copy_from_slice(&150u16.to_le_bytes());
+    payload
+}
+
+fn decode(bytes: &[u8]) -> Vec<Result<Message, Error>> {
+    let mut parser = Parser::new();
+    bytes
+        .iter()
+        .filter_map(|byte| parser.feed(*byte).map(|r| r.map(|f| Message::decode(&f))))
+        .collect()
+}
+
+#[test]
+fn encodes_a_known_frame() {
+    let mut buf = [0u8; 32];
+
+    // 5Hz, straight from the u-blox documentation
+    assert_eq!(
+        encode(CLASS_CFG, ID_CFG_RATE, &cfg_rate(200), &mut buf),
+        Ok(
+            &[0xB5, 0x62, 0x06, 0x08, 0x06, 0x00, 0xC8, 0x00, 0x01, 0x00, 0x01, 0x00, 0xDE, 0x6A][..]
+        )
+    );
+
+    assert_eq!(
+        encode(CLASS_CFG, 0x00, &cfg_prt_uart(115_200), &mut buf[..27]),
+        Err(Error::BufferTooSmall)
+    );
+}
+
+#[test]
+fn finds_frames_between_nmea_sentences() {
+    let mut buf = [0u8; 100];
+    let mut stream = b"$GNGGA,092750.00,,,,,0,00,99.99,,,,,,*7A\r\n".to_vec();
+    stream.extend_from_slice(encode(CLASS_NAV, ID_NAV_PVT, &nav_pvt(3, 0x01), &mut buf).unwrap());
+    stream.extend_from_slice(b"$GNVTG,,,,,,,,,N*2E\r\n");
+    stream.extend_from_slice(&[0xB5, 0x62, 0x0A, 0x04, 0x00, 0x00, 0x0E, 0x34]);
+
+    let messages = decode(&stream);
+
+    assert_eq!(messages.len(), 2);
+    let Ok(Message::NavPvt(pvt)) = messages[0] else {
+        panic!("expected a NAV-PVT");
+    };
+    // Nanoseconds can take it back into the previous second
+    assert_eq!(pvt.time, "2026-04-18T09:27:49.800Z".parse().ok());
+    assert_eq!(pvt.satellites, 11);
+    assert_eq!(pvt.fix(), Fix::Gps);
+    assert!((pvt.latitude - -41.2865).abs() < 1e-4);
+    assert!((pvt.longitude - 174.7762).abs() < 1e-4);
+    assert_eq!(pvt.altitude, 120.3);
+    assert_eq!(pvt.vertical_speed, 10.0);
+    assert_eq!(pvt.ground_speed, 12.5);
+    assert_eq!(pvt.heading, 271.5);
+    assert_eq!(
+        messages[1],
+        Ok(Message::Other {
+            class: 0x0A,
+            id: 0x04
+        })
+    );
+}
+
+#[test]
+fn recovers_after_a_damaged_frame() {
+    let mut buf = [0u8; 32];
+    let good = encode(CLASS_CFG, ID_CFG_RATE, &cfg_rate(100), &mut buf)
+        .unwrap()
+        .to_vec();
+    let mut bad = good.clone();
+    bad[7] ^= 0x01;
+
+    let mut too_long = vec![0xB5, 0x62, 0x01, 0x07, 0xFF, 0x00];
+    too_long.extend([bad, good].concat());
+
+    assert_eq!(
+        decode(&too_long),
+        [
+            Err(Error::TooLong(0xFF)),
+            Err(Error::BadChecksum),
+            Ok(Message::Other {
+                class: CLASS_CFG,
+                id: ID_CFG_RATE
+            }),
+        ]
+    );
+}
+
+#[test]
+fn airborne_model_only_touches_the_dynamic_model() {
+    let payload = cfg_nav5_airborne();
+
+    assert_eq!(&payload[..3], &[0x01, 0x00, 8]);
+    assert!(payload[3..].iter().all(|b| *b == 0));
+}
+
+#[test]
+fn takes_the_fix_type_from_nav_pvt() {
+    let cases = [
+        (0, 0x00, Fix::None),
+        (3, 0x00, Fix::None), // gnssFixOK not set
+        (1, 0x01, Fix::Estimated),
+        (2, 0x01, Fix::Gps2d),
+        (3, 0x03, Fix::Dgps),
+        (3, 0x81, Fix::Rtk),
+        (5, 0x01, Fix::None),
+    ];
+
+    let mut buf = [0u8; 100];
+    for (fix_type, flags, fix) in cases {
+        let frame = encode(CLASS_NAV, ID_NAV_PVT, &nav_pvt(fix_type, flags), &mut buf).unwrap();
+        let Ok(Message::NavPvt(pvt)) = decode(frame)[0] else {
+            panic!("expected a NAV-PVT");
+        };
+        assert_eq!(pvt.fix(), fix, "{} {:#x}", fix_type, flags);
+    }
+}
+
+#[test]
+fn losing_the_fix_keeps_the_last_position() {
+    let mut buf = [0u8; 100];
+    let mut state = State::default();
+
+    for (fix_type, flags) in [(3, 0x01), (0, 0x00)] {
+        let frame = encode(CLASS_NAV, ID_NAV_PVT, &nav_pvt(fix_type, flags), &mut buf).unwrap();
+        let Ok(message) = decode(frame)[0] else {
+            panic!("expected a NAV-PVT");
+        };
+        update(&mut state, &message);
+    }
+    update(&mut state, &Message::NavDop { horizontal: 0.87 });
+
+    assert_eq!(state.fx, Fix::None);
+    assert_eq!(state.t, Utc::from_date_time(2026, 4, 18, 34_069_800));
+    assert_eq!(state.ga, Some(120.3));
+    assert_eq!(state.gs, None);
+    assert_eq!(state.hd, Some(0.87));
+}
@@ -0,0 +1,283 @@
+mod common;
+
+use common::Rng;
+use stack_ripper::{
+    auth::Key,
+    lora::Profile,
+    protocol::Error,
+    uplink::{
+        decode, encode, parse_request, window_ms, Command, Inbox, Outbox, Reject, Uplink, MAGIC,
+        MAX_ATTEMPTS, MAX_FRAME_LEN,
+    },
+};
+
+const EVERY_COMMAND: [Command; 7] = [
+    Command::Ping,
+    Command::Arm,
+    Command::Disarm,
+    Command::StartLogging,
+    Command::Profile(None),
+    Command::Profile(Some(Profile::LongRange)),
+    Command::Profile(Some(Profile::Beacon)),
+];
+
+fn key() -> Key {
+    Key::new(core::array::from_fn(|i| i as u8))
+}
+
+fn uplink(counter: u32, command: Command) -> Uplink {
+    Uplink {
+        vehicle: 1,
+        counter,
+        command,
+    }
+}
+
+#[test]
+fn round_trip() {
+    for command in EVERY_COMMAND {
+        let mut buf = [0u8; MAX_FRAME_LEN];
+        let frame = encode(&uplink(200, command), &key(), &mut buf).unwrap();
+
+        assert_eq!(frame[0], MAGIC);
+        assert_eq!(decode(frame, &key()), Ok(uplink(200, command)));
+    }
+}
+
+#[test]
+fn frame_matches_known_vector() {
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let frame = encode(&uplink(1, Command::Arm), &key(), &mut buf).unwrap();
+
+    // Header, postcard `Arm`, then the first 8 bytes of HMAC-SHA256 over those
+    assert_eq!(
+        frame,
+        [
+            0xA8, 0x02, 0x01, 0x01, 0x00, 0x00, 0x00, 0x01, 0x01, 0x8A, 0x76, 0x36, 0xA5, 0x04,
+            0xC9, 0x74, 0x7B,
+        ]
+    );
+}
+
+#[test]
+fn rejects_damaged_and_foreign_frames() {
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let len = encode(&uplink(3, Command::Arm), &key(), &mut buf)
+        .unwrap()
+        .len();
+
+    // Telemetry from another vehicle on the same channel
+    let mut telemetry = buf;
+    telemetry[0] = stack_ripper::protocol::MAGIC;
+    assert_eq!(
+        decode(&telemetry[..len], &key()),
+        Err(Error::BadMagic(stack_ripper::protocol::MAGIC))
+    );
+
+    let mut flipped = buf;
+    flipped[3] ^= 0x01;
+    assert_eq!(decode(&flipped[..len], &key()), Err(Error::BadTag));
+
+    assert_eq!(decode(&buf[..len - 1], &key()), Err(Error::LengthMismatch));
+    assert_eq!(decode(&buf[..3], &key()), Err(Error::Truncated));
+}
+
+#[test]
+fn rejects_forged_frames() {
+    let mut buf = [0u8; MAX_FRAME_LEN];
+    let len = encode(&uplink(1, Command::Arm), &key(), &mut buf)
+        .unwrap()
+        .len();
+
+    // Signed with the wrong key
+    assert_eq!(
+        decode(&buf[..len], &Key::new([0x55; 32])),
+        Err(Error::BadTag)
+    );
+
+    // A real frame with the command swapped for Disarm, the counter bumped, or the tag guessed
+    for (i, change) in [(8, 0x02), (3, 0x02), (9, 0x00)] {
+        let mut forged = buf;
+        forged[i] = change;
+        assert_eq!(decode(&forged[..len], &key()), Err(Error::BadTag));
+    }
+}
+
+#[test]
+fn parses_requests_typed_at_the_ground_station() {
+    assert_eq!(parse_request("1 Ping\r"), Some((1, Command::Ping)));
+    assert_eq!(parse_request(" 2 Disarm "), Some((2, Command::Disarm)));
+    assert_eq!(
+        parse_request("1 Profile Beacon"),
+        Some((1, Command::Profile(Some(Profile::Beacon))))
+    );
+    assert_eq!(
+        parse_request("1 Profile"),
+        Some((1, Command::Profile(None)))
+    );
+
+    for bad in [
+        "Ping",
+        "1",
+        "1 Launch",
+        "1 Profile Fast",
+        "300 Ping",
+        "1 Arm now",
+    ] {
+        assert_eq!(parse_request(bad), None, "{}", bad);
+    }
+}
+
+#[test]
+fn vehicle_only_acts_once_on_its_own_commands() {
+    let mut inbox = Inbox::new(1, 0);
+    assert_eq!(inbox.ack(), None);
+
+    let other = Uplink {
+        vehicle: 2,
+        ..uplink(1, Command::Arm)
+    };
+    assert_eq!(inbox.accept(&other), Err(Reject::NotOurs));
+    assert_eq!(inbox.ack(), None);
+
+    assert_eq!(inbox.accept(&uplink(1, Command::Arm)), Ok(Command::Arm));
+    assert_eq!(inbox.ack(), Some(1));
+
+    // The ground station didn't hear the ack and sent it again
+    assert_eq!(inbox.accept(&uplink(1, Command::Arm)), Err(Reject::Resent));
+    assert_eq!(inbox.ack(), Some(1));
+
+    assert_eq!(
+        inbox.accept(&uplink(2, Command::Disarm)),
+        Ok(Command::Disarm)
+    );
+    assert_eq!(inbox.ack(), Some(2));
+    assert_eq!(inbox.counter(), 2);
+}
+
+#[test]
+fn vehicle_ignores_replayed_commands() {
+    let mut inbox = Inbox::new(1, 0);
+    let arm = uplink(1, Command::Arm);
+    let disarm = uplink(2, Command::Disarm);
+    assert_eq!(inbox.accept(&arm), Ok(Command::Arm));
+    assert_eq!(inbox.accept(&disarm), Ok(Command::Disarm));
+
+    // Someone recorded the arm command and plays it back
+    assert_eq!(inbox.accept(&arm), Err(Reject::Replayed));
+    assert_eq!(inbox.ack(), Some(2));
+
+    // Or waits for a reboot first, but the counter was saved
+    let mut rebooted = Inbox::new(1, inbox.counter());
+    assert_eq!(rebooted.accept(&arm), Err(Reject::Replayed));
+    assert_eq!(rebooted.accept(&disarm), Err(Reject::Resent));
+
+    // Still acknowledging the last one, or the ground station would keep sending it
+    assert_eq!(rebooted.ack(), Some(disarm.counter));
+    assert_eq!(
+        rebooted.accept(&uplink(3, Command::Ping)),
+        Ok(Command::Ping)
+    );
+}
+
+#[test]
+fn ground_station_resends_until_acknowledged() {
+    let mut outbox = Outbox::new();
+    let sent = outbox.queue(1, Command::StartLogging).unwrap();
+
+    // One at a time
+    assert_eq!(outbox.queue(1, Command::Ping), None);
+
+    // Only when that vehicle is listening
+    assert_eq!(outbox.due(2), None);
+    assert_eq!(outbox.due(1), Some(sent));
+
+    // Some other command's ack, or none at all, doesn't count
+    assert_eq!(outbox.acked(1, None), None);
+    assert_eq!(outbox.acked(1, Some(sent.counter - 1)), None);
+    assert_eq!(outbox.acked(2, Some(sent.counter)), None);
+    assert_eq!(outbox.due(1), Some(sent));
+
+    assert_eq!(outbox.acked(1, Some(sent.counter)), Some(sent));
+    assert_eq!(outbox.pending(), None);
+    assert_eq!(outbox.due(1), None);
+
+    let next = outbox.queue(1, Command::Ping).unwrap();
+    assert!(next.counter > sent.counter);
+}
+
+#[test]
+fn ack_from_before_a_ground_station_reboot_does_not_count() {
+    let mut inbox = Inbox::new(1, 0);
+    let mut outbox = Outbox::new();
+    let sent = outbox.queue(1, Command::Arm).unwrap();
+    assert_eq!(inbox.accept(&outbox.due(1).unwrap()), Ok(Command::Arm));
+    assert_eq!(outbox.acked(1, inbox.ack()), Some(sent));
+
+    // Rebooted, carrying on from the counter it saved when it sent the last one
+    let mut outbox = Outbox::new();
+    outbox.resume(sent.counter);
+    let next = outbox.queue(1, Command::Disarm).unwrap();
+
+    // The vehicle's still acking the last one, which isn't this
+    assert_eq!(outbox.acked(1, inbox.ack()), None);
+    assert_eq!(outbox.due(1), Some(next));
+    assert_eq!(inbox.accept(&next), Ok(Command::Disarm));
+    assert_eq!(outbox.acked(1, inbox.ack()), Some(next));
+}
+
+#[test]
+fn ground_station_counter_carries_on_after_a_reboot() {
+    let mut outbox = Outbox::new();
+    outbox.resume(41);
+    assert_eq!(outbox.queue(1, Command::Ping).unwrap().counter, 42);
+
+    // Never goes back
+    outbox.acked(1, Some(42));
+    outbox.resume(3);
+    assert_eq!(outbox.queue(1, Command::Ping).unwrap().counter, 43);
+}
+
+#[test]
+fn ground_station_gives_up_eventually() {
+    let mut outbox = Outbox::new();
+    let sent = outbox.queue(1, Command::Ping).unwrap();
+
+    for _ in 0..MAX_ATTEMPTS {
+        assert_eq!(outbox.due(1), Some(sent));
+    }
+    assert_eq!(outbox.due(1), None);
+    assert_eq!(outbox.pending(), None);
+    assert!(outbox.queue(1, Command::Arm).is_some());
+}
+
+#[test]
+fn window_fits_between_packets() {
+    for profile in Profile::ALL {
+        let modulation = profile.modulation();
+        let window = window_ms(&modulation);
+
+        // Enough time to hear the biggest command, with room to spare
+        assert!(window > modulation.airtime_us(MAX_FRAME_LEN) / 1_000);
+
+        // And the packet plus the window still leaves the channel quiet for a while
+        let busy = modulation.airtime_us(90) / 1_000 + window;
+        assert!(busy < profile.interval_ms(), "{:?}", profile);
+    }
+}
+
+#[test]
+fn fuzz_random_frames() {
+    let mut rng = Rng::new(0xA8);
+    let mut buf = [0u8; MAX_FRAME_LEN];
+
+    for _ in 0..10_000 {
+        let len = rng.below(MAX_FRAME_LEN + 1);
+        rng.fill(&mut buf[..len]);
+        if rng.below(2) == 0 {
+            buf[..len.min(1)].fill(MAGIC);
+        }
+
+        assert!(decode(&buf[..len], &key()).is_err(), "{:02x?}", &buf[..len]);
+    }
+}